@@ -232,6 +232,27 @@ impl Cipher {
         new
     }
 
+    /// Decrypt in CBC mode, checking the PKCS7 padding.
+    ///
+    /// Returns `None` if the input is empty, its length is not a multiple of the block size, or
+    /// the padding is invalid, which usually means the key is wrong or the data is corrupted.
+    pub fn cbc_decrypt_checked(&self, iv: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        let length = data.len();
+        if length == 0 || (length % AES_BLOCK_SIZE) != 0 {
+            return None;
+        }
+        let mut new = data.to_vec();
+        let mut my_iv = iv;
+
+        for i in (0..length).step_by(AES_BLOCK_SIZE) {
+            let block = &mut new[i..i + AES_BLOCK_SIZE];
+            aes_decrypt(block, &self.decrypt_key);
+            xor_with_iv(block, my_iv);
+            my_iv = &data[i..i + AES_BLOCK_SIZE];
+        }
+        unpad_checked(&mut new).then_some(new)
+    }
+
     pub fn ebc_encrypt(&self, data: &[u8]) -> Vec<u8> {
         let mut padded = pad(data);
 
@@ -257,6 +278,24 @@ impl Cipher {
         new
     }
 
+    /// Decrypt in ECB mode, checking the PKCS7 padding.
+    ///
+    /// Returns `None` if the input is empty, its length is not a multiple of the block size, or
+    /// the padding is invalid, which usually means the key is wrong or the data is corrupted.
+    pub fn ebc_decrypt_checked(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let length = data.len();
+        if length == 0 || (length % AES_BLOCK_SIZE) != 0 {
+            return None;
+        }
+        let mut new = data.to_vec();
+
+        for block in new.chunks_exact_mut(AES_BLOCK_SIZE) {
+            aes_decrypt(block, &self.decrypt_key);
+        }
+
+        unpad_checked(&mut new).then_some(new)
+    }
+
     /// Encrypt in CFB128 mode (i.e. CFB mode with 128-bit segment size).
     ///
     /// The input `data` is not modified. The output is a new Vec. No padding.
@@ -361,11 +400,32 @@ fn pad(input: &[u8]) -> Vec<u8> {
 // otherwise truncated to empty.
 fn unpad(padded: &mut Vec<u8>) {
     let sz = padded.len();
+    if sz == 0 {
+        return;
+    }
     let added = padded[sz - 1] as usize;
     let unpad_sz = if sz > added { sz - added } else { 0 };
     padded.truncate(unpad_sz);
 }
 
+// PKCS7 un-padding in-place with validation: returns false and leaves the input untouched if
+// the padding length is out of range or the padding bytes don't all match it.
+fn unpad_checked(padded: &mut Vec<u8>) -> bool {
+    let sz = padded.len();
+    let Some(&added) = padded.last() else {
+        return false;
+    };
+    let added = added as usize;
+    if added == 0 || added > AES_BLOCK_SIZE || added > sz {
+        return false;
+    }
+    if padded[sz - added..].iter().any(|&x| x as usize != added) {
+        return false;
+    }
+    padded.truncate(sz - added);
+    true
+}
+
 // bit-wise XOR `buf` slice with `iv` slice
 // `buf` length must be less or equal to `iv` length
 fn xor_with_iv(buf: &mut [u8], iv: &[u8]) {
@@ -0,0 +1,143 @@
+use std::f64::consts::PI;
+
+/// 归一化后（a0 = 1）的双二阶滤波器系数
+///
+/// 各种滤波器的设计公式来自 RBJ 的 Audio EQ Cookbook。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl Default for BiquadCoefficients {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl BiquadCoefficients {
+    pub const IDENTITY: Self = Self {
+        b0: 1.,
+        b1: 0.,
+        b2: 0.,
+        a1: 0.,
+        a2: 0.,
+    };
+
+    fn normalize(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// 返回 (cos(w0), alpha)，频率会被限制在奈奎斯特频率以内
+    fn prepare(sample_rate: f64, frequency: f64, q: f64) -> (f64, f64) {
+        let frequency = frequency.clamp(1., sample_rate * 0.49);
+        let w0 = 2. * PI * frequency / sample_rate;
+        let q = q.max(0.01);
+        (w0.cos(), w0.sin() / (2. * q))
+    }
+
+    pub fn peaking(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.);
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalize(
+            1. + alpha * a,
+            -2. * cos,
+            1. - alpha * a,
+            1. + alpha / a,
+            -2. * cos,
+            1. - alpha / a,
+        )
+    }
+
+    pub fn low_shelf(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.);
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        let k = 2. * a.sqrt() * alpha;
+        Self::normalize(
+            a * ((a + 1.) - (a - 1.) * cos + k),
+            2. * a * ((a - 1.) - (a + 1.) * cos),
+            a * ((a + 1.) - (a - 1.) * cos - k),
+            (a + 1.) + (a - 1.) * cos + k,
+            -2. * ((a - 1.) + (a + 1.) * cos),
+            (a + 1.) + (a - 1.) * cos - k,
+        )
+    }
+
+    pub fn high_shelf(sample_rate: f64, frequency: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.);
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        let k = 2. * a.sqrt() * alpha;
+        Self::normalize(
+            a * ((a + 1.) + (a - 1.) * cos + k),
+            -2. * a * ((a - 1.) + (a + 1.) * cos),
+            a * ((a + 1.) + (a - 1.) * cos - k),
+            (a + 1.) - (a - 1.) * cos + k,
+            2. * ((a - 1.) - (a + 1.) * cos),
+            (a + 1.) - (a - 1.) * cos - k,
+        )
+    }
+
+    pub fn low_pass(sample_rate: f64, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalize(
+            (1. - cos) / 2.,
+            1. - cos,
+            (1. - cos) / 2.,
+            1. + alpha,
+            -2. * cos,
+            1. - alpha,
+        )
+    }
+
+    pub fn high_pass(sample_rate: f64, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = Self::prepare(sample_rate, frequency, q);
+        Self::normalize(
+            (1. + cos) / 2.,
+            -(1. + cos),
+            (1. + cos) / 2.,
+            1. + alpha,
+            -2. * cos,
+            1. - alpha,
+        )
+    }
+}
+
+/// 转置直接 II 型的双二阶滤波器
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Biquad {
+    coeffs: BiquadCoefficients,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn new(coeffs: BiquadCoefficients) -> Self {
+        Self {
+            coeffs,
+            z1: 0.,
+            z2: 0.,
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let c = &self.coeffs;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.;
+        self.z2 = 0.;
+    }
+}
@@ -0,0 +1,104 @@
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::sample::Sample;
+
+/// 解码后统一转换为 f32 的平面（非交错）音频数据
+///
+/// 解码器输出的各种采样格式都会先转换到这里，然后依次经过音效处理和重采样再写入输出设备。
+pub struct PlanarBuffer {
+    spec: SignalSpec,
+    capacity: usize,
+    planes: Vec<Vec<f32>>,
+}
+
+impl Default for PlanarBuffer {
+    fn default() -> Self {
+        Self {
+            spec: SignalSpec {
+                rate: 0,
+                channels: Channels::empty(),
+            },
+            capacity: 0,
+            planes: Vec::new(),
+        }
+    }
+}
+
+impl PlanarBuffer {
+    /// 将解码出来的音频数据复制并转换到缓冲区内，之前的数据会被覆盖
+    pub fn load(&mut self, input: &AudioBufferRef<'_>) {
+        self.spec = *input.spec();
+        self.capacity = input.capacity();
+        self.planes
+            .resize_with(self.spec.channels.count(), Vec::new);
+        for plane in self.planes.iter_mut() {
+            plane.clear();
+        }
+        convert_samples_any(input, &mut self.planes);
+    }
+
+    pub fn spec(&self) -> &SignalSpec {
+        &self.spec
+    }
+
+    /// 解码器单个数据包最多能输出的帧数，重采样器以此作为分块大小
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn frames(&self) -> usize {
+        self.planes.first().map(|x| x.len()).unwrap_or_default()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn planes(&self) -> &[Vec<f32>] {
+        &self.planes
+    }
+
+    pub fn planes_mut(&mut self) -> &mut [Vec<f32>] {
+        &mut self.planes
+    }
+
+    /// 只保留前 `frames` 帧
+    pub fn truncate(&mut self, frames: usize) {
+        for plane in self.planes.iter_mut() {
+            plane.truncate(frames);
+        }
+    }
+
+    /// 丢弃开头的 `frames` 帧
+    pub fn discard_front(&mut self, frames: usize) {
+        for plane in self.planes.iter_mut() {
+            let frames = frames.min(plane.len());
+            plane.drain(..frames);
+        }
+    }
+}
+
+fn convert_samples_any(input: &AudioBufferRef<'_>, output: &mut [Vec<f32>]) {
+    match input {
+        AudioBufferRef::U8(input) => convert_samples(input, output),
+        AudioBufferRef::U16(input) => convert_samples(input, output),
+        AudioBufferRef::U24(input) => convert_samples(input, output),
+        AudioBufferRef::U32(input) => convert_samples(input, output),
+        AudioBufferRef::S8(input) => convert_samples(input, output),
+        AudioBufferRef::S16(input) => convert_samples(input, output),
+        AudioBufferRef::S24(input) => convert_samples(input, output),
+        AudioBufferRef::S32(input) => convert_samples(input, output),
+        AudioBufferRef::F32(input) => convert_samples(input, output),
+        AudioBufferRef::F64(input) => convert_samples(input, output),
+    }
+}
+
+fn convert_samples<S>(input: &AudioBuffer<S>, output: &mut [Vec<f32>])
+where
+    S: Sample + IntoSample<f32>,
+{
+    for (c, dst) in output.iter_mut().enumerate() {
+        let src = input.chan(c);
+        dst.extend(src.iter().map(|&s| s.into_sample()));
+    }
+}
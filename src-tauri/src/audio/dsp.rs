@@ -0,0 +1,362 @@
+use super::{buffer::PlanarBuffer, equalizer::EqProfile, volume::db_to_gain};
+
+/// 增益变化的平滑时间
+const GAIN_SMOOTHING_SECS: f32 = 0.05;
+/// 限幅器的阈值（约 -0.3 dBFS）与释放时间
+const LIMITER_THRESHOLD: f32 = 0.966;
+const LIMITER_RELEASE_SECS: f32 = 0.08;
+
+/// 音效处理链中的一个处理环节
+///
+/// 所有环节都在重采样前以解码器的原始采样率处理 f32 平面音频数据。
+pub trait DspStage: Send {
+    fn kind(&self) -> DspStageKind;
+    /// 根据最新的音效设置更新自身参数，不需要的参数可以忽略
+    fn update(&mut self, settings: &DspSettings);
+    fn process(&mut self, buf: &mut PlanarBuffer);
+    /// 清空内部状态，用于切歌或跳转后
+    fn reset(&mut self);
+    /// 该环节引入的延迟，单位为帧
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum DspStageKind {
+    Normalization,
+    Equalizer,
+    Limiter,
+    Balance,
+    MonoMix,
+}
+
+impl DspStageKind {
+    pub fn create(self) -> Box<dyn DspStage> {
+        match self {
+            Self::Normalization => Box::<NormalizationStage>::default(),
+            Self::Equalizer => Box::<super::equalizer::Equalizer>::default(),
+            Self::Limiter => Box::<LimiterStage>::default(),
+            Self::Balance => Box::<BalanceStage>::default(),
+            Self::MonoMix => Box::new(MonoMixStage),
+        }
+    }
+}
+
+/// 各个处理环节共享的参数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DspSettings {
+    pub equalizer: EqProfile,
+    /// 响度标准化增益，单位为 dB
+    pub normalization_gain: f64,
+    /// 左右声道平衡，-1.0 为完全偏左，1.0 为完全偏右
+    pub balance: f64,
+}
+
+/// 按顺序执行的音效处理链
+pub struct DspChain {
+    stages: Vec<Box<dyn DspStage>>,
+}
+
+impl Default for DspChain {
+    fn default() -> Self {
+        Self::new(&[
+            DspStageKind::Normalization,
+            DspStageKind::Equalizer,
+            DspStageKind::Limiter,
+        ])
+    }
+}
+
+impl DspChain {
+    pub fn new(kinds: &[DspStageKind]) -> Self {
+        Self {
+            stages: kinds.iter().map(|x| x.create()).collect(),
+        }
+    }
+
+    /// 重新排列处理链，已存在的环节会保留其内部状态
+    pub fn set_stages(&mut self, kinds: &[DspStageKind], settings: &DspSettings) {
+        let mut old_stages = std::mem::take(&mut self.stages);
+        for kind in kinds {
+            let stage = match old_stages.iter().position(|x| x.kind() == *kind) {
+                Some(i) => old_stages.remove(i),
+                None => {
+                    let mut stage = kind.create();
+                    stage.update(settings);
+                    stage
+                }
+            };
+            self.stages.push(stage);
+        }
+    }
+
+    pub fn kinds(&self) -> Vec<DspStageKind> {
+        self.stages.iter().map(|x| x.kind()).collect()
+    }
+
+    pub fn update(&mut self, settings: &DspSettings) {
+        for stage in self.stages.iter_mut() {
+            stage.update(settings);
+        }
+    }
+
+    pub fn process(&mut self, buf: &mut PlanarBuffer) {
+        for stage in self.stages.iter_mut() {
+            stage.process(buf);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+
+    pub fn latency(&self) -> usize {
+        self.stages.iter().map(|x| x.latency()).sum()
+    }
+}
+
+fn smoothing_coefficient(sample_rate: u32, secs: f32) -> f32 {
+    1. - (-1. / (secs * sample_rate.max(1) as f32)).exp()
+}
+
+/// 应用响度标准化增益，增益变化时会平滑过渡
+pub struct NormalizationStage {
+    target: f32,
+    current: f32,
+}
+
+impl Default for NormalizationStage {
+    fn default() -> Self {
+        Self {
+            target: 1.,
+            current: 1.,
+        }
+    }
+}
+
+impl DspStage for NormalizationStage {
+    fn kind(&self) -> DspStageKind {
+        DspStageKind::Normalization
+    }
+
+    fn update(&mut self, settings: &DspSettings) {
+        self.target = db_to_gain(settings.normalization_gain);
+    }
+
+    fn process(&mut self, buf: &mut PlanarBuffer) {
+        if self.current == self.target && self.target == 1. {
+            return;
+        }
+        let coeff = smoothing_coefficient(buf.spec().rate, GAIN_SMOOTHING_SECS);
+        let frames = buf.frames();
+        let planes = buf.planes_mut();
+        for i in 0..frames {
+            self.current += (self.target - self.current) * coeff;
+            for plane in planes.iter_mut() {
+                plane[i] *= self.current;
+            }
+        }
+        if (self.current - self.target).abs() < 1e-5 {
+            self.current = self.target;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = self.target;
+    }
+}
+
+/// 峰值限幅器，瞬时压低超过阈值的峰值并缓慢释放，防止均衡器等提升后的信号削波
+///
+/// 只有响度标准化或均衡器会提升信号时才介入，否则原样输出，不改变默认播放的音质。
+pub struct LimiterStage {
+    gain: f32,
+    active: bool,
+}
+
+impl Default for LimiterStage {
+    fn default() -> Self {
+        Self {
+            gain: 1.,
+            active: false,
+        }
+    }
+}
+
+impl DspStage for LimiterStage {
+    fn kind(&self) -> DspStageKind {
+        DspStageKind::Limiter
+    }
+
+    fn update(&mut self, settings: &DspSettings) {
+        self.active = settings.normalization_gain + settings.equalizer.max_boost_db() > 0.;
+    }
+
+    fn process(&mut self, buf: &mut PlanarBuffer) {
+        // 停用后先把正在压低的增益释放完，避免音量突变
+        if !self.active && self.gain == 1. {
+            return;
+        }
+        let release = smoothing_coefficient(buf.spec().rate, LIMITER_RELEASE_SECS);
+        let frames = buf.frames();
+        let planes = buf.planes_mut();
+        for i in 0..frames {
+            let peak = planes.iter().map(|x| x[i].abs()).fold(0f32, f32::max);
+            let required = if self.active && peak * self.gain > LIMITER_THRESHOLD {
+                LIMITER_THRESHOLD / peak
+            } else {
+                1.
+            };
+            if required < self.gain {
+                self.gain = required;
+            } else {
+                self.gain += (required - self.gain) * release;
+            }
+            for plane in planes.iter_mut() {
+                plane[i] *= self.gain;
+            }
+        }
+        if self.gain > 0.9999 {
+            self.gain = 1.;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gain = 1.;
+    }
+}
+
+/// 左右声道平衡
+#[derive(Default)]
+pub struct BalanceStage {
+    balance: f32,
+}
+
+impl DspStage for BalanceStage {
+    fn kind(&self) -> DspStageKind {
+        DspStageKind::Balance
+    }
+
+    fn update(&mut self, settings: &DspSettings) {
+        self.balance = settings.balance.clamp(-1., 1.) as f32;
+    }
+
+    fn process(&mut self, buf: &mut PlanarBuffer) {
+        if self.balance == 0. || buf.channels() < 2 {
+            return;
+        }
+        let left = (1. - self.balance).min(1.);
+        let right = (1. + self.balance).min(1.);
+        let planes = buf.planes_mut();
+        planes[0].iter_mut().for_each(|x| *x *= left);
+        planes[1].iter_mut().for_each(|x| *x *= right);
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// 将所有声道混合为单声道后再输出到每个声道
+pub struct MonoMixStage;
+
+impl DspStage for MonoMixStage {
+    fn kind(&self) -> DspStageKind {
+        DspStageKind::MonoMix
+    }
+
+    fn update(&mut self, _settings: &DspSettings) {}
+
+    fn process(&mut self, buf: &mut PlanarBuffer) {
+        let channels = buf.channels();
+        if channels < 2 {
+            return;
+        }
+        let frames = buf.frames();
+        let planes = buf.planes_mut();
+        for i in 0..frames {
+            let mixed = planes.iter().map(|x| x[i]).sum::<f32>() / channels as f32;
+            for plane in planes.iter_mut() {
+                plane[i] = mixed;
+            }
+        }
+    }
+
+    fn reset(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal, SignalSpec};
+
+    use super::*;
+
+    /// 两个声道都是满幅 100Hz 正弦波的 48kHz 音频
+    fn full_scale_sine(frames: usize) -> PlanarBuffer {
+        let spec = SignalSpec::new(48000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut input = AudioBuffer::<f32>::new(frames as u64, spec);
+        input.render_reserved(Some(frames));
+        for ch in 0..2 {
+            for (i, x) in input.chan_mut(ch).iter_mut().enumerate() {
+                *x = (i as f32 * 100. / 48000. * std::f32::consts::TAU).sin();
+            }
+        }
+        let mut buf = PlanarBuffer::default();
+        buf.load(&input.as_audio_buffer_ref());
+        buf
+    }
+
+    fn peak(buf: &PlanarBuffer) -> f32 {
+        buf.planes()
+            .iter()
+            .flatten()
+            .fold(0f32, |a, x| a.max(x.abs()))
+    }
+
+    #[test]
+    fn default_chain_is_transparent_without_boost() {
+        let mut chain = DspChain::default();
+        chain.update(&DspSettings {
+            normalization_gain: -3.,
+            ..Default::default()
+        });
+        chain.update(&DspSettings::default());
+        let mut buf = full_scale_sine(48000);
+        let expected = buf.planes().to_vec();
+        chain.process(&mut buf);
+        assert!(
+            buf.planes() == expected.as_slice(),
+            "没有提升时限幅器不应该改变信号"
+        );
+    }
+
+    #[test]
+    fn limiter_engages_when_gain_is_boosted() {
+        let mut chain = DspChain::default();
+        chain.update(&DspSettings {
+            normalization_gain: 6.,
+            ..Default::default()
+        });
+        let mut buf = full_scale_sine(48000);
+        chain.process(&mut buf);
+        assert!(peak(&buf) <= LIMITER_THRESHOLD + 1e-3, "{}", peak(&buf));
+
+        // 补偿后没有净提升的均衡器不需要限幅
+        let equalizer = EqProfile::parse_equalizer_apo(
+            "Preamp: -6 dB\nFilter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70",
+        )
+        .unwrap();
+        assert!(equalizer.max_boost_db() <= 0.);
+        let mut limiter = LimiterStage::default();
+        limiter.update(&DspSettings {
+            equalizer,
+            ..Default::default()
+        });
+        let mut buf = full_scale_sine(4800);
+        let expected = buf.planes().to_vec();
+        limiter.process(&mut buf);
+        assert!(buf.planes() == expected.as_slice());
+    }
+}
@@ -0,0 +1,463 @@
+use anyhow::Context;
+
+use super::{
+    biquad::{Biquad, BiquadCoefficients},
+    buffer::PlanarBuffer,
+    dsp::{DspSettings, DspStage, DspStageKind},
+    volume::db_to_gain,
+};
+
+/// 修改均衡器参数时新旧滤波器交叉淡化的帧数，用于避免爆音
+const CROSSFADE_FRAMES: usize = 2048;
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum EqFilterType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+fn default_q() -> f64 {
+    std::f64::consts::FRAC_1_SQRT_2
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// 均衡器的单个频段
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct EqBand {
+    pub filter_type: EqFilterType,
+    /// 中心频率或截止频率，单位为 Hz
+    pub frequency: f64,
+    /// 增益，单位为 dB，对低通和高通滤波器无效
+    #[serde(default)]
+    pub gain: f64,
+    #[serde(default = "default_q")]
+    pub q: f64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+impl EqBand {
+    pub fn coefficients(&self, sample_rate: f64) -> BiquadCoefficients {
+        match self.filter_type {
+            EqFilterType::Peaking => {
+                BiquadCoefficients::peaking(sample_rate, self.frequency, self.q, self.gain)
+            }
+            EqFilterType::LowShelf => {
+                BiquadCoefficients::low_shelf(sample_rate, self.frequency, self.q, self.gain)
+            }
+            EqFilterType::HighShelf => {
+                BiquadCoefficients::high_shelf(sample_rate, self.frequency, self.q, self.gain)
+            }
+            EqFilterType::LowPass => {
+                BiquadCoefficients::low_pass(sample_rate, self.frequency, self.q)
+            }
+            EqFilterType::HighPass => {
+                BiquadCoefficients::high_pass(sample_rate, self.frequency, self.q)
+            }
+        }
+    }
+}
+
+/// 一套完整的均衡器设置
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EqProfile {
+    /// 均衡器前置增益，单位为 dB，通常为负数以给提升的频段留出余量
+    #[serde(default)]
+    pub preamp: f64,
+    #[serde(default)]
+    pub bands: Vec<EqBand>,
+}
+
+impl EqProfile {
+    pub fn is_flat(&self) -> bool {
+        self.preamp == 0. && !self.bands.iter().any(|x| x.enabled)
+    }
+
+    /// 均衡器最多可能把信号提升多少 dB，按所有启用频段的提升量之和加上前置增益估算
+    pub fn max_boost_db(&self) -> f64 {
+        let boost = self
+            .bands
+            .iter()
+            .filter(|x| x.enabled)
+            .filter(|x| {
+                matches!(
+                    x.filter_type,
+                    EqFilterType::Peaking | EqFilterType::LowShelf | EqFilterType::HighShelf
+                )
+            })
+            .map(|x| x.gain.max(0.))
+            .sum::<f64>();
+        self.preamp + boost
+    }
+
+    /// 内置的预设
+    pub fn preset(name: &str) -> Option<Self> {
+        let peaking = |frequency, gain| EqBand {
+            filter_type: EqFilterType::Peaking,
+            frequency,
+            gain,
+            q: 1.,
+            enabled: true,
+        };
+        let low_shelf = |frequency, gain| EqBand {
+            filter_type: EqFilterType::LowShelf,
+            frequency,
+            gain,
+            q: default_q(),
+            enabled: true,
+        };
+        let high_shelf = |frequency, gain| EqBand {
+            filter_type: EqFilterType::HighShelf,
+            frequency,
+            gain,
+            q: default_q(),
+            enabled: true,
+        };
+        let profile = match name {
+            "flat" => Self::default(),
+            "bassBoost" => Self {
+                preamp: -6.,
+                bands: vec![low_shelf(105., 6.)],
+            },
+            "trebleBoost" => Self {
+                preamp: -5.,
+                bands: vec![high_shelf(6000., 5.)],
+            },
+            "vocal" => Self {
+                preamp: -3.,
+                bands: vec![low_shelf(120., -2.), peaking(1000., 2.), peaking(3000., 3.)],
+            },
+            "loudness" => Self {
+                preamp: -6.,
+                bands: vec![low_shelf(90., 6.), high_shelf(10000., 4.)],
+            },
+            _ => return None,
+        };
+        Some(profile)
+    }
+
+    /// 解析 EqualizerAPO / AutoEQ 格式的参数文本
+    ///
+    /// ```text
+    /// Preamp: -6.2 dB
+    /// Filter 1: ON PK Fc 105 Hz Gain -3.1 dB Q 0.70
+    /// Filter 2: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.71
+    /// ```
+    pub fn parse_equalizer_apo(text: &str) -> anyhow::Result<Self> {
+        let mut result = Self::default();
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim();
+            if key.eq_ignore_ascii_case("preamp") {
+                let value = value.trim();
+                let value = value.strip_suffix("dB").unwrap_or(value).trim();
+                result.preamp = value
+                    .parse()
+                    .with_context(|| format!("第 {} 行的前置增益不合法", line_index + 1))?;
+            } else if key.starts_with("Filter") {
+                if let Some(band) = parse_apo_filter(value)
+                    .with_context(|| format!("第 {} 行的滤波器格式不合法", line_index + 1))?
+                {
+                    result.bands.push(band);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// 解析 `ON PK Fc 105 Hz Gain -3.1 dB Q 0.70` 部分，不支持的滤波器类型会被忽略
+fn parse_apo_filter(value: &str) -> anyhow::Result<Option<EqBand>> {
+    let tokens = value.split_whitespace().collect::<Vec<_>>();
+    anyhow::ensure!(tokens.len() >= 2, "缺少滤波器类型");
+    let enabled = match tokens[0] {
+        x if x.eq_ignore_ascii_case("on") => true,
+        x if x.eq_ignore_ascii_case("off") => false,
+        _ => anyhow::bail!("缺少 ON/OFF 标记"),
+    };
+    let filter_type = match tokens[1].to_ascii_uppercase().as_str() {
+        "PK" | "PEQ" => EqFilterType::Peaking,
+        "LS" | "LSC" | "LSQ" => EqFilterType::LowShelf,
+        "HS" | "HSC" | "HSQ" => EqFilterType::HighShelf,
+        "LP" | "LPQ" => EqFilterType::LowPass,
+        "HP" | "HPQ" => EqFilterType::HighPass,
+        _ => return Ok(None),
+    };
+    let mut band = EqBand {
+        filter_type,
+        frequency: 0.,
+        gain: 0.,
+        q: default_q(),
+        enabled,
+    };
+    for pair in tokens[2..].windows(2) {
+        let field = match pair[0] {
+            "Fc" => &mut band.frequency,
+            "Gain" => &mut band.gain,
+            "Q" => &mut band.q,
+            _ => continue,
+        };
+        *field = pair[1]
+            .parse()
+            .with_context(|| format!("参数 {} 的数值不合法", pair[0]))?;
+    }
+    anyhow::ensure!(band.frequency > 0., "缺少滤波器频率");
+    Ok(Some(band))
+}
+
+/// 参数均衡器，在重采样前对 f32 平面音频数据进行处理
+///
+/// 修改参数时会让新旧两套滤波器同时运行一小段时间并交叉淡化，以保证播放中调节不会产生爆音。
+pub struct Equalizer {
+    profile: EqProfile,
+    is_dirty: bool,
+    sample_rate: u32,
+    gain: f32,
+    filters: Vec<Vec<Biquad>>,
+    fading_gain: f32,
+    fading_filters: Vec<Vec<Biquad>>,
+    fade_pos: usize,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self {
+            profile: EqProfile::default(),
+            is_dirty: false,
+            sample_rate: 0,
+            gain: 1.,
+            filters: Vec::new(),
+            fading_gain: 1.,
+            fading_filters: Vec::new(),
+            fade_pos: CROSSFADE_FRAMES,
+        }
+    }
+}
+
+impl Equalizer {
+    fn build_filters(&self, channels: usize) -> Vec<Vec<Biquad>> {
+        let rate = self.sample_rate as f64;
+        let chain = self
+            .profile
+            .bands
+            .iter()
+            .filter(|x| x.enabled)
+            .map(|x| Biquad::new(x.coefficients(rate)))
+            .collect::<Vec<_>>();
+        vec![chain; channels]
+    }
+}
+
+impl DspStage for Equalizer {
+    fn kind(&self) -> DspStageKind {
+        DspStageKind::Equalizer
+    }
+
+    fn update(&mut self, settings: &DspSettings) {
+        if self.profile != settings.equalizer {
+            self.profile = settings.equalizer.to_owned();
+            self.is_dirty = true;
+        }
+    }
+
+    fn process(&mut self, buf: &mut PlanarBuffer) {
+        let channels = buf.channels();
+        if self.sample_rate != buf.spec().rate || self.filters.len() != channels {
+            // 格式变化时没有可以淡出的旧状态，直接重建
+            self.sample_rate = buf.spec().rate;
+            self.filters = self.build_filters(channels);
+            self.gain = db_to_gain(self.profile.preamp);
+            self.fading_filters.clear();
+            self.fade_pos = CROSSFADE_FRAMES;
+            self.is_dirty = false;
+        } else if self.is_dirty {
+            let new_filters = self.build_filters(channels);
+            self.fading_filters = std::mem::replace(&mut self.filters, new_filters);
+            self.fading_gain = self.gain;
+            self.gain = db_to_gain(self.profile.preamp);
+            self.fade_pos = 0;
+            self.is_dirty = false;
+        }
+
+        let is_fading = self.fade_pos < CROSSFADE_FRAMES;
+        if !is_fading && self.profile.is_flat() {
+            return;
+        }
+
+        let frames = buf.frames();
+        for (ch, plane) in buf.planes_mut().iter_mut().enumerate() {
+            let mut fade_pos = self.fade_pos;
+            for x in plane.iter_mut().take(frames) {
+                let input = *x as f64;
+                let mut y =
+                    self.filters[ch].iter_mut().fold(input, |s, f| f.process(s)) as f32 * self.gain;
+                if fade_pos < CROSSFADE_FRAMES {
+                    let old = self.fading_filters[ch]
+                        .iter_mut()
+                        .fold(input, |s, f| f.process(s)) as f32
+                        * self.fading_gain;
+                    let t = fade_pos as f32 / CROSSFADE_FRAMES as f32;
+                    y = old * (1. - t) + y * t;
+                    fade_pos += 1;
+                }
+                *x = y;
+            }
+        }
+        if is_fading {
+            self.fade_pos = (self.fade_pos + frames).min(CROSSFADE_FRAMES);
+            if self.fade_pos >= CROSSFADE_FRAMES {
+                self.fading_filters.clear();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.fade_pos = CROSSFADE_FRAMES;
+        self.fading_filters.clear();
+        self.filters.iter_mut().flatten().for_each(|x| x.reset());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// AutoEQ 导出的 ParametricEQ.txt
+    const AUTOEQ_SAMPLE: &str = "Preamp: -6.4 dB
+Filter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70
+Filter 2: ON PK Fc 58 Hz Gain -1.4 dB Q 0.49
+Filter 3: ON PK Fc 2548 Hz Gain 3.2 dB Q 2.09
+Filter 4: ON PK Fc 3931 Hz Gain -2.6 dB Q 4.13
+Filter 5: ON PK Fc 5622 Hz Gain -2.0 dB Q 2.75
+Filter 6: ON PK Fc 8102 Hz Gain 1.9 dB Q 2.66
+Filter 7: ON PK Fc 11194 Hz Gain -3.7 dB Q 0.99
+Filter 8: ON PK Fc 19722 Hz Gain -2.1 dB Q 0.56
+Filter 9: ON PK Fc 20000 Hz Gain -1.2 dB Q 0.74
+Filter 10: ON HSC Fc 10000 Hz Gain -2.3 dB Q 0.70
+";
+
+    fn band(filter_type: EqFilterType, frequency: f64, gain: f64, q: f64) -> EqBand {
+        EqBand {
+            filter_type,
+            frequency,
+            gain,
+            q,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn parse_autoeq_parametric_eq() {
+        let profile = EqProfile::parse_equalizer_apo(AUTOEQ_SAMPLE).unwrap();
+        assert_eq!(profile.preamp, -6.4);
+        assert_eq!(profile.bands.len(), 10);
+        assert_eq!(
+            profile.bands[0],
+            band(EqFilterType::LowShelf, 105., 5.5, 0.7)
+        );
+        assert_eq!(
+            profile.bands[2],
+            band(EqFilterType::Peaking, 2548., 3.2, 2.09)
+        );
+        assert_eq!(
+            profile.bands[9],
+            band(EqFilterType::HighShelf, 10000., -2.3, 0.7)
+        );
+        assert!(profile.bands[1..9]
+            .iter()
+            .all(|x| x.filter_type == EqFilterType::Peaking));
+    }
+
+    #[test]
+    fn parse_equalizer_apo_skips_comments_and_unsupported_filters() {
+        let profile = EqProfile::parse_equalizer_apo(
+            "# 注释\r
+Device: Headphones\r
+\r
+Preamp: -3 dB\r
+Filter 1: OFF PK Fc 1000 Hz Gain 2 dB Q 1\r
+Filter 2: ON NO Fc 50 Hz\r
+Filter 3: ON HP Fc 20 Hz\r
+",
+        )
+        .unwrap();
+        assert_eq!(profile.preamp, -3.);
+        assert_eq!(
+            profile.bands,
+            vec![
+                EqBand {
+                    enabled: false,
+                    ..band(EqFilterType::Peaking, 1000., 2., 1.)
+                },
+                band(EqFilterType::HighPass, 20., 0., default_q()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_equalizer_apo_reports_malformed_lines() {
+        for (text, expected) in [
+            ("Preamp: loud dB", "第 1 行的前置增益不合法"),
+            (
+                "Preamp: -1 dB\nFilter 1: PK Fc 100 Hz Gain 1 dB Q 1",
+                "第 2 行的滤波器格式不合法: 缺少 ON/OFF 标记",
+            ),
+            ("Filter 1: ON", "缺少滤波器类型"),
+            (
+                "Filter 1: ON PK Fc 1k Hz Gain 1 dB Q 1",
+                "参数 Fc 的数值不合法",
+            ),
+            ("Filter 1: ON PK Gain 1 dB Q 1", "缺少滤波器频率"),
+        ] {
+            let err = EqProfile::parse_equalizer_apo(text).unwrap_err();
+            let err = format!("{err:#}");
+            assert!(err.contains(expected), "{text:?} => {err}");
+        }
+    }
+
+    #[test]
+    fn peaking_coefficients_match_cookbook() {
+        let coeffs = band(EqFilterType::Peaking, 1000., 6., 1.).coefficients(48000.);
+        let expected = BiquadCoefficients {
+            b0: 1.043953086990335,
+            b1: -1.8953207239365961,
+            b2: 0.8677222847598566,
+            a1: -1.8953207239365961,
+            a2: 0.9116753717501915,
+        };
+        for (x, y) in [
+            (coeffs.b0, expected.b0),
+            (coeffs.b1, expected.b1),
+            (coeffs.b2, expected.b2),
+            (coeffs.a1, expected.a1),
+            (coeffs.a2, expected.a2),
+        ] {
+            assert!((x - y).abs() < 1e-12, "{coeffs:?}");
+        }
+
+        // 中心频率处的增益应该正好是设置的 6dB
+        let w = 2. * std::f64::consts::PI * 1000. / 48000.;
+        let response = |b0: f64, b1: f64, b2: f64| {
+            let re = b0 + b1 * w.cos() + b2 * (2. * w).cos();
+            let im = -b1 * w.sin() - b2 * (2. * w).sin();
+            re.hypot(im)
+        };
+        let gain = response(coeffs.b0, coeffs.b1, coeffs.b2) / response(1., coeffs.a1, coeffs.a2);
+        assert!((20. * gain.log10() - 6.).abs() < 1e-9, "{gain}");
+    }
+}
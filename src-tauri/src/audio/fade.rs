@@ -0,0 +1,137 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use symphonia::core::conv::IntoSample;
+
+use super::output::AudioOutputSample;
+
+/// 默认的淡入淡出时长
+pub const DEFAULT_FADE_DURATION: Duration = Duration::from_millis(30);
+/// 允许设置的最长淡入淡出时长，暂停和跳转都要等淡出完成后才会生效，所以不能太长
+pub const MAX_FADE_DURATION: Duration = Duration::from_secs(1);
+
+/// 在音频线程和输出回调之间共享的淡入淡出状态
+///
+/// 音频线程只负责设置目标和发出请求，实际的电平由输出回调逐帧计算并回报。
+#[derive(Debug)]
+pub struct SharedFade {
+    audible: AtomicBool,
+    duration_us: AtomicU32,
+    /// 输出回调当前的淡入淡出电平，以 f32 的位模式存储
+    level: AtomicU32,
+    /// 请求输出回调立即把电平置零
+    mute_request: AtomicBool,
+    /// 请求输出回调丢弃环形缓冲区里尚未播放的数据
+    clear_request: AtomicBool,
+}
+
+impl Default for SharedFade {
+    fn default() -> Self {
+        Self {
+            audible: AtomicBool::new(true),
+            duration_us: AtomicU32::new(DEFAULT_FADE_DURATION.as_micros() as _),
+            level: AtomicU32::new(0f32.to_bits()),
+            mute_request: AtomicBool::new(false),
+            clear_request: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SharedFade {
+    pub fn set_duration(&self, duration: Duration) {
+        let duration = duration.min(MAX_FADE_DURATION);
+        self.duration_us
+            .store(duration.as_micros() as _, Ordering::SeqCst);
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.duration_us.load(Ordering::SeqCst) as _)
+    }
+
+    pub fn fade_in(&self) {
+        self.audible.store(true, Ordering::SeqCst);
+    }
+
+    pub fn fade_out(&self) {
+        self.audible.store(false, Ordering::SeqCst);
+    }
+
+    /// 下次输出回调时从静音开始淡入，用于输出流停止后重新开始播放
+    pub fn mute_now(&self) {
+        self.mute_request.store(true, Ordering::SeqCst);
+    }
+
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::SeqCst))
+    }
+
+    pub fn request_clear(&self) {
+        self.clear_request.store(true, Ordering::SeqCst);
+    }
+
+    pub fn take_clear_request(&self) -> bool {
+        self.clear_request.swap(false, Ordering::SeqCst)
+    }
+
+    /// 清空请求是否还没有被输出回调处理
+    pub fn is_clear_requested(&self) -> bool {
+        self.clear_request.load(Ordering::SeqCst)
+    }
+}
+
+/// 在输出回调中使用的淡入淡出处理器，电平按线性斜坡逐帧靠近目标
+pub struct Fader {
+    shared: Arc<SharedFade>,
+    level: f32,
+    sample_rate: u32,
+    channels: usize,
+}
+
+impl Fader {
+    pub fn new(shared: Arc<SharedFade>, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            level: shared.level(),
+            shared,
+            sample_rate,
+            channels: channels.max(1),
+        }
+    }
+
+    pub fn process<T: AudioOutputSample>(&mut self, data: &mut [T]) {
+        if self.shared.mute_request.swap(false, Ordering::SeqCst) {
+            self.level = 0.;
+        }
+        let target = if self.shared.audible.load(Ordering::SeqCst) {
+            1.
+        } else {
+            0.
+        };
+        if self.level == target {
+            if target == 0. {
+                data.fill(T::MID);
+            }
+        } else {
+            let frames = self.shared.duration().as_secs_f32() * self.sample_rate as f32;
+            let step = if frames >= 1. { 1. / frames } else { 1. };
+            for frame in data.chunks_exact_mut(self.channels) {
+                self.level = if target > self.level {
+                    (self.level + step).min(target)
+                } else {
+                    (self.level - step).max(target)
+                };
+                for x in frame.iter_mut() {
+                    let s: f32 = (*x).into_sample();
+                    *x = (s * self.level).into_sample();
+                }
+            }
+        }
+        self.shared
+            .level
+            .store(self.level.to_bits(), Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,65 @@
+use super::buffer::PlanarBuffer;
+
+/// 循环点交叉淡化的最大时长
+const LOOP_CROSSFADE_SECS: f64 = 0.02;
+
+/// A-B 循环的区间，单位为秒
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct LoopRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// 在循环点跳回开头时，把循环终点之后的一小段音频淡出并与开头叠加，避免跳转处出现爆音
+#[derive(Default)]
+pub struct LoopCrossfade {
+    tail: Vec<Vec<f32>>,
+    pos: usize,
+}
+
+impl LoopCrossfade {
+    pub fn clear(&mut self) {
+        self.tail.clear();
+        self.pos = 0;
+    }
+
+    /// 从 `buf` 的第 `from` 帧开始截取淡出用的音频，之后 `buf` 会被截断到 `from` 帧
+    pub fn capture(&mut self, buf: &mut PlanarBuffer, from: usize) {
+        let max_len = (buf.spec().rate as f64 * LOOP_CROSSFADE_SECS) as usize;
+        self.pos = 0;
+        self.tail.resize_with(buf.channels(), Vec::new);
+        for (tail, plane) in self.tail.iter_mut().zip(buf.planes()) {
+            tail.clear();
+            let from = from.min(plane.len());
+            let to = (from + max_len).min(plane.len());
+            tail.extend_from_slice(&plane[from..to]);
+        }
+        buf.truncate(from);
+    }
+
+    /// 将截取到的音频以等功率曲线淡出，同时淡入 `buf` 开头的音频
+    pub fn apply(&mut self, buf: &mut PlanarBuffer) {
+        let len = self.tail.first().map(|x| x.len()).unwrap_or_default();
+        if self.pos >= len || self.tail.len() != buf.channels() {
+            self.clear();
+            return;
+        }
+        let mut end = self.pos;
+        for (tail, plane) in self.tail.iter().zip(buf.planes_mut().iter_mut()) {
+            end = self.pos;
+            for x in plane.iter_mut() {
+                if end >= len {
+                    break;
+                }
+                let t = end as f32 / len as f32 * std::f32::consts::FRAC_PI_2;
+                *x = *x * t.sin() + tail[end] * t.cos();
+                end += 1;
+            }
+        }
+        self.pos = end;
+        if self.pos >= len {
+            self.clear();
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use symphonia::core::{
+    meta::{MetadataRevision, StandardTagKey},
+    probe::ProbeResult,
+};
+
+use super::{
+    biquad::{Biquad, BiquadCoefficients},
+    buffer::PlanarBuffer,
+};
+
+/// ReplayGain 2.0 所使用的参考响度
+pub const REFERENCE_LOUDNESS: f64 = -18.;
+const ABSOLUTE_GATE: f64 = -70.;
+const RELATIVE_GATE: f64 = -10.;
+
+/// 响度标准化模式
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum NormalizationMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+/// 从音频元数据中读取到的 ReplayGain 信息，单位为 dB 和线性峰值
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ReplayGainInfo {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl ReplayGainInfo {
+    pub fn is_empty(&self) -> bool {
+        self.track_gain.is_none() && self.album_gain.is_none()
+    }
+
+    fn read_revision(&mut self, rev: &MetadataRevision) {
+        for tag in rev.tags() {
+            let value = || parse_gain_value(&tag.value.to_string());
+            match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => self.track_gain = value(),
+                Some(StandardTagKey::ReplayGainTrackPeak) => self.track_peak = value(),
+                Some(StandardTagKey::ReplayGainAlbumGain) => self.album_gain = value(),
+                Some(StandardTagKey::ReplayGainAlbumPeak) => self.album_peak = value(),
+                _ => {}
+            }
+        }
+    }
+
+    /// 读取探测时和容器内的全部元数据中的 ReplayGain 标签
+    pub fn read(probe_result: &mut ProbeResult) -> Self {
+        let mut result = Self::default();
+        if let Some(metadata) = probe_result.metadata.get() {
+            if let Some(rev) = metadata.current() {
+                result.read_revision(rev);
+            }
+        }
+        if let Some(rev) = probe_result.format.metadata().current() {
+            result.read_revision(rev);
+        }
+        result
+    }
+
+    /// 根据模式选择增益，如果有峰值信息则限制增益以避免削波
+    pub fn gain_for(&self, mode: NormalizationMode) -> Option<f64> {
+        let (gain, peak) = match mode {
+            NormalizationMode::Off => return None,
+            NormalizationMode::Track => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            NormalizationMode::Album => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+        let gain = gain?;
+        match peak {
+            Some(peak) if peak > 0. => Some(gain.min(-20. * peak.log10())),
+            _ => Some(gain),
+        }
+    }
+}
+
+/// 解析形如 `-6.54 dB` 或 `0.988525` 的标签值
+fn parse_gain_value(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value.trim().parse().ok()
+}
+
+/// 按 ITU-R BS.1770 / EBU R128 计算一首歌的综合响度
+pub struct LoudnessMeter {
+    channels: usize,
+    /// K 计权滤波器
+    filters: Vec<[Biquad; 2]>,
+    /// 每 100ms 一个子块的能量
+    sub_block_frames: usize,
+    sub_block_pos: usize,
+    sub_block_energy: f64,
+    recent_sub_blocks: [f64; 4],
+    recent_sub_blocks_count: usize,
+    /// 每个 400ms（75% 重叠）门限块的平均能量
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let rate = sample_rate as f64;
+
+        // 第一级：高频搁架滤波器，模拟头部的声学影响
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let vh = 10f64.powf(g / 20.);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1. + k / q + k * k;
+        let shelf = Biquad::new(BiquadCoefficients {
+            b0: (vh + vb * k / q + k * k) / a0,
+            b1: 2. * (k * k - vh) / a0,
+            b2: (vh - vb * k / q + k * k) / a0,
+            a1: 2. * (k * k - 1.) / a0,
+            a2: (1. - k / q + k * k) / a0,
+        });
+
+        // 第二级：RLB 高通滤波器
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (std::f64::consts::PI * f0 / rate).tan();
+        let a0 = 1. + k / q + k * k;
+        let high_pass = Biquad::new(BiquadCoefficients {
+            b0: 1.,
+            b1: -2.,
+            b2: 1.,
+            a1: 2. * (k * k - 1.) / a0,
+            a2: (1. - k / q + k * k) / a0,
+        });
+
+        Self {
+            channels,
+            filters: vec![[shelf, high_pass]; channels],
+            sub_block_frames: (sample_rate as usize / 10).max(1),
+            sub_block_pos: 0,
+            sub_block_energy: 0.,
+            recent_sub_blocks: [0.; 4],
+            recent_sub_blocks_count: 0,
+            blocks: Vec::with_capacity(4096),
+        }
+    }
+
+    pub fn process(&mut self, buf: &PlanarBuffer) {
+        let channels = self.channels.min(buf.channels());
+        let planes = &buf.planes()[..channels];
+        for frame in 0..buf.frames() {
+            let mut energy = 0.;
+            for (plane, [shelf, high_pass]) in planes.iter().zip(self.filters.iter_mut()) {
+                let s = high_pass.process(shelf.process(plane[frame] as f64));
+                energy += s * s;
+            }
+            self.sub_block_energy += energy;
+            self.sub_block_pos += 1;
+            if self.sub_block_pos >= self.sub_block_frames {
+                self.finish_sub_block();
+            }
+        }
+    }
+
+    fn finish_sub_block(&mut self) {
+        self.recent_sub_blocks.rotate_left(1);
+        self.recent_sub_blocks[3] = self.sub_block_energy / self.sub_block_frames as f64;
+        self.sub_block_energy = 0.;
+        self.sub_block_pos = 0;
+        self.recent_sub_blocks_count += 1;
+        if self.recent_sub_blocks_count >= 4 {
+            self.blocks
+                .push(self.recent_sub_blocks.iter().sum::<f64>() / 4.);
+        }
+    }
+
+    /// 返回门限后的综合响度（LUFS），如果音频过短或全为静音则返回 `None`
+    pub fn integrated_loudness(&self) -> Option<f64> {
+        let absolute_gate = loudness_to_energy(ABSOLUTE_GATE);
+        let gated = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|x| *x > absolute_gate)
+            .collect::<Vec<_>>();
+        if gated.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness_to_energy(
+            energy_to_loudness(gated.iter().sum::<f64>() / gated.len() as f64) + RELATIVE_GATE,
+        );
+        let gated = gated
+            .into_iter()
+            .filter(|x| *x > relative_gate)
+            .collect::<Vec<_>>();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(energy_to_loudness(
+            gated.iter().sum::<f64>() / gated.len() as f64,
+        ))
+    }
+}
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10. * energy.log10()
+}
+
+fn loudness_to_energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.)
+}
+
+/// 以歌曲 ID 为键缓存计算过的综合响度，保存在缓存目录中
+pub struct LoudnessCache {
+    path: PathBuf,
+    entries: HashMap<String, f64>,
+}
+
+impl LoudnessCache {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    pub fn get(&self, ncm_id: &str) -> Option<f64> {
+        self.entries.get(ncm_id).copied()
+    }
+
+    pub fn insert(&mut self, ncm_id: String, loudness: f64) {
+        self.entries.insert(ncm_id, loudness);
+        match serde_json::to_vec(&self.entries) {
+            Ok(data) => {
+                if let Err(err) = std::fs::write(&self.path, data) {
+                    println!("[WARN][AT] 无法保存响度缓存 {err}");
+                }
+            }
+            Err(err) => println!("[WARN][AT] 无法序列化响度缓存 {err}"),
+        }
+    }
+}
@@ -1,37 +1,37 @@
 use std::time::Duration;
 
 use std::sync::{
-    mpsc::{channel, Receiver, Sender},
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
     Mutex,
 };
 use std::thread::spawn;
 
-use tauri::{Manager, State};
-
+mod biquad;
+mod buffer;
+mod dsp;
+mod equalizer;
+mod fade;
+mod looping;
+mod loudness;
 mod output;
+mod peaks;
 mod player;
 mod resampler;
+mod sink;
+mod sleep_timer;
+mod stretch;
+mod visualizer;
+mod volume;
 
-#[derive(serde::Deserialize, Debug, Default, Clone)]
-#[serde(default)]
-pub struct NCMResponse<T> {
-    data: Option<T>,
-    code: i32,
-}
-
-#[derive(serde::Deserialize, Debug, Default, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct NCMSongResponse {
-    pub id: usize,
-    pub url: Option<String>,
-    pub br: usize,
-    pub size: usize,
-    pub md5: Option<String>,
-    #[serde(rename = "type")]
-    pub audio_type: Option<String>,
-    pub encode_type: Option<String>,
-    pub time: usize,
-}
+pub use dsp::DspStageKind;
+pub use equalizer::EqProfile;
+pub use looping::LoopRange;
+pub use loudness::NormalizationMode;
+pub use peaks::AudioPeaks;
+pub use resampler::ResamplerQuality;
+pub use sleep_timer::{SleepTimerAfter, SleepTimerStatus};
+pub use stretch::PlaybackRateMode;
+pub use visualizer::VisualizerConfig;
 
 #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Default, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -46,34 +46,65 @@ pub struct SongData {
 #[serde(rename_all = "camelCase")]
 pub enum AudioThreadMessage {
     #[serde(rename_all = "camelCase")]
-    ResumeAudio { callback_id: String },
+    ResumeAudio {},
     #[serde(rename_all = "camelCase")]
-    PauseAudio { callback_id: String },
+    PauseAudio {},
     #[serde(rename_all = "camelCase")]
-    SeekAudio {
-        callback_id: String,
-        position: Duration,
-    },
+    SeekAudio { position: Duration },
     #[serde(rename_all = "camelCase")]
-    JumpToSong {
-        callback_id: String,
-        song_index: usize,
-    },
+    JumpToSong { song_index: usize },
+    #[serde(rename_all = "camelCase")]
+    PrevSong {},
+    #[serde(rename_all = "camelCase")]
+    NextSong {},
+    #[serde(rename_all = "camelCase")]
+    SetPlaylist { songs: Vec<SongData> },
+    #[serde(rename_all = "camelCase")]
+    SetVolume { volume: f64 },
+    #[serde(rename_all = "camelCase")]
+    SetPreamp { preamp: f64 },
     #[serde(rename_all = "camelCase")]
-    PrevSong { callback_id: String },
+    SetAudioOutput { name: String },
     #[serde(rename_all = "camelCase")]
-    NextSong { callback_id: String },
+    SetNormalization { mode: NormalizationMode },
     #[serde(rename_all = "camelCase")]
-    SetPlaylist {
-        callback_id: String,
-        songs: Vec<SongData>,
+    SetEqualizer { profile: EqProfile },
+    #[serde(rename_all = "camelCase")]
+    SetEqPreset { preset: String },
+    /// 导入 EqualizerAPO / AutoEQ 格式的均衡器参数文本
+    #[serde(rename_all = "camelCase")]
+    ImportEqProfile { profile: String },
+    #[serde(rename_all = "camelCase")]
+    SetBalance { balance: f64 },
+    /// 按给定顺序重新排列音效处理链
+    #[serde(rename_all = "camelCase")]
+    SetDspChain { stages: Vec<DspStageKind> },
+    #[serde(rename_all = "camelCase")]
+    SetResamplerQuality { quality: ResamplerQuality },
+    /// 设置播放速度，范围为 0.5 到 2.0 倍
+    #[serde(rename_all = "camelCase")]
+    SetPlaybackRate {
+        rate: f64,
+        #[serde(default)]
+        mode: PlaybackRateMode,
     },
+    /// 设置 A-B 循环区间，播放到 `end` 时会跳回 `start`
     #[serde(rename_all = "camelCase")]
-    SetCookie { callback_id: String, cookie: String },
+    SetLoop { start: Duration, end: Duration },
     #[serde(rename_all = "camelCase")]
-    SetVolume { callback_id: String, volume: f64 },
+    ClearLoop {},
+    /// 设置暂停、继续播放、切歌和跳转时淡入淡出的时长
     #[serde(rename_all = "camelCase")]
-    SetAudioOutput { callback_id: String, name: String },
+    SetFadeDuration { duration: Duration },
+    /// 设置睡眠定时器，为空时取消
+    #[serde(rename_all = "camelCase")]
+    SetSleepTimer { after: Option<SleepTimerAfter> },
+    /// 设置每秒发送播放位置事件的次数
+    #[serde(rename_all = "camelCase")]
+    SetPositionUpdateRate { rate: f64 },
+    /// 开启或关闭可视化数据，开启后会以 `on-audio-visualizer` 事件发送给前端
+    #[serde(rename_all = "camelCase")]
+    SetVisualizer { config: Option<VisualizerConfig> },
     #[serde(rename_all = "camelCase")]
     SyncStatus,
 }
@@ -81,6 +112,8 @@ pub enum AudioThreadMessage {
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "type", content = "data")]
+// 事件发出后立即序列化给前端，不会大量保存，`SyncStatus` 不需要装箱
+#[allow(clippy::large_enum_variant)]
 pub enum AudioThreadEvent {
     #[serde(rename_all = "camelCase")]
     PlayPosition { position: f64 },
@@ -97,6 +130,20 @@ pub enum AudioThreadEvent {
         duration: f64,
         position: f64,
         volume: f64,
+        preamp: f64,
+        normalization: NormalizationMode,
+        normalization_gain: f64,
+        equalizer: EqProfile,
+        balance: f64,
+        dsp_chain: Vec<DspStageKind>,
+        resampler_quality: ResamplerQuality,
+        playback_rate: f64,
+        playback_rate_mode: PlaybackRateMode,
+        loop_range: Option<LoopRange>,
+        fade_duration: f64,
+        sleep_timer: Option<SleepTimerStatus>,
+        position_update_rate: f64,
+        visualizer: Option<VisualizerConfig>,
         load_position: f64,
         playlist: Vec<SongData>,
     },
@@ -104,108 +151,253 @@ pub enum AudioThreadEvent {
     PlayStatus { is_playing: bool },
     #[serde(rename_all = "camelCase")]
     LoadError { error: String },
+    /// 播放中输出设备断开并且无法恢复，播放已暂停
+    #[serde(rename_all = "camelCase")]
+    OutputError { error: String },
+    #[serde(rename_all = "camelCase")]
+    AudioPeaksReady { ncm_id: String },
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+/// 音频线程处理完消息后返回给调用方的数据，数值均为实际生效的值
+#[derive(serde::Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
-pub struct AudioThreadEventMessage<T> {
-    callback_id: String,
-    data: T,
+#[serde(tag = "type", content = "data")]
+pub enum AudioResponse {
+    /// 消息已处理，没有需要返回的数据
+    Done,
+    #[serde(rename_all = "camelCase")]
+    PlayStatus { is_playing: bool },
+    /// 切歌后即将播放的歌曲
+    #[serde(rename_all = "camelCase")]
+    SongIndex { index: usize, ncm_id: String },
+    /// 设置播放列表后当前歌曲在新列表中的位置，不在列表中时为空
+    #[serde(rename_all = "camelCase")]
+    Playlist {
+        len: usize,
+        current_index: Option<usize>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Position { position: f64 },
+    #[serde(rename_all = "camelCase")]
+    Volume { volume: f64 },
+    #[serde(rename_all = "camelCase")]
+    Preamp { preamp: f64 },
+    #[serde(rename_all = "camelCase")]
+    AudioOutput { name: String },
+    #[serde(rename_all = "camelCase")]
+    Normalization { mode: NormalizationMode, gain: f64 },
+    #[serde(rename_all = "camelCase")]
+    Equalizer { profile: EqProfile },
+    #[serde(rename_all = "camelCase")]
+    Balance { balance: f64 },
+    #[serde(rename_all = "camelCase")]
+    DspChain {
+        stages: Vec<DspStageKind>,
+        latency: usize,
+    },
+    #[serde(rename_all = "camelCase")]
+    ResamplerQuality { quality: ResamplerQuality },
+    #[serde(rename_all = "camelCase")]
+    PlaybackRate { rate: f64, mode: PlaybackRateMode },
+    #[serde(rename_all = "camelCase")]
+    Loop { range: Option<LoopRange> },
+    #[serde(rename_all = "camelCase")]
+    FadeDuration { duration: f64 },
+    #[serde(rename_all = "camelCase")]
+    SleepTimer { status: Option<SleepTimerStatus> },
+    #[serde(rename_all = "camelCase")]
+    PositionUpdateRate { rate: f64 },
+    #[serde(rename_all = "camelCase")]
+    Visualizer { config: Option<VisualizerConfig> },
 }
 
-impl AudioThreadMessage {
-    pub fn ret(
-        &self,
-        app: &tauri::AppHandle,
-        data: impl serde::Serialize + Clone,
-    ) -> tauri::Result<()> {
-        app.emit_all(
-            "on_audio_thread_message",
-            AudioThreadEventMessage {
-                callback_id: self.callback_id().to_owned(),
-                data,
-            },
-        )
-    }
+/// 音频线程无法完成消息时返回给调用方的错误
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "type", content = "data")]
+pub enum AudioError {
+    /// 音频线程还没有启动或者已经退出
+    ThreadNotRunning,
+    EmptyPlaylist,
+    #[serde(rename_all = "camelCase")]
+    InvalidSongIndex {
+        index: usize,
+        len: usize,
+    },
+    /// 当前没有已经加载的歌曲，无法跳转
+    NoTrackLoaded,
+    #[serde(rename_all = "camelCase")]
+    SeekFailed {
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    OutputDeviceNotFound {
+        name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    OutputDevice {
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    UnknownEqPreset {
+        preset: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    InvalidEqProfile {
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    InvalidLoopRange {
+        start: f64,
+        end: f64,
+    },
+}
 
-    pub fn callback_id(&self) -> &str {
-        match &self {
-            AudioThreadMessage::ResumeAudio { callback_id } => callback_id.as_str(),
-            AudioThreadMessage::PauseAudio { callback_id } => callback_id.as_str(),
-            AudioThreadMessage::SeekAudio { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::JumpToSong { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::PrevSong { callback_id } => callback_id.as_str(),
-            AudioThreadMessage::NextSong { callback_id } => callback_id.as_str(),
-            AudioThreadMessage::SetPlaylist { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::SetCookie { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::SetVolume { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::SetAudioOutput { callback_id, .. } => callback_id.as_str(),
-            AudioThreadMessage::SyncStatus { .. } => "",
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ThreadNotRunning => write!(f, "音频线程未运行"),
+            Self::EmptyPlaylist => write!(f, "播放列表为空"),
+            Self::InvalidSongIndex { index, len } => {
+                write!(f, "歌曲序号 {index} 超出了播放列表的范围（共 {len} 首）")
+            }
+            Self::NoTrackLoaded => write!(f, "当前没有正在播放的歌曲"),
+            Self::SeekFailed { error } => write!(f, "无法跳转 {error}"),
+            Self::OutputDeviceNotFound { name } => write!(f, "找不到输出设备 {name}"),
+            Self::OutputDevice { error } => write!(f, "输出设备出错 {error}"),
+            Self::UnknownEqPreset { preset } => write!(f, "未知的均衡器预设 {preset}"),
+            Self::InvalidEqProfile { error } => write!(f, "无法解析均衡器参数 {error}"),
+            Self::InvalidLoopRange { start, end } => {
+                write!(f, "循环区间不合法 {start:.2}s - {end:.2}s")
+            }
         }
     }
 }
 
-static MSG_SENDER: Mutex<Option<Sender<AudioThreadMessage>>> = Mutex::new(None);
+pub type AudioResult = std::result::Result<AudioResponse, AudioError>;
+
+/// 音频线程等待的输入，除了前端发来的消息以外，下载线程也会通过它唤醒音频线程
+pub enum AudioThreadInput {
+    /// 需要处理的消息，以及用来返回处理结果的通道，不需要结果时为空
+    Message(
+        AudioThreadMessage,
+        Option<tokio::sync::oneshot::Sender<AudioResult>>,
+    ),
+    /// 下载线程的状态或进度发生了变化
+    DownloadProgress,
+    /// 后台解码测得了歌曲的综合响度，单位为 LUFS
+    LoudnessMeasured { ncm_id: String, loudness: f64 },
+}
+
+static MSG_SENDER: Mutex<Option<Sender<AudioThreadInput>>> = Mutex::new(None);
 
 pub fn stop_audio_thread() {
     (*MSG_SENDER.lock().unwrap()) = None;
 }
 
-fn send_msg_to_audio_thread_inner(msg: AudioThreadMessage) -> std::result::Result<(), String> {
-    let sx = MSG_SENDER.lock().map_err(|x| x.to_string())?;
-    let sx = sx
-        .as_ref()
-        .ok_or_else(|| "线程消息通道未建立".to_string())?;
-    sx.send(msg).map_err(|x| x.to_string())?;
-    Ok(())
+fn send_msg_to_audio_thread_inner(
+    msg: AudioThreadMessage,
+    reply: Option<tokio::sync::oneshot::Sender<AudioResult>>,
+) -> std::result::Result<(), AudioError> {
+    let sx = MSG_SENDER.lock().unwrap();
+    let sx = sx.as_ref().ok_or(AudioError::ThreadNotRunning)?;
+    sx.send(AudioThreadInput::Message(msg, reply))
+        .map_err(|_| AudioError::ThreadNotRunning)
 }
 
-#[tauri::command]
-pub fn send_msg_to_audio_thread(
-    app_state: State<crate::AppState>,
-    msg: AudioThreadMessage,
-) -> std::result::Result<(), String> {
-    if let AudioThreadMessage::SetCookie { cookie, .. } = &msg {
-        *app_state.cookie.lock().unwrap() = cookie.to_owned();
-        let mut session = app_state.session.lock().unwrap();
-        session.header("cookie", cookie.to_owned());
+/// 通知音频线程下载状态发生了变化，音频线程未运行时忽略
+fn notify_download_progress() {
+    if let Some(sx) = MSG_SENDER.lock().unwrap().as_ref() {
+        let _ = sx.send(AudioThreadInput::DownloadProgress);
+    }
+}
+
+/// 把后台测得的响度交给音频线程写入缓存，音频线程未运行时忽略
+fn notify_loudness_measured(ncm_id: &str, loudness: f64) {
+    if let Some(sx) = MSG_SENDER.lock().unwrap().as_ref() {
+        let _ = sx.send(AudioThreadInput::LoudnessMeasured {
+            ncm_id: ncm_id.to_owned(),
+            loudness,
+        });
     }
-    send_msg_to_audio_thread_inner(msg)
 }
 
-pub fn audio_thread_main(app: tauri::AppHandle, rx: Receiver<AudioThreadMessage>) {
+/// 向音频线程发送消息，并等待音频线程处理完毕后返回结果
+#[tauri::command]
+pub async fn send_msg_to_audio_thread(msg: AudioThreadMessage) -> AudioResult {
+    let (sx, rx) = tokio::sync::oneshot::channel();
+    send_msg_to_audio_thread_inner(msg, Some(sx))?;
+    rx.await.map_err(|_| AudioError::ThreadNotRunning)?
+}
+
+pub fn audio_thread_main(app: tauri::AppHandle, rx: Receiver<AudioThreadInput>) {
     println!("音频线程已开始运行！");
     let mut player = player::AudioPlayer::new(app);
 
     loop {
-        if player.is_playing() {
-            for msg in rx.try_iter() {
-                player.process_message(msg);
-            }
-            player.process_audio();
-        } else {
-            match rx.recv() {
-                Ok(msg) => player.process_message(msg),
+        // 同时等待消息、下载进度和输出缓冲区腾出空间，没有事情要做时不会占用 CPU
+        let input = match player.next_wakeup() {
+            Some(timeout) => match rx.recv_timeout(timeout) {
+                Ok(input) => Some(input),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(input) => Some(input),
                 _ => break,
+            },
+        };
+        for input in input.into_iter().chain(rx.try_iter()) {
+            match input {
+                AudioThreadInput::Message(msg, reply) => {
+                    let result = player.process_message(msg);
+                    if let Err(err) = &result {
+                        println!("[WARN][AT] 无法处理消息 {err}");
+                    }
+                    if let Some(reply) = reply {
+                        let _ = reply.send(result);
+                    }
+                }
+                AudioThreadInput::LoudnessMeasured { ncm_id, loudness } => {
+                    player.store_measured_loudness(ncm_id, loudness);
+                }
+                AudioThreadInput::DownloadProgress => {}
             }
         }
+        if player.is_playing() {
+            player.process_audio();
+        }
     }
     (*MSG_SENDER.lock().unwrap()) = None;
     println!("音频线程已结束运行！");
 }
 
+/// 获取歌曲的波形，用于在进度条上绘制，计算在后台进行，不会影响音频线程
+#[tauri::command]
+pub async fn get_audio_peaks(
+    app: tauri::AppHandle,
+    ncm_id: String,
+    local_file: String,
+    interval_ms: Option<u32>,
+) -> std::result::Result<Option<AudioPeaks>, String> {
+    peaks::get_peaks(app, ncm_id, local_file, interval_ms)
+        .await
+        .map_err(|x| format!("{x:?}"))
+}
+
 #[tauri::command]
 pub async fn init_audio_thread(app: tauri::AppHandle) -> std::result::Result<(), String> {
     let mut sender = MSG_SENDER.lock().map_err(|x| x.to_string())?;
     if sender.is_none() {
-        let (sx, rx) = channel::<AudioThreadMessage>();
+        let (sx, rx) = channel::<AudioThreadInput>();
         (*sender) = Some(sx);
         spawn(move || {
             audio_thread_main(app, rx);
         });
     } else {
         drop(sender);
-        send_msg_to_audio_thread_inner(AudioThreadMessage::SyncStatus)?;
+        send_msg_to_audio_thread_inner(AudioThreadMessage::SyncStatus, None)
+            .map_err(|x| x.to_string())?;
     }
     Ok(())
 }
@@ -1,9 +1,14 @@
 use std::sync::{
-    atomic::{AtomicBool, AtomicU8},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 
-use super::resampler::Resampler;
+use super::buffer::PlanarBuffer;
+use super::fade::{Fader, SharedFade};
+use super::resampler::{Resampler, ResamplerQuality};
+use super::visualizer::VisualizerTap;
+use super::volume::{GainProcessor, OutputVolume};
 use cpal::{traits::*, *};
 use rb::*;
 use symphonia::core::{
@@ -14,25 +19,66 @@ use symphonia::core::{
 pub trait AudioOutput {
     fn stream_config(&self) -> &StreamConfig;
     fn sample_format(&self) -> SampleFormat;
-    fn stream(&self) -> &Stream;
+    /// 开始播放，并从静音淡入
+    fn play(&mut self) -> anyhow::Result<()>;
+    /// 开始淡出，淡出到静音后才会真正暂停播放，不会阻塞
+    fn pause(&mut self) -> anyhow::Result<()>;
     fn is_dead(&self) -> bool;
-    fn stream_mut(&mut self) -> &mut Stream;
     fn set_volume(&mut self, volume: f64);
     fn volume(&self) -> f64;
-    fn write(&mut self, decoded: symphonia::core::audio::AudioBufferRef<'_>);
+    /// 设置软件前级增益，单位为分贝
+    fn set_preamp(&mut self, preamp_db: f64);
+    fn preamp(&self) -> f64;
+    fn set_resampler_quality(&mut self, quality: ResamplerQuality);
+    fn resampler_quality(&self) -> ResamplerQuality;
+    /// 通过改变重采样比例来改变播放速度，音调会随之变化
+    fn set_speed(&mut self, speed: f64);
+    fn set_fade_duration(&mut self, duration: Duration);
+    /// 淡出并丢弃已经写入但还没有播放的音频，用于切歌和跳转
+    ///
+    /// 不会阻塞，完成之前新写入的数据会先积压起来
+    fn clear(&mut self);
+    /// 写入音频数据，不会阻塞，输出暂时放不下的部分会先积压起来
+    fn write(&mut self, decoded: &PlanarBuffer);
+    /// 尽量把积压的数据写入输出，返回还需要等待多久才能继续写入新的数据
+    fn poll_write(&mut self) -> Duration;
+    /// 推进暂停和清空时的淡出过程，返回还需要等待多久才能继续推进，为空时表示没有正在进行的淡出
+    fn poll_fade(&mut self) -> Option<Duration>;
     fn flush(&mut self);
+    /// 已经写入输出的音频总时长，不包括还积压在重采样器中的部分
+    fn written_duration(&self) -> Duration;
+    /// 已经实际播放出来的音频总时长，已经扣除了设备延迟
+    fn played_duration(&self) -> Duration;
+    /// 还积压在重采样器中的输入时长，按输入的采样率计算
+    fn pending_input(&self) -> Duration;
 }
 
-pub struct AudioStreamPlayer<T: AudioOutputSample> {
-    config: StreamConfig,
-    sample_format: SampleFormat,
-    stream: Stream,
-    is_dead: Arc<AtomicBool>,
-    prod: rb::Producer<T>,
-    volume: Arc<AtomicU8>,
-    resampler: Option<Resampler<T>>,
-    resampler_duration: usize,
-    resampler_spec: SignalSpec,
+/// 输出回调统计的播放进度，在音频线程和输出回调之间共享
+#[derive(Debug, Default)]
+pub struct OutputClock {
+    /// 输出回调已经从环形缓冲区中取走的帧数
+    consumed: AtomicU64,
+    /// 最近一次回调时数据从回调到实际播放之间的延迟，单位为纳秒
+    latency_ns: AtomicU64,
+}
+
+impl OutputClock {
+    pub fn add_consumed(&self, frames: u64) {
+        self.consumed.fetch_add(frames, Ordering::SeqCst);
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.consumed.load(Ordering::SeqCst)
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency_ns
+            .store(latency.as_nanos() as _, Ordering::SeqCst);
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_nanos(self.latency_ns.load(Ordering::SeqCst))
+    }
 }
 
 pub trait AudioOutputSample:
@@ -57,76 +103,364 @@ impl AudioOutputSample for u32 {}
 impl AudioOutputSample for f32 {}
 impl AudioOutputSample for f64 {}
 
-impl<T: AudioOutputSample> AudioOutput for AudioStreamPlayer<T> {
-    fn stream_config(&self) -> &StreamConfig {
-        &self.config
+/// 将解码数据重采样到输出采样率，输入格式或质量设置变化时会自动重建重采样器
+pub struct OutputResampler<T: AudioOutputSample> {
+    sample_rate: u32,
+    resampler: Option<Resampler<T>>,
+    quality: ResamplerQuality,
+    speed: f64,
+    duration: usize,
+    spec: SignalSpec,
+    /// 因为设置变化而重建重采样器时保留下来的输入，格式与 `spec` 相同
+    pending: Vec<Vec<f32>>,
+}
+
+impl<T: AudioOutputSample> OutputResampler<T> {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            resampler: None,
+            quality: ResamplerQuality::default(),
+            speed: 1.,
+            duration: 0,
+            spec: SignalSpec {
+                rate: 0,
+                channels: Channels::empty(),
+            },
+            pending: Vec::new(),
+        }
     }
 
-    fn sample_format(&self) -> SampleFormat {
-        self.sample_format
+    /// 下次写入时按新的设置重建重采样器，还没有被重采样的输入会接到新的重采样器中，避免丢失音频
+    fn rebuild_resampler(&mut self) {
+        if let Some(mut resampler) = self.resampler.take() {
+            self.pending = resampler.take_pending();
+        }
     }
 
-    fn stream(&self) -> &Stream {
-        &self.stream
+    pub fn set_quality(&mut self, quality: ResamplerQuality) {
+        if self.quality != quality {
+            self.quality = quality;
+            self.rebuild_resampler();
+        }
     }
 
-    fn stream_mut(&mut self) -> &mut Stream {
-        &mut self.stream
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
     }
 
-    fn is_dead(&self) -> bool {
-        self.is_dead.load(std::sync::atomic::Ordering::SeqCst)
+    pub fn set_speed(&mut self, speed: f64) {
+        if self.speed != speed {
+            self.speed = speed;
+            self.rebuild_resampler();
+        }
     }
 
-    fn set_volume(&mut self, volume: f64) {
-        let volume = (volume * 255.).clamp(0., 255.) as u8;
-        self.volume
-            .store(volume, std::sync::atomic::Ordering::SeqCst);
+    /// 按播放速度换算出的重采样目标采样率
+    ///
+    /// 取整到 100Hz，避免 FFT 重采样器因为两个采样率的最大公约数过小而需要极大的 FFT 长度。
+    fn target_sample_rate(&self) -> usize {
+        if self.speed == 1. {
+            return self.sample_rate as _;
+        }
+        ((self.sample_rate as f64 / self.speed / 100.).round() as usize * 100).max(100)
     }
 
-    fn volume(&self) -> f64 {
-        self.volume.load(std::sync::atomic::Ordering::SeqCst) as f64 / 255.
+    /// 丢弃还积压在重采样器中的输入
+    pub fn clear(&mut self) {
+        self.resampler = None;
+        self.pending.clear();
+    }
+
+    pub fn pending_input(&self) -> Duration {
+        if self.spec.rate == 0 {
+            return Duration::ZERO;
+        }
+        let frames = match &self.resampler {
+            Some(resampler) => resampler.pending_frames(),
+            None => self.pending.first().map(|x| x.len()).unwrap_or_default(),
+        };
+        Duration::from_secs_f64(frames as f64 / self.spec.rate as f64)
     }
 
-    fn write(&mut self, decoded: symphonia::core::audio::AudioBufferRef<'_>) {
+    /// 返回交错格式的重采样结果，如果积累的数据还不够一个分块则返回 `None`
+    pub fn resample(&mut self, decoded: &PlanarBuffer) -> Option<&[T]> {
         if decoded.frames() == 0 {
-            return;
+            return None;
         }
 
         let should_replace_resampler = self.resampler.is_none()
-            || self.resampler_duration != decoded.capacity()
-            || &self.resampler_spec != decoded.spec();
+            || self.duration != decoded.capacity()
+            || &self.spec != decoded.spec();
 
         if should_replace_resampler {
-            self.resampler = Some(Resampler::<T>::new(
+            let mut resampler = Resampler::<T>::new(
                 *decoded.spec(),
-                self.config.sample_rate.0 as _,
+                self.target_sample_rate(),
                 decoded.capacity() as _,
-            ));
+                self.quality,
+            );
+            let pending = std::mem::take(&mut self.pending);
+            // 输入格式变化时（例如切歌）之前积压的输入已经没有意义
+            if &self.spec == decoded.spec() {
+                resampler.restore_pending(pending);
+            }
+            self.resampler = Some(resampler);
             println!(
-                "将会重采样 {}hz -> {}hz",
+                "将会重采样 {}hz -> {}hz ({:?}，{}x)",
                 decoded.spec().rate,
-                self.config.sample_rate.0
+                self.sample_rate,
+                self.quality,
+                self.speed
             );
-            self.resampler_duration = decoded.capacity();
-            self.resampler_spec = *decoded.spec();
+            self.duration = decoded.capacity();
+            self.spec = *decoded.spec();
         }
 
-        let rsp = self.resampler.as_mut().unwrap();
+        self.resampler.as_mut().unwrap().resample(decoded)
+    }
+}
+
+/// 等待输出回调处理清空请求的最长时间
+const CLEAR_TIMEOUT: Duration = Duration::from_millis(100);
+/// 等待淡出时两次检查之间的最短间隔
+const FADE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// 暂停或清空时需要等输出回调淡出到静音后才能完成的操作
+#[derive(Clone, Copy)]
+struct FadeTask {
+    /// 完成后暂停输出流
+    pause: bool,
+    /// 完成后丢弃环形缓冲区中尚未播放的数据
+    clear: bool,
+    /// 已经请求输出回调清空环形缓冲区，正在等待它处理
+    clearing: bool,
+    /// 输出流没有在运行时迟迟无法完成，超过这个时间后直接继续
+    deadline: Instant,
+}
+
+pub struct AudioStreamPlayer<T: AudioOutputSample> {
+    config: StreamConfig,
+    sample_format: SampleFormat,
+    stream: Stream,
+    is_dead: Arc<AtomicBool>,
+    is_running: bool,
+    ring: rb::SpscRb<T>,
+    prod: rb::Producer<T>,
+    /// 环形缓冲区已满时暂存的交错音频数据
+    backlog: Vec<T>,
+    written_frames: u64,
+    clock: Arc<OutputClock>,
+    fade: Arc<SharedFade>,
+    fade_task: Option<FadeTask>,
+    volume: OutputVolume,
+    resampler: OutputResampler<T>,
+}
+
+impl<T: AudioOutputSample> AudioStreamPlayer<T> {
+    fn fade_deadline(&self) -> Instant {
+        Instant::now() + self.fade.duration() + CLEAR_TIMEOUT
+    }
 
-        if let Some(mut buf) = rsp.resample(decoded) {
-            while let Some(written) = self.prod.write_blocking(buf) {
-                buf = &buf[written..];
+    /// 输出流已经暂停，可以直接清空环形缓冲区
+    fn clear_ring(&mut self) {
+        self.ring.clear();
+        let backlog = (self.backlog.len() / self.config.channels as usize) as u64;
+        self.written_frames = self.clock.consumed() + backlog;
+    }
+}
+
+impl<T: AudioOutputSample> AudioOutput for AudioStreamPlayer<T> {
+    fn stream_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.sample_format
+    }
+
+    fn play(&mut self) -> anyhow::Result<()> {
+        if !self.is_running {
+            self.fade.mute_now();
+        }
+        match self.fade_task.as_mut() {
+            // 清空完成后会自动淡入，提前淡入会播放出将要丢弃的数据
+            Some(task) if task.clear => task.pause = false,
+            _ => {
+                self.fade_task = None;
+                self.fade.fade_in();
             }
         }
+        self.stream.play()?;
+        self.is_running = true;
+        Ok(())
+    }
+
+    fn pause(&mut self) -> anyhow::Result<()> {
+        if !self.is_running {
+            return Ok(self.stream.pause()?);
+        }
+        // 输出流要等淡出完成后才会在 `poll_fade` 中暂停
+        self.fade.fade_out();
+        let deadline = self.fade_deadline();
+        let task = self.fade_task.get_or_insert(FadeTask {
+            pause: true,
+            clear: false,
+            clearing: false,
+            deadline,
+        });
+        task.pause = true;
+        Ok(())
+    }
+
+    fn is_dead(&self) -> bool {
+        self.is_dead.load(Ordering::SeqCst)
+    }
+
+    fn set_volume(&mut self, volume: f64) {
+        self.volume.set_volume(volume);
+    }
+
+    fn volume(&self) -> f64 {
+        self.volume.volume()
+    }
+
+    fn set_preamp(&mut self, preamp_db: f64) {
+        self.volume.set_preamp(preamp_db);
+    }
+
+    fn preamp(&self) -> f64 {
+        self.volume.preamp()
+    }
+
+    fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.resampler.set_quality(quality);
+    }
+
+    fn resampler_quality(&self) -> ResamplerQuality {
+        self.resampler.quality()
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.resampler.set_speed(speed);
+    }
+
+    fn set_fade_duration(&mut self, duration: Duration) {
+        self.fade.set_duration(duration);
+    }
+
+    fn clear(&mut self) {
+        let channels = self.config.channels as usize;
+        self.written_frames -= (self.backlog.len() / channels) as u64;
+        self.backlog.clear();
+        self.resampler.clear();
+        if !self.is_running {
+            self.clear_ring();
+            self.fade.mute_now();
+            self.fade.fade_in();
+            return;
+        }
+        // 清空完成之前不会再写入环形缓冲区，已经在等待清空时不需要重新开始
+        self.fade.fade_out();
+        let deadline = self.fade_deadline();
+        let task = self.fade_task.get_or_insert(FadeTask {
+            pause: false,
+            clear: true,
+            clearing: false,
+            deadline,
+        });
+        if !task.clear {
+            task.clear = true;
+            task.deadline = deadline;
+        }
+    }
+
+    fn write(&mut self, decoded: &PlanarBuffer) {
+        if let Some(buf) = self.resampler.resample(decoded) {
+            self.written_frames += (buf.len() / self.config.channels as usize) as u64;
+            self.backlog.extend_from_slice(buf);
+        }
+        self.poll_write();
+    }
+
+    fn poll_write(&mut self) -> Duration {
+        if let Some(wait) = self.poll_fade() {
+            if self.fade_task.is_some_and(|x| x.clear) {
+                // 清空完成之前写入的数据会被一起丢弃，先留在积压的数据中
+                return wait;
+            }
+        }
+        if !self.backlog.is_empty() {
+            let written = self.prod.write(&self.backlog).unwrap_or(0);
+            self.backlog.drain(..written);
+        }
+        if self.backlog.is_empty() {
+            return Duration::ZERO;
+        }
+        // 等到环形缓冲区只剩一半数据时再继续写入，减少唤醒次数
+        let buffered = self.ring.count().saturating_sub(self.ring.capacity() / 2);
+        let frames = buffered / self.config.channels as usize;
+        Duration::from_secs_f64(frames as f64 / self.config.sample_rate.0 as f64)
+            .max(Duration::from_millis(1))
+    }
+
+    fn poll_fade(&mut self) -> Option<Duration> {
+        let mut task = self.fade_task?;
+        let now = Instant::now();
+        if now < task.deadline {
+            if task.clearing && self.fade.is_clear_requested() {
+                return Some(FADE_POLL_INTERVAL);
+            }
+            if !task.clearing && self.fade.level() > 0. {
+                // 按当前电平估算剩余的淡出时间
+                let remaining = self.fade.duration().mul_f32(self.fade.level());
+                return Some(remaining.min(task.deadline - now).max(FADE_POLL_INTERVAL));
+            }
+        }
+        if task.clear && !task.clearing && !task.pause {
+            // 输出回调正在读取环形缓冲区，只能交给它自己清空
+            self.fade.request_clear();
+            task.clearing = true;
+            task.deadline = now + CLEAR_TIMEOUT;
+            self.fade_task = Some(task);
+            return Some(FADE_POLL_INTERVAL);
+        }
+        self.fade_task = None;
+        if task.pause {
+            self.is_running = false;
+            if let Err(err) = self.stream.pause() {
+                println!("[WARN][AT] 无法暂停输出流 {err}");
+            }
+            if task.clear && !task.clearing {
+                self.clear_ring();
+            }
+        } else if task.clear {
+            self.fade.mute_now();
+            self.fade.fade_in();
+        }
+        None
     }
 
     fn flush(&mut self) {}
+
+    fn written_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.written_frames as f64 / self.config.sample_rate.0 as f64)
+    }
+
+    fn played_duration(&self) -> Duration {
+        let consumed = self.clock.consumed() as f64 / self.config.sample_rate.0 as f64;
+        Duration::from_secs_f64(consumed).saturating_sub(self.clock.latency())
+    }
+
+    fn pending_input(&self) -> Duration {
+        self.resampler.pending_input()
+    }
 }
 
 fn init_audio_stream_inner<T: AudioOutputSample + Into<f64>>(
     output: Device,
     selected_config: StreamConfig,
+    visualizer_tap: Arc<VisualizerTap>,
 ) -> Box<dyn AudioOutput> {
     let ring_len =
         ((200 * selected_config.sample_rate.0 as usize) / 1000) * selected_config.channels as usize;
@@ -135,45 +469,98 @@ fn init_audio_stream_inner<T: AudioOutputSample + Into<f64>>(
     let cons = ring.consumer();
     let is_dead = Arc::new(AtomicBool::new(false));
     let is_dead_c = is_dead.clone();
-    let volume: Arc<_> = Arc::new(AtomicU8::new(u8::MAX >> 1));
-    let volume_c = volume.clone();
+    let fade = Arc::new(SharedFade::default());
+    let fade_c = fade.clone();
+    let clock = Arc::new(OutputClock::default());
+    let clock_c = clock.clone();
+    let channels = selected_config.channels as usize;
+    let sample_rate = selected_config.sample_rate.0;
+    let mut fader = Fader::new(
+        fade.clone(),
+        selected_config.sample_rate.0,
+        selected_config.channels as _,
+    );
+    let volume = OutputVolume::default();
+    let mut gain_processor = GainProcessor::new(
+        volume.shared_gain(),
+        selected_config.sample_rate.0,
+        selected_config.channels as _,
+    );
     let stream = output
         .build_output_stream::<T, _, _>(
             &selected_config,
-            move |data, _info| {
+            move |data, info| {
+                if fade_c.take_clear_request() {
+                    let skipped = cons.skip_pending().unwrap_or(0);
+                    clock_c.add_consumed((skipped / channels) as u64);
+                }
                 let written = cons.read(data).unwrap_or(0);
+                clock_c.add_consumed((written / channels) as u64);
+                let timestamp = info.timestamp();
+                if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                    clock_c.set_latency(latency);
+                }
                 data[written..].fill(T::MID);
-                let volume = volume_c.load(std::sync::atomic::Ordering::SeqCst) as f32 / 255.;
-                data.iter_mut().for_each(|x| {
-                    let s: f32 = (*x).into_sample();
-                    *x = (s * volume).into_sample();
-                });
+                if visualizer_tap.is_enabled() {
+                    // 在调节音量前取样，可视化效果不受音量影响
+                    visualizer_tap.push(data, channels, sample_rate);
+                }
+                gain_processor.process(data);
+                fader.process(data);
             },
             move |err| {
                 println!("[WARN][AT] {err}");
-                is_dead_c.store(true, std::sync::atomic::Ordering::SeqCst);
+                is_dead_c.store(true, Ordering::SeqCst);
             },
             None,
         )
         .unwrap();
     println!("音频输出流准备完毕！");
     Box::new(AudioStreamPlayer {
-        config: selected_config,
         sample_format: <T as SizedSample>::FORMAT,
         stream,
         prod,
+        ring,
+        backlog: Vec::new(),
+        written_frames: 0,
+        clock,
+        fade,
+        fade_task: None,
         is_dead,
+        is_running: false,
         volume,
-        resampler: None,
-        resampler_duration: 0,
-        resampler_spec: SignalSpec {
-            rate: 0,
-            channels: Channels::empty(),
-        },
+        resampler: OutputResampler::new(selected_config.sample_rate.0),
+        config: selected_config,
     })
 }
 
-pub fn init_audio_player(output_device_name: &str) -> Box<dyn AudioOutput> {
+/// 检查输出设备是否存在，空名称表示系统默认设备
+pub fn output_device_exists(output_device_name: &str) -> bool {
+    if output_device_name.is_empty() || super::sink::is_sink_name(output_device_name) {
+        return true;
+    }
+    cpal::default_host()
+        .output_devices()
+        .map(|mut x| x.any(|d| d.name().unwrap_or_default() == output_device_name))
+        .unwrap_or(false)
+}
+
+/// 初始化输出设备
+///
+/// 除了系统中的音频设备名称以外，还支持以下几种特殊名称：
+///
+/// - `null`：丢弃所有音频数据，但会按实时速度消耗，用于在没有声卡的环境下运行
+/// - `wav:<路径>`：将输出写入 32 位浮点 WAV 文件
+/// - `raw:<路径>`：将输出写入 32 位浮点小端序交错 PCM 文件
+///
+/// 特殊输出不会向 `visualizer_tap` 提供可视化数据。
+pub fn init_audio_player(
+    output_device_name: &str,
+    visualizer_tap: Arc<VisualizerTap>,
+) -> Box<dyn AudioOutput> {
+    if let Some(sink) = super::sink::init_sink(output_device_name) {
+        return sink;
+    }
     let host = cpal::default_host();
     let output = if output_device_name.is_empty() {
         host.default_output_device().unwrap()
@@ -218,16 +605,28 @@ pub fn init_audio_player(output_device_name: &str) -> Box<dyn AudioOutput> {
         selected_config.sample_rate.0, selected_config.channels,
     );
     match selected_sample_format {
-        SampleFormat::I8 => init_audio_stream_inner::<i8>(output, selected_config),
-        SampleFormat::I16 => init_audio_stream_inner::<i16>(output, selected_config),
-        SampleFormat::I32 => init_audio_stream_inner::<i32>(output, selected_config),
-        // SampleFormat::I64 => init_audio_stream_inner::<i64>(output, selected_config),
-        SampleFormat::U8 => init_audio_stream_inner::<u8>(output, selected_config),
-        SampleFormat::U16 => init_audio_stream_inner::<u16>(output, selected_config),
-        SampleFormat::U32 => init_audio_stream_inner::<u32>(output, selected_config),
-        // SampleFormat::U64 => init_audio_stream_inner::<u64>(output, selected_config),
-        SampleFormat::F32 => init_audio_stream_inner::<f32>(output, selected_config),
-        SampleFormat::F64 => init_audio_stream_inner::<f64>(output, selected_config),
+        SampleFormat::I8 => init_audio_stream_inner::<i8>(output, selected_config, visualizer_tap),
+        SampleFormat::I16 => {
+            init_audio_stream_inner::<i16>(output, selected_config, visualizer_tap)
+        }
+        SampleFormat::I32 => {
+            init_audio_stream_inner::<i32>(output, selected_config, visualizer_tap)
+        }
+        // SampleFormat::I64 => init_audio_stream_inner::<i64>(output, selected_config, visualizer_tap),
+        SampleFormat::U8 => init_audio_stream_inner::<u8>(output, selected_config, visualizer_tap),
+        SampleFormat::U16 => {
+            init_audio_stream_inner::<u16>(output, selected_config, visualizer_tap)
+        }
+        SampleFormat::U32 => {
+            init_audio_stream_inner::<u32>(output, selected_config, visualizer_tap)
+        }
+        // SampleFormat::U64 => init_audio_stream_inner::<u64>(output, selected_config, visualizer_tap),
+        SampleFormat::F32 => {
+            init_audio_stream_inner::<f32>(output, selected_config, visualizer_tap)
+        }
+        SampleFormat::F64 => {
+            init_audio_stream_inner::<f64>(output, selected_config, visualizer_tap)
+        }
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashSet,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use symphonia::core::{
+    errors::Error as DecodeError,
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+};
+use tauri::Manager;
+
+use super::{buffer::PlanarBuffer, loudness::LoudnessMeter, AudioThreadEvent};
+
+/// 缓存的波形中每个采样点覆盖的时长，前端请求更大的间隔时再合并
+pub const PEAKS_INTERVAL_MS: u32 = 10;
+
+/// 正在计算中的波形缓存文件，避免同一首歌被重复计算
+static RUNNING_JOBS: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Default::default);
+/// 同一时间只解码一首歌，尽量少占用 CPU，避免影响音频线程
+static DECODE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 整首歌的波形，每个采样点记录一段时间内单声道混音的最小值和最大值
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioPeaks {
+    pub interval_ms: u32,
+    /// 歌曲时长，单位为秒
+    pub duration: f64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+impl AudioPeaks {
+    /// 合并相邻的采样点，得到间隔接近 `interval_ms` 的波形，不会比缓存的波形更精细
+    pub fn downsample(self, interval_ms: u32) -> Self {
+        let factor = (interval_ms / self.interval_ms.max(1)).max(1) as usize;
+        if factor == 1 {
+            return self;
+        }
+        Self {
+            interval_ms: self.interval_ms * factor as u32,
+            duration: self.duration,
+            min: self
+                .min
+                .chunks(factor)
+                .map(|x| x.iter().copied().fold(0f32, f32::min))
+                .collect(),
+            max: self
+                .max
+                .chunks(factor)
+                .map(|x| x.iter().copied().fold(0f32, f32::max))
+                .collect(),
+        }
+    }
+}
+
+struct PeaksBuilder {
+    interval_ms: u32,
+    sample_rate: u32,
+    bucket_len: usize,
+    bucket_pos: usize,
+    bucket_min: f32,
+    bucket_max: f32,
+    duration: f64,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl PeaksBuilder {
+    fn new(interval_ms: u32) -> Self {
+        Self {
+            interval_ms: interval_ms.max(1),
+            sample_rate: 0,
+            bucket_len: 1,
+            bucket_pos: 0,
+            bucket_min: 0.,
+            bucket_max: 0.,
+            duration: 0.,
+            min: Vec::new(),
+            max: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, buf: &PlanarBuffer) {
+        let rate = buf.spec().rate;
+        if rate == 0 || buf.channels() == 0 {
+            return;
+        }
+        if rate != self.sample_rate {
+            self.sample_rate = rate;
+            self.bucket_len = (rate as usize * self.interval_ms as usize / 1000).max(1);
+        }
+        let frames = buf.frames();
+        let planes = buf.planes();
+        let channels = planes.len() as f32;
+        for i in 0..frames {
+            let mixed = planes.iter().map(|x| x[i]).sum::<f32>() / channels;
+            self.bucket_min = self.bucket_min.min(mixed);
+            self.bucket_max = self.bucket_max.max(mixed);
+            self.bucket_pos += 1;
+            if self.bucket_pos >= self.bucket_len {
+                self.flush();
+            }
+        }
+        self.duration += frames as f64 / rate as f64;
+    }
+
+    fn flush(&mut self) {
+        self.min.push(self.bucket_min);
+        self.max.push(self.bucket_max);
+        self.bucket_pos = 0;
+        self.bucket_min = 0.;
+        self.bucket_max = 0.;
+    }
+
+    fn finish(mut self) -> AudioPeaks {
+        if self.bucket_pos > 0 {
+            self.flush();
+        }
+        AudioPeaks {
+            interval_ms: self.interval_ms,
+            duration: self.duration,
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+/// 解码整个音频文件，每解码出一个数据包就调用一次 `on_buffer`，耗时较长，不能在音频线程中调用
+fn decode_all(data: Vec<u8>, mut on_buffer: impl FnMut(&PlanarBuffer)) -> anyhow::Result<()> {
+    let source_stream = MediaSourceStream::new(
+        Box::new(Cursor::new(data)),
+        MediaSourceStreamOptions::default(),
+    );
+    let mut format_result = symphonia::default::get_probe()
+        .format(
+            &Default::default(),
+            source_stream,
+            &Default::default(),
+            &Default::default(),
+        )
+        .context("无法识别音频格式")?;
+    let track = format_result
+        .format
+        .default_track()
+        .context("音频文件中没有可播放的音轨")?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &Default::default())
+        .context("无法创建解码器")?;
+    let mut buf = PlanarBuffer::default();
+    loop {
+        let packet = match format_result.format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(err) => return Err(err).context("读取音频数据包失败"),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                buf.load(&decoded);
+                on_buffer(&buf);
+            }
+            Err(DecodeError::DecodeError(err)) => {
+                println!("[WARN][AT] 计算波形时跳过无法解码的数据包 {err}");
+            }
+            Err(err) => return Err(err).context("解码音频失败"),
+        }
+    }
+    Ok(())
+}
+
+/// 解码整个音频文件并计算波形
+pub fn compute_peaks(data: Vec<u8>, interval_ms: u32) -> anyhow::Result<AudioPeaks> {
+    let mut builder = PeaksBuilder::new(interval_ms);
+    decode_all(data, |buf| builder.push(buf))?;
+    Ok(builder.finish())
+}
+
+/// 解码整个音频文件，同时计算波形和综合响度，只需要解码一遍
+fn compute_peaks_and_loudness(data: Vec<u8>) -> anyhow::Result<(AudioPeaks, Option<f64>)> {
+    let mut builder = PeaksBuilder::new(PEAKS_INTERVAL_MS);
+    let mut meter = None;
+    decode_all(data, |buf| {
+        builder.push(buf);
+        if buf.channels() > 0 {
+            meter
+                .get_or_insert_with(|| LoudnessMeter::new(buf.spec().rate, buf.channels()))
+                .process(buf);
+        }
+    })?;
+    let loudness = meter.and_then(|x| x.integrated_loudness());
+    Ok((builder.finish(), loudness))
+}
+
+/// 波形缓存文件的路径，有歌曲 ID 时以 ID 命名，否则以本地文件路径的 MD5 命名
+fn peaks_file(app: &tauri::AppHandle, ncm_id: &str, local_file: &str) -> Option<PathBuf> {
+    let key = if !ncm_id.is_empty() && ncm_id.chars().all(|x| x.is_ascii_alphanumeric()) {
+        ncm_id.to_owned()
+    } else {
+        format!("local-{:x}", md5::compute(local_file))
+    };
+    app.path_resolver().app_cache_dir().map(|x| {
+        x.join("audio-cache")
+            .join("peaks")
+            .join(format!("{key}.json"))
+    })
+}
+
+fn load_peaks(path: &Path) -> Option<AudioPeaks> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|x| serde_json::from_slice(&x).ok())
+}
+
+fn save_peaks(path: &Path, peaks: &AudioPeaks) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    match serde_json::to_vec(peaks) {
+        Ok(data) => {
+            if let Err(err) = std::fs::write(path, data) {
+                println!("[WARN][AT] 无法保存波形缓存 {err}");
+            }
+        }
+        Err(err) => println!("[WARN][AT] 无法序列化波形缓存 {err}"),
+    }
+}
+
+/// 在后台线程中计算歌曲的波形并保存到缓存，完成后发送 `AudioPeaksReady` 事件
+///
+/// `measure_loudness` 为真时会在同一次解码中测量综合响度，并通过
+/// [`super::notify_loudness_measured`] 交给音频线程写入响度缓存，
+/// 这样即使歌曲没有从头到尾完整播放过也能得到响度标准化所需的数据。
+///
+/// `source` 可能是在线播放时的临时文件，切歌后会被下一首歌覆盖，
+/// 所以会先把整个文件读入内存，并在读取后检查文件大小是否发生了变化。
+pub fn spawn_peaks_job(
+    app: &tauri::AppHandle,
+    ncm_id: &str,
+    local_file: &str,
+    source: &Path,
+    measure_loudness: bool,
+) {
+    let Some(target) = peaks_file(app, ncm_id, local_file) else {
+        return;
+    };
+    if (target.is_file() && !measure_loudness)
+        || !RUNNING_JOBS.lock().unwrap().insert(target.clone())
+    {
+        return;
+    }
+    let expected_len = std::fs::metadata(source).map(|x| x.len()).ok();
+    let source = source.to_owned();
+    let ncm_id = ncm_id.to_owned();
+    let app = app.to_owned();
+    std::thread::spawn(move || {
+        let result = std::fs::read(&source)
+            .context("无法读取音频文件")
+            .and_then(|data| {
+                anyhow::ensure!(
+                    Some(data.len() as u64) == expected_len,
+                    "音频文件在读取时发生了变化"
+                );
+                let _guard = DECODE_LOCK.lock().unwrap();
+                compute_peaks_and_loudness(data)
+            });
+        match result {
+            Ok((peaks, loudness)) => {
+                if measure_loudness {
+                    match loudness {
+                        Some(loudness) => {
+                            println!("歌曲 {ncm_id} 的综合响度为 {loudness:.2} LUFS");
+                            super::notify_loudness_measured(&ncm_id, loudness);
+                        }
+                        None => println!("[WARN][AT] 歌曲 {ncm_id} 太短或太安静，无法测量响度"),
+                    }
+                }
+                save_peaks(&target, &peaks);
+                println!("歌曲 {ncm_id} 的波形已计算完成");
+                let _ = app.emit_all(
+                    "on-audio-thread-event",
+                    AudioThreadEvent::AudioPeaksReady {
+                        ncm_id: ncm_id.to_owned(),
+                    },
+                );
+            }
+            Err(err) => println!("[WARN][AT] 无法计算歌曲 {ncm_id} 的波形 {err:?}"),
+        }
+        RUNNING_JOBS.lock().unwrap().remove(&target);
+    });
+}
+
+/// 读取缓存的波形，本地文件没有缓存时会在阻塞线程池中立即计算
+///
+/// 在线播放的歌曲在下载完成前返回空，计算完成后会发送 `AudioPeaksReady` 事件。
+pub async fn get_peaks(
+    app: tauri::AppHandle,
+    ncm_id: String,
+    local_file: String,
+    interval_ms: Option<u32>,
+) -> anyhow::Result<Option<AudioPeaks>> {
+    let target = peaks_file(&app, &ncm_id, &local_file).context("无法获取缓存文件夹")?;
+    let peaks = tauri::async_runtime::spawn_blocking(move || {
+        if let Some(peaks) = load_peaks(&target) {
+            return Ok(Some(peaks));
+        }
+        if local_file.is_empty() || RUNNING_JOBS.lock().unwrap().contains(&target) {
+            return Ok(None);
+        }
+        let data = std::fs::read(&local_file).context("无法读取本地音频文件")?;
+        let _guard = DECODE_LOCK.lock().unwrap();
+        let peaks = compute_peaks(data, PEAKS_INTERVAL_MS)?;
+        save_peaks(&target, &peaks);
+        anyhow::Ok(Some(peaks))
+    })
+    .await
+    .context("波形计算线程执行出错")??;
+    Ok(peaks.map(|x| x.downsample(interval_ms.unwrap_or(PEAKS_INTERVAL_MS))))
+}
@@ -1,28 +1,40 @@
 use std::{
-    io::{ErrorKind, Read, Write},
+    collections::VecDeque,
+    io::{ErrorKind, Write},
     path::PathBuf,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex, MutexGuard,
-    },
-    thread::{spawn, JoinHandle},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
 };
 
-use attohttpc::{RequestBuilder, Session};
-use cpal::traits::StreamTrait;
-use serde::de::DeserializeOwned;
+use anyhow::Context;
 use symphonia::core::errors::Error as DecodeError;
 use symphonia::core::{
     codecs::{CodecRegistry, Decoder},
+    formats::{SeekMode, SeekTo},
     io::{MediaSourceStream, MediaSourceStreamOptions},
     probe::{Probe, ProbeResult},
-    units::TimeBase,
+    units::{Time, TimeBase},
 };
-use tauri::Manager;
+use tauri::{async_runtime::JoinHandle, Manager};
+use tokio_util::sync::CancellationToken;
 
-use crate::audio::{AudioThreadEvent, NCMResponse, NCMSongResponse};
+use crate::audio::AudioThreadEvent;
+use crate::ncm_api::{NCMApi, NCMApiError};
 
-use super::{output::AudioOutput, AudioThreadMessage, SongData};
+use super::{
+    buffer::PlanarBuffer,
+    dsp::{DspChain, DspSettings},
+    equalizer::EqProfile,
+    fade::{DEFAULT_FADE_DURATION, MAX_FADE_DURATION},
+    looping::{LoopCrossfade, LoopRange},
+    loudness::{LoudnessCache, ReplayGainInfo, REFERENCE_LOUDNESS},
+    output::AudioOutput,
+    sleep_timer::{SleepTimer, SleepTimerStatus},
+    stretch::TimeStretcher,
+    visualizer::Visualizer,
+    AudioError, AudioResponse, AudioResult, AudioThreadMessage, NormalizationMode,
+    PlaybackRateMode, ResamplerQuality, SongData,
+};
 
 #[derive(Default, Clone, PartialEq)]
 pub enum DownloadStatus {
@@ -48,122 +60,331 @@ impl DownloadStatus {
     }
 }
 
+/// 默认每秒发送播放位置事件的次数
+const DEFAULT_POSITION_UPDATE_RATE: f64 = 30.;
+/// 最多保留的位置标记数量，足够覆盖环形缓冲区和设备延迟
+const MAX_POSITION_MARKERS: usize = 256;
+
+/// 记录写入输出的某一时刻与歌曲位置之间的对应关系
+struct PositionMarker {
+    /// 写入完成时输出的累计写入时长，单位为秒
+    written: f64,
+    /// 此时已写入音频的末尾对应的歌曲位置，单位为秒
+    position: f64,
+}
+
+/// 播放器所属的应用，用来向前端发送事件、发起请求和存放缓存
+enum PlayerHost {
+    App(tauri::AppHandle),
+    /// 测试时不连接前端，只记录发出的事件，也不能在线播放
+    #[cfg(test)]
+    Mock {
+        events: Arc<Mutex<Vec<AudioThreadEvent>>>,
+    },
+}
+
 pub struct AudioPlayer {
-    app: tauri::AppHandle,
+    host: PlayerHost,
     codecs: &'static CodecRegistry,
     probe: &'static Probe,
     player: Box<dyn AudioOutput>,
+    visualizer: Visualizer,
+    output_device_name: String,
     volume: f64,
+    preamp: f64,
+    resampler_quality: ResamplerQuality,
+    playback_rate: f64,
+    playback_rate_mode: PlaybackRateMode,
+    time_stretcher: TimeStretcher,
+    fade_duration: Duration,
+    sleep_timer: Option<SleepTimer>,
+    /// 睡眠定时器结束前逐渐降低音量时，当前应用在音量上的系数
+    sleep_fade_factor: f64,
+    loop_range: Option<LoopRange>,
+    loop_crossfade: LoopCrossfade,
+    normalization: NormalizationMode,
+    replay_gain: ReplayGainInfo,
+    loudness_cache: LoudnessCache,
+    dsp_chain: DspChain,
+    dsp_settings: DspSettings,
     is_playing: bool,
-    session: Session,
     audio_current_tmp_file: PathBuf,
+    /// 当前歌曲是否已经开始在后台计算波形
+    peaks_requested: bool,
+    /// 已经读到了下载中文件的末尾，需要等待更多数据
+    waiting_for_download: bool,
 
     playlist: Vec<SongData>,
     current_play_index: usize,
     current_song: SongData,
-    stop_download_atom: Arc<AtomicBool>,
-
-    download_thread_handle: Option<JoinHandle<()>>,
+    /// 取消后正在进行的请求和下载会立即中断
+    download_cancel: CancellationToken,
+    download_task: Option<JoinHandle<()>>,
     download_state: Arc<Mutex<DownloadStatus>>,
 
     format_result: Option<ProbeResult>,
     decoder: Option<Box<dyn Decoder>>,
+    decoded: PlanarBuffer,
+    /// 精确跳转的目标位置，解码出的位于该位置之前的音频会被丢弃
+    seek_target: Option<f64>,
     timebase: TimeBase,
+    /// 实际听到的播放位置，根据输出已经播放的帧数推算
     play_position: f64,
     play_duration: f64,
+    position_markers: VecDeque<PositionMarker>,
+    position_update_rate: f64,
+    last_position_update: Instant,
 }
 
 impl AudioPlayer {
     pub fn new(app: tauri::AppHandle) -> Self {
-        let codecs = symphonia::default::get_codecs();
-        let probe = symphonia::default::get_probe();
-        let player = super::output::init_audio_player("");
         let audio_cache_dir = app
             .path_resolver()
             .app_cache_dir()
             .unwrap()
             .join("audio-cache");
-        let mut session = attohttpc::Session::new();
-        session.header("origin", "orpheus://orpheus");
-        session.header("user-agent", "Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Safari/537.36 Chrome/91.0.4472.164 NeteaseMusicDesktop/2.10.7.200791");
+        Self::with_host(PlayerHost::App(app), audio_cache_dir, String::new())
+    }
+
+    /// 创建不连接前端的播放器，返回的列表会记录播放器发出的所有事件
+    #[cfg(test)]
+    fn mock(
+        output_device_name: &str,
+        audio_cache_dir: PathBuf,
+    ) -> (Self, Arc<Mutex<Vec<AudioThreadEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let host = PlayerHost::Mock {
+            events: events.clone(),
+        };
+        let player = Self::with_host(host, audio_cache_dir, output_device_name.to_owned());
+        (player, events)
+    }
+
+    fn with_host(host: PlayerHost, audio_cache_dir: PathBuf, output_device_name: String) -> Self {
+        let codecs = symphonia::default::get_codecs();
+        let probe = symphonia::default::get_probe();
+        let visualizer = Visualizer::default();
+        let player = super::output::init_audio_player(&output_device_name, visualizer.tap());
         let audio_current_tmp_file = audio_cache_dir.join("audio_tmp");
+        let loudness_cache = LoudnessCache::load(audio_cache_dir.join("loudness-cache.json"));
         let _ = std::fs::create_dir_all(audio_cache_dir);
 
         let playlist = Vec::<SongData>::with_capacity(4096);
         let current_song = SongData::default();
-        let stop_download_atom = Arc::new(AtomicBool::new(false));
         let download_state = Arc::new(Mutex::new(DownloadStatus::Idle));
-        let download_thread_handle: Option<JoinHandle<()>> = None;
         let format_result: Option<ProbeResult> = None;
         let decoder: Option<Box<dyn Decoder>> = None;
         let timebase = TimeBase::default();
 
         Self {
-            app,
+            host,
             codecs,
             probe,
             player,
+            visualizer,
+            output_device_name,
             volume: 0.5,
-            session,
+            preamp: 0.,
+            resampler_quality: ResamplerQuality::default(),
+            playback_rate: 1.,
+            playback_rate_mode: PlaybackRateMode::default(),
+            time_stretcher: TimeStretcher::default(),
+            fade_duration: DEFAULT_FADE_DURATION,
+            sleep_timer: None,
+            sleep_fade_factor: 1.,
+            loop_range: None,
+            loop_crossfade: LoopCrossfade::default(),
+            normalization: NormalizationMode::default(),
+            replay_gain: ReplayGainInfo::default(),
+            loudness_cache,
+            dsp_chain: DspChain::default(),
+            dsp_settings: DspSettings::default(),
             audio_current_tmp_file,
+            peaks_requested: false,
+            waiting_for_download: false,
             playlist,
             current_song,
-            stop_download_atom,
+            download_cancel: CancellationToken::new(),
             download_state,
-            download_thread_handle,
+            download_task: None,
             format_result,
             decoder,
+            decoded: PlanarBuffer::default(),
+            seek_target: None,
             timebase,
             is_playing: false,
             current_play_index: 0,
             play_position: 0.,
+            position_markers: VecDeque::new(),
+            position_update_rate: DEFAULT_POSITION_UPDATE_RATE,
+            last_position_update: Instant::now(),
             play_duration: 0.,
         }
     }
 
+    /// 所属的 Tauri 应用，测试时为空
+    fn app(&self) -> Option<&tauri::AppHandle> {
+        match &self.host {
+            PlayerHost::App(app) => Some(app),
+            #[cfg(test)]
+            PlayerHost::Mock { .. } => None,
+        }
+    }
+
+    /// 向前端发送音频线程事件
+    fn emit_event(&self, event: AudioThreadEvent) {
+        match &self.host {
+            PlayerHost::App(app) => {
+                let _ = app.emit_all("on-audio-thread-event", event);
+            }
+            #[cfg(test)]
+            PlayerHost::Mock { events } => events.lock().unwrap().push(event),
+        }
+    }
+
     pub fn is_playing(&self) -> bool {
         self.is_playing
     }
 
-    pub fn process_message(&mut self, msg: AudioThreadMessage) {
-        match &msg {
-            AudioThreadMessage::SetCookie { cookie, .. } => {
-                self.session.header("cookie", cookie);
-                println!("已设置 Cookie 头为 {cookie}");
-                msg.ret(&self.app, None::<()>).unwrap();
+    /// 最多等待多久就需要再次调用 [`Self::process_audio`]，为空时只需等待消息和下载进度通知
+    ///
+    /// 同时会推进输出设备暂停和清空时的淡出，淡出完成之前会定时唤醒音频线程
+    pub fn next_wakeup(&mut self) -> Option<Duration> {
+        let fade = self.player.poll_fade();
+        let playback = self.playback_wakeup();
+        match (fade, playback) {
+            (Some(fade), Some(playback)) => Some(fade.min(playback)),
+            (fade, playback) => fade.or(playback),
+        }
+    }
+
+    fn playback_wakeup(&mut self) -> Option<Duration> {
+        if !self.is_playing {
+            return None;
+        }
+        // 播放位置和睡眠定时器需要按固定频率更新
+        let tick = Duration::from_secs_f64(1. / self.position_update_rate);
+        if self.format_result.is_none() {
+            return match *self.get_download_state() {
+                DownloadStatus::QueryingUrl | DownloadStatus::DownloadingAudio(_) => {
+                    self.sleep_timer.is_some().then_some(tick)
+                }
+                _ => Some(Duration::ZERO),
+            };
+        }
+        if self.decoder.is_none() {
+            // 下一次处理时会创建解码器，失败时会跳过这首歌
+            return Some(Duration::ZERO);
+        }
+        let tick = tick.saturating_sub(self.last_position_update.elapsed());
+        if self.waiting_for_download {
+            // 下载线程写入新的数据后会发送 `DownloadProgress` 唤醒音频线程，
+            // 在此之前只需要在输出还在播放缓冲的音频时更新播放位置
+            let is_draining = self.player.written_duration() > self.player.played_duration();
+            return (is_draining || self.sleep_timer.is_some())
+                .then_some(tick.max(Duration::from_millis(1)));
+        }
+        Some(self.player.poll_write().min(tick))
+    }
+
+    /// 重新初始化输出设备，并恢复之前的各项输出设置
+    fn reinit_player(&mut self) {
+        self.player =
+            super::output::init_audio_player(&self.output_device_name, self.visualizer.tap());
+        // 新的输出设备从零开始计数，旧的位置标记已经无效
+        self.position_markers.clear();
+        self.player.set_fade_duration(self.fade_duration);
+        self.apply_volume();
+        self.player.set_preamp(self.preamp);
+        self.player.set_resampler_quality(self.resampler_quality);
+        self.apply_playback_rate();
+    }
+
+    /// 将用户设置的音量乘上睡眠定时器的淡出系数后交给输出设备
+    fn apply_volume(&mut self) {
+        self.player.set_volume(self.volume * self.sleep_fade_factor);
+    }
+
+    /// 根据播放速度的调节方式，把速度交给输出设备的重采样器或时间伸缩器其中之一
+    fn apply_playback_rate(&mut self) {
+        match self.playback_rate_mode {
+            PlaybackRateMode::Resample => {
+                self.time_stretcher.set_rate(1.);
+                self.player.set_speed(self.playback_rate);
             }
-            AudioThreadMessage::ResumeAudio { .. } => {
+            PlaybackRateMode::TimeStretch => {
+                self.player.set_speed(1.);
+                self.time_stretcher.set_rate(self.playback_rate);
+            }
+        }
+    }
+
+    /// 开始输出，失败时重新初始化输出设备后再试一次
+    fn start_output(&mut self) -> Result<(), AudioError> {
+        if self.player.play().is_err() {
+            self.reinit_player();
+            self.player.play().map_err(|err| AudioError::OutputDevice {
+                error: err.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 停止当前歌曲并开始输出，之后由 `process_audio` 加载 `current_play_index` 的下一首歌
+    fn restart_playback(&mut self) -> AudioResult {
+        self.player.clear();
+        self.format_result = None;
+        self.decoder = None;
+        self.is_playing = true;
+        self.set_download_state(DownloadStatus::Idle);
+        self.start_output()?;
+        let index = if self.current_play_index + 1 >= self.playlist.len() {
+            0
+        } else {
+            self.current_play_index + 1
+        };
+        Ok(AudioResponse::SongIndex {
+            index,
+            ncm_id: self.playlist[index].ncm_id.to_owned(),
+        })
+    }
+
+    pub fn process_message(&mut self, msg: AudioThreadMessage) -> AudioResult {
+        match msg {
+            AudioThreadMessage::ResumeAudio {} => {
+                if self.sleep_timer.as_ref().is_some_and(|x| x.is_expired()) {
+                    // 暂停期间睡眠定时器已经到期，不应该在继续播放后立刻又暂停
+                    self.sleep_timer = None;
+                }
                 self.is_playing = true;
                 println!("开始继续播放歌曲！");
-                if self.player.stream().play().is_err() {
-                    self.player = super::output::init_audio_player("");
-                }
-                let _ = self.app.emit_all(
-                    "on-audio-thread-event",
-                    AudioThreadEvent::PlayStatus {
-                        is_playing: self.is_playing,
-                    },
-                );
-                msg.ret(&self.app, None::<()>).unwrap();
+                let result = self.start_output();
+                self.emit_event(AudioThreadEvent::PlayStatus {
+                    is_playing: self.is_playing,
+                });
+                result?;
+                Ok(AudioResponse::PlayStatus {
+                    is_playing: self.is_playing,
+                })
             }
-            AudioThreadMessage::PauseAudio { .. } => {
+            AudioThreadMessage::PauseAudio {} => {
                 self.is_playing = false;
-                if self.player.stream().pause().is_err() {
-                    self.player = super::output::init_audio_player("");
+                if self.player.pause().is_err() {
+                    self.reinit_player();
                 }
+                self.update_play_position();
                 println!("播放已暂停！");
-                let _ = self.app.emit_all(
-                    "on-audio-thread-event",
-                    AudioThreadEvent::PlayStatus {
-                        is_playing: self.is_playing,
-                    },
-                );
-                msg.ret(&self.app, None::<()>).unwrap();
+                self.emit_event(AudioThreadEvent::PlayStatus {
+                    is_playing: self.is_playing,
+                });
+                Ok(AudioResponse::PlayStatus {
+                    is_playing: self.is_playing,
+                })
             }
-            AudioThreadMessage::PrevSong { .. } => {
-                self.format_result = None;
-                self.decoder = None;
-
+            AudioThreadMessage::PrevSong {} => {
+                if self.playlist.is_empty() {
+                    return Err(AudioError::EmptyPlaylist);
+                }
                 if self.playlist.len() > 2 {
                     if self.current_play_index == 1 {
                         self.current_play_index = self.playlist.len();
@@ -173,82 +394,336 @@ impl AudioPlayer {
                         self.current_play_index -= 2;
                     }
                 }
-
-                self.is_playing = true;
-                if self.player.stream().play().is_err() {
-                    self.player = super::output::init_audio_player("");
-                }
                 println!("播放上一首歌曲！");
-                self.set_download_state(DownloadStatus::Idle);
-                msg.ret(&self.app, None::<()>).unwrap();
+                self.restart_playback()
             }
-            AudioThreadMessage::NextSong { .. } => {
-                self.format_result = None;
-                self.decoder = None;
-                self.is_playing = true;
-                if self.player.stream().play().is_err() {
-                    self.player = super::output::init_audio_player("");
+            AudioThreadMessage::NextSong {} => {
+                if self.playlist.is_empty() {
+                    return Err(AudioError::EmptyPlaylist);
                 }
                 println!("播放下一首歌曲！");
-                self.set_download_state(DownloadStatus::Idle);
-                msg.ret(&self.app, None::<()>).unwrap();
+                self.restart_playback()
             }
-            AudioThreadMessage::JumpToSong { song_index, .. } => {
-                self.format_result = None;
-                self.decoder = None;
-                self.is_playing = true;
-                if *song_index == 0 {
+            AudioThreadMessage::JumpToSong { song_index } => {
+                if song_index >= self.playlist.len() {
+                    return Err(AudioError::InvalidSongIndex {
+                        index: song_index,
+                        len: self.playlist.len(),
+                    });
+                }
+                if song_index == 0 {
                     self.current_play_index = self.playlist.len();
                 } else {
-                    self.current_play_index = *song_index - 1;
-                }
-                if self.player.stream().play().is_err() {
-                    self.player = super::output::init_audio_player("");
+                    self.current_play_index = song_index - 1;
                 }
-                println!("播放第 {} 首歌曲！", *song_index + 1);
-                self.set_download_state(DownloadStatus::Idle);
-                msg.ret(&self.app, None::<()>).unwrap();
-            }
-            AudioThreadMessage::SetPlaylist { songs, .. } => {
-                self.playlist = songs.to_owned();
-                println!("已设置播放列表，歌曲数量为 {}", songs.len());
-                if let Some(song) = self
+                println!("播放第 {} 首歌曲！", song_index + 1);
+                self.restart_playback()
+            }
+            AudioThreadMessage::SetPlaylist { songs } => {
+                self.playlist = songs;
+                println!("已设置播放列表，歌曲数量为 {}", self.playlist.len());
+                let current_index = self
                     .playlist
                     .iter()
-                    .enumerate()
-                    .find(|x| x.1.ncm_id == self.current_song.ncm_id)
-                {
-                    self.current_play_index = song.0;
-                } else {
-                    self.current_play_index = self.playlist.len();
-                }
-                msg.ret(&self.app, None::<()>).unwrap();
+                    .position(|x| x.ncm_id == self.current_song.ncm_id);
+                self.current_play_index = current_index.unwrap_or(self.playlist.len());
+                Ok(AudioResponse::Playlist {
+                    len: self.playlist.len(),
+                    current_index,
+                })
             }
             AudioThreadMessage::SyncStatus => {
                 self.send_sync_status();
+                Ok(AudioResponse::Done)
             }
-            AudioThreadMessage::SetVolume { volume, .. } => {
+            AudioThreadMessage::SetVolume { volume } => {
                 self.volume = volume.clamp(0., 1.);
-                self.player.set_volume(self.volume);
-                msg.ret(&self.app, None::<()>).unwrap();
+                self.apply_volume();
+                Ok(AudioResponse::Volume {
+                    volume: self.volume,
+                })
+            }
+            AudioThreadMessage::SetPreamp { preamp } => {
+                self.player.set_preamp(preamp);
+                self.preamp = self.player.preamp();
+                println!("已设置前级增益为 {:.1}dB", self.preamp);
+                Ok(AudioResponse::Preamp {
+                    preamp: self.preamp,
+                })
+            }
+            AudioThreadMessage::SetNormalization { mode } => {
+                self.normalization = mode;
+                self.update_normalization_gain();
+                println!(
+                    "已设置响度标准化模式为 {mode:?}，当前增益 {:.2}dB",
+                    self.dsp_settings.normalization_gain
+                );
+                Ok(AudioResponse::Normalization {
+                    mode,
+                    gain: self.dsp_settings.normalization_gain,
+                })
+            }
+            AudioThreadMessage::SetEqualizer { profile } => {
+                println!("已设置均衡器，共 {} 个频段", profile.bands.len());
+                self.set_equalizer(profile)
+            }
+            AudioThreadMessage::SetEqPreset { preset } => {
+                let Some(profile) = EqProfile::preset(&preset) else {
+                    return Err(AudioError::UnknownEqPreset { preset });
+                };
+                println!("已切换均衡器预设为 {preset}");
+                self.set_equalizer(profile)
+            }
+            AudioThreadMessage::ImportEqProfile { profile } => {
+                let profile = EqProfile::parse_equalizer_apo(&profile).map_err(|err| {
+                    AudioError::InvalidEqProfile {
+                        error: format!("{err:?}"),
+                    }
+                })?;
+                println!("已导入均衡器参数，共 {} 个频段", profile.bands.len());
+                self.set_equalizer(profile)
+            }
+            AudioThreadMessage::SetBalance { balance } => {
+                self.dsp_settings.balance = balance.clamp(-1., 1.);
+                self.dsp_chain.update(&self.dsp_settings);
+                Ok(AudioResponse::Balance {
+                    balance: self.dsp_settings.balance,
+                })
+            }
+            AudioThreadMessage::SetDspChain { stages } => {
+                self.dsp_chain.set_stages(&stages, &self.dsp_settings);
+                println!(
+                    "已设置音效处理链为 {:?}，延迟 {} 帧",
+                    self.dsp_chain.kinds(),
+                    self.dsp_chain.latency()
+                );
+                Ok(AudioResponse::DspChain {
+                    stages: self.dsp_chain.kinds(),
+                    latency: self.dsp_chain.latency(),
+                })
+            }
+            AudioThreadMessage::SetResamplerQuality { quality } => {
+                self.resampler_quality = quality;
+                self.player.set_resampler_quality(self.resampler_quality);
+                println!("已设置重采样质量为 {quality:?}");
+                Ok(AudioResponse::ResamplerQuality { quality })
+            }
+            AudioThreadMessage::SetAudioOutput { name } => {
+                if !super::output::output_device_exists(&name) {
+                    return Err(AudioError::OutputDeviceNotFound { name });
+                }
+                self.output_device_name = name;
+                self.reinit_player();
+                if self.is_playing {
+                    self.player.play().map_err(|err| AudioError::OutputDevice {
+                        error: err.to_string(),
+                    })?;
+                }
+                println!("已切换输出设备为 {}", self.output_device_name);
+                Ok(AudioResponse::AudioOutput {
+                    name: self.output_device_name.to_owned(),
+                })
+            }
+            AudioThreadMessage::SetPlaybackRate { rate, mode } => {
+                self.playback_rate = rate.clamp(0.5, 2.);
+                self.playback_rate_mode = mode;
+                self.apply_playback_rate();
+                println!("已设置播放速度为 {:.2}x ({mode:?})", self.playback_rate);
+                Ok(AudioResponse::PlaybackRate {
+                    rate: self.playback_rate,
+                    mode,
+                })
+            }
+            AudioThreadMessage::SeekAudio { position } => {
+                let position = position.as_secs_f64();
+                if self.decoder.is_none() {
+                    return Err(AudioError::NoTrackLoaded);
+                }
+                // 跳转失败时保留已经缓冲的音频，继续播放原来的位置
+                self.seek_to(position)?;
+                self.player.clear();
+                self.dsp_chain.reset();
+                self.time_stretcher.reset();
+                self.loop_crossfade.clear();
+                self.position_markers.clear();
+                self.play_position = position;
+                println!("已跳转到 {position:.2}s");
+                Ok(AudioResponse::Position { position })
+            }
+            AudioThreadMessage::SetLoop { start, end } => {
+                let start = start.as_secs_f64();
+                let end = end.as_secs_f64();
+                if start >= end {
+                    return Err(AudioError::InvalidLoopRange { start, end });
+                }
+                self.loop_range = Some(LoopRange { start, end });
+                println!("已设置循环区间 {start:.2}s - {end:.2}s");
+                Ok(AudioResponse::Loop {
+                    range: self.loop_range,
+                })
+            }
+            AudioThreadMessage::ClearLoop {} => {
+                self.loop_range = None;
+                println!("已取消循环区间");
+                Ok(AudioResponse::Loop { range: None })
+            }
+            AudioThreadMessage::SetFadeDuration { duration } => {
+                self.fade_duration = duration.min(MAX_FADE_DURATION);
+                self.player.set_fade_duration(self.fade_duration);
+                println!("已设置淡入淡出时长为 {}ms", self.fade_duration.as_millis());
+                Ok(AudioResponse::FadeDuration {
+                    duration: self.fade_duration.as_secs_f64(),
+                })
+            }
+            AudioThreadMessage::SetSleepTimer { after } => {
+                self.sleep_timer = after.map(SleepTimer::new);
+                self.sleep_fade_factor = 1.;
+                self.apply_volume();
+                println!("已设置睡眠定时器为 {after:?}");
+                Ok(AudioResponse::SleepTimer {
+                    status: self.sleep_timer_status(),
+                })
+            }
+            AudioThreadMessage::SetPositionUpdateRate { rate } => {
+                self.position_update_rate = rate.clamp(1., 120.);
+                println!(
+                    "已设置播放位置更新频率为 {:.0}Hz",
+                    self.position_update_rate
+                );
+                Ok(AudioResponse::PositionUpdateRate {
+                    rate: self.position_update_rate,
+                })
+            }
+            AudioThreadMessage::SetVisualizer { config } => {
+                match &self.host {
+                    PlayerHost::App(app) => self.visualizer.set_config(app, config),
+                    #[cfg(test)]
+                    PlayerHost::Mock { .. } => {}
+                }
+                Ok(AudioResponse::Visualizer {
+                    config: self.visualizer.config(),
+                })
             }
-            other => dbg!(other).ret(&self.app, None::<()>).unwrap(),
         }
     }
 
+    fn set_equalizer(&mut self, profile: EqProfile) -> AudioResult {
+        self.dsp_settings.equalizer = profile;
+        self.dsp_chain.update(&self.dsp_settings);
+        Ok(AudioResponse::Equalizer {
+            profile: self.dsp_settings.equalizer.to_owned(),
+        })
+    }
+
     fn send_sync_status(&self) {
-        let _ = self.app.emit_all(
-            "on-audio-thread-event",
-            AudioThreadEvent::SyncStatus {
-                ncm_id: self.current_song.ncm_id.to_owned(),
-                is_playing: self.is_playing,
-                duration: self.play_duration,
-                position: self.play_position,
-                volume: self.volume,
-                load_position: self.download_state.lock().unwrap().get_download_progress(),
-                playlist: self.playlist.to_owned(),
-            },
-        );
+        self.emit_event(AudioThreadEvent::SyncStatus {
+            ncm_id: self.current_song.ncm_id.to_owned(),
+            is_playing: self.is_playing,
+            duration: self.play_duration,
+            position: self.play_position,
+            volume: self.volume,
+            preamp: self.preamp,
+            normalization: self.normalization,
+            normalization_gain: self.dsp_settings.normalization_gain,
+            equalizer: self.dsp_settings.equalizer.to_owned(),
+            balance: self.dsp_settings.balance,
+            dsp_chain: self.dsp_chain.kinds(),
+            resampler_quality: self.resampler_quality,
+            playback_rate: self.playback_rate,
+            playback_rate_mode: self.playback_rate_mode,
+            loop_range: self.loop_range,
+            fade_duration: self.fade_duration.as_secs_f64(),
+            sleep_timer: self.sleep_timer_status(),
+            position_update_rate: self.position_update_rate,
+            visualizer: self.visualizer.config(),
+            load_position: self.download_state.lock().unwrap().get_download_progress(),
+            playlist: self.playlist.to_owned(),
+        });
+    }
+
+    /// 当前歌曲的剩余秒数，歌曲时长未知时为空
+    fn track_remaining(&self) -> Option<f64> {
+        (self.play_duration > 0.).then_some(self.play_duration - self.play_position)
+    }
+
+    fn sleep_timer_status(&self) -> Option<SleepTimerStatus> {
+        self.sleep_timer
+            .as_ref()
+            .map(|x| x.status(self.track_remaining()))
+    }
+
+    /// 检查睡眠定时器是否到期，并在到期前逐渐降低音量
+    fn update_sleep_timer(&mut self) {
+        let Some(timer) = self.sleep_timer.as_ref() else {
+            return;
+        };
+        if timer.is_expired() {
+            self.finish_sleep_timer();
+            return;
+        }
+        let factor = timer.fade_factor(self.track_remaining());
+        if (factor - self.sleep_fade_factor).abs() > 0.001 {
+            self.sleep_fade_factor = factor;
+            self.apply_volume();
+        }
+    }
+
+    /// 睡眠定时器结束，暂停播放并恢复原来的音量
+    fn finish_sleep_timer(&mut self) {
+        println!("睡眠定时器已到期，暂停播放");
+        self.sleep_timer = None;
+        self.is_playing = false;
+        if self.player.pause().is_err() {
+            self.reinit_player();
+        }
+        self.sleep_fade_factor = 1.;
+        self.apply_volume();
+        self.emit_event(AudioThreadEvent::PlayStatus {
+            is_playing: self.is_playing,
+        });
+    }
+
+    /// 记录刚写入输出的音频对应的歌曲位置，`input_end` 为写入的解码数据末尾的位置
+    fn push_position_marker(&mut self, input_end: f64) {
+        // 还积压在时间伸缩器和重采样器中的音频尚未写入输出，需要从位置中扣除
+        let mut pending = self.player.pending_input().as_secs_f64();
+        if self.playback_rate_mode == PlaybackRateMode::TimeStretch {
+            // 重采样器的输入已经经过时间伸缩，需要换算回歌曲本身的时间
+            pending *= self.playback_rate;
+        }
+        pending += self.time_stretcher.pending_duration().as_secs_f64();
+        self.position_markers.push_back(PositionMarker {
+            written: self.player.written_duration().as_secs_f64(),
+            position: input_end - pending,
+        });
+        if self.position_markers.len() > MAX_POSITION_MARKERS {
+            self.position_markers.pop_front();
+        }
+    }
+
+    /// 根据输出已经播放的时长和位置标记推算实际听到的播放位置
+    fn update_play_position(&mut self) {
+        let played = self.player.played_duration().as_secs_f64();
+        while self.position_markers.len() > 1 && self.position_markers[0].written <= played {
+            self.position_markers.pop_front();
+        }
+        if let Some(marker) = self.position_markers.front() {
+            let unplayed = (marker.written - played).max(0.) * self.playback_rate;
+            self.play_position = (marker.position - unplayed).max(0.);
+        }
+    }
+
+    /// 按设置的频率发送播放位置事件
+    fn emit_play_position(&mut self) {
+        let interval = Duration::from_secs_f64(1. / self.position_update_rate);
+        if self.last_position_update.elapsed() < interval {
+            return;
+        }
+        self.last_position_update = Instant::now();
+        self.update_play_position();
+        self.emit_event(AudioThreadEvent::PlayPosition {
+            position: self.play_position,
+        });
     }
 
     fn get_download_state(&self) -> MutexGuard<'_, DownloadStatus> {
@@ -261,28 +736,76 @@ impl AudioPlayer {
 
     pub fn process_audio(&mut self) {
         let mut is_song_finished = false;
+        let mut is_song_completed = false;
+        let mut is_new_track = false;
+        let mut load_error = None;
+        let mut loop_to = None;
+        if self.is_playing && self.decoder.is_some() && self.player.is_dead() {
+            println!("[WARN][AT] 现有输出设备已断开，正在重新初始化播放器");
+            self.reinit_player();
+            if let Err(err) = self.start_output() {
+                println!("[WARN][AT] 无法恢复输出，已暂停播放 {err}");
+                self.is_playing = false;
+                self.emit_event(AudioThreadEvent::OutputError {
+                    error: err.to_string(),
+                });
+                self.emit_event(AudioThreadEvent::PlayStatus {
+                    is_playing: self.is_playing,
+                });
+                return;
+            }
+        }
+        if self.is_playing {
+            self.update_sleep_timer();
+        }
+        if self.is_playing && self.decoder.is_some() {
+            self.emit_play_position();
+            // 输出缓冲区还没有腾出空间时不解码，等下次唤醒
+            if self.player.poll_write() > Duration::ZERO {
+                return;
+            }
+        }
         if let Some(format_result) = self.format_result.as_mut() {
             if !self.is_playing {
                 return;
             }
             if let Some(decoder) = self.decoder.as_mut() {
+                self.waiting_for_download = false;
                 match format_result.format.next_packet() {
                     Ok(packet) => match decoder.decode(&packet) {
                         Ok(buf) => {
+                            self.decoded.load(&buf);
+                            // 播放位置始终以音频本身的时间为准，不受播放速度影响
                             let time = self.timebase.calc_time(packet.ts);
-                            self.play_position = time.seconds as f64 + time.frac;
-                            let _ = self.app.emit_all(
-                                "on-audio-thread-event",
-                                AudioThreadEvent::PlayPosition {
-                                    position: self.play_position,
-                                },
-                            );
-                            if self.player.is_dead() {
-                                println!("[WARN][AT] 现有输出设备已断开，正在重新初始化播放器");
-                                self.player = super::output::init_audio_player("");
-                                self.player.stream().play().unwrap();
+                            let mut packet_time = time.seconds as f64 + time.frac;
+                            let rate = self.decoded.spec().rate as f64;
+                            if let Some(target) = self.seek_target {
+                                let skip = ((target - packet_time) * rate).round().max(0.) as usize;
+                                if skip < self.decoded.frames() {
+                                    self.seek_target = None;
+                                    packet_time = packet_time.max(target);
+                                }
+                                self.decoded.discard_front(skip);
+                            }
+                            if self.decoded.frames() > 0 {
+                                self.loop_crossfade.apply(&mut self.decoded);
+                                if let Some(range) = self.loop_range {
+                                    let packet_end =
+                                        packet_time + self.decoded.frames() as f64 / rate;
+                                    if packet_end >= range.end {
+                                        let keep =
+                                            ((range.end - packet_time) * rate).round().max(0.);
+                                        self.loop_crossfade
+                                            .capture(&mut self.decoded, keep as usize);
+                                        loop_to = Some(range.start);
+                                    }
+                                }
+                                let input_end = packet_time + self.decoded.frames() as f64 / rate;
+                                self.dsp_chain.process(&mut self.decoded);
+                                self.time_stretcher.process(&mut self.decoded);
+                                self.player.write(&self.decoded);
+                                self.push_position_marker(input_end);
                             }
-                            self.player.write(buf);
                         }
                         Err(err) => {
                             println!("[WARN][AT] 解码器解码出错 {err}");
@@ -291,7 +814,15 @@ impl AudioPlayer {
                     Err(DecodeError::IoError(err)) => match err.kind() {
                         ErrorKind::UnexpectedEof => {
                             if self.get_download_state().get_download_progress() == 1. {
-                                is_song_finished = true;
+                                if let Some(range) = self.loop_range {
+                                    // 循环终点超出了歌曲长度
+                                    loop_to = Some(range.start);
+                                } else {
+                                    is_song_finished = true;
+                                    is_song_completed = true;
+                                }
+                            } else {
+                                self.waiting_for_download = true;
                             }
                         }
                         _ => {
@@ -302,30 +833,28 @@ impl AudioPlayer {
                         is_song_finished = true;
                     }
                 }
+            } else if let Some(track) = format_result.format.default_track() {
+                match self.codecs.make(&track.codec_params, &Default::default()) {
+                    Ok(decoder) => {
+                        is_new_track = true;
+                        self.decoder = Some(decoder);
+                        self.timebase = track.codec_params.time_base.unwrap_or_default();
+                        let duration = self
+                            .timebase
+                            .calc_time(track.codec_params.n_frames.unwrap_or_default());
+                        self.play_duration = duration.seconds as f64 + duration.frac;
+                        self.emit_event(AudioThreadEvent::LoadAudio {
+                            ncm_id: self.current_song.ncm_id.to_owned(),
+                            duration: self.play_duration,
+                        });
+                        self.emit_event(AudioThreadEvent::PlayStatus {
+                            is_playing: self.is_playing,
+                        });
+                    }
+                    Err(err) => load_error = Some(format!("无法创建解码器 {err}")),
+                }
             } else {
-                let track = format_result.format.default_track().unwrap();
-                self.timebase = track.codec_params.time_base.unwrap_or_default();
-                self.decoder = self
-                    .codecs
-                    .make(&track.codec_params, &Default::default())
-                    .ok();
-                let duration = self
-                    .timebase
-                    .calc_time(track.codec_params.n_frames.unwrap_or_default());
-                self.play_duration = duration.seconds as f64 + duration.frac;
-                let _ = self.app.emit_all(
-                    "on-audio-thread-event",
-                    AudioThreadEvent::LoadAudio {
-                        ncm_id: self.current_song.ncm_id.to_owned(),
-                        duration: self.play_duration,
-                    },
-                );
-                let _ = self.app.emit_all(
-                    "on-audio-thread-event",
-                    AudioThreadEvent::PlayStatus {
-                        is_playing: self.is_playing,
-                    },
-                );
+                load_error = Some("音频文件中没有可播放的音轨".to_owned());
             }
         } else {
             let download_state = self.download_state.clone();
@@ -336,24 +865,22 @@ impl AudioPlayer {
                     if self.playlist.is_empty() {
                         self.is_playing = false;
                     } else {
-                        // 如果存在则中断正在流式播放的歌曲下载线程
-                        self.take_and_wait_thread();
+                        // 如果存在则中断正在流式播放的歌曲下载任务
+                        self.cancel_download_task();
                         // 选歌
                         self.current_play_index += 1;
                         if self.current_play_index >= self.playlist.len() {
                             self.current_play_index = 0;
                         }
                         self.current_song = self.playlist[self.current_play_index].to_owned();
+                        self.peaks_requested = false;
                         println!(
                             "即将尝试播放下一首歌：{} ({})",
                             self.current_song.ncm_id, self.current_song.local_file
                         );
-                        let _ = self.app.emit_all(
-                            "on-audio-thread-event",
-                            AudioThreadEvent::LoadingAudio {
-                                ncm_id: self.current_song.ncm_id.to_owned(),
-                            },
-                        );
+                        self.emit_event(AudioThreadEvent::LoadingAudio {
+                            ncm_id: self.current_song.ncm_id.to_owned(),
+                        });
                         // 是否有本地文件
                         if let Ok(file) = std::fs::OpenOptions::new()
                             .read(true)
@@ -374,30 +901,20 @@ impl AudioPlayer {
                                 .ok();
                             self.set_download_state(DownloadStatus::Downloaded);
                         } else {
-                            self.get_audio_url_in_thread();
+                            self.query_audio_url_in_task();
                         }
                     }
                 }
                 DownloadStatus::QueryingUrl => {
-                    let _ = self.app.emit_all(
-                        "on-audio-thread-event",
-                        AudioThreadEvent::LoadProgress { position: -1. },
-                    );
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    self.emit_event(AudioThreadEvent::LoadProgress { position: -1. });
                 }
                 DownloadStatus::GetUrl(song_url, song_size) => {
-                    let _ = self.app.emit_all(
-                        "on-audio-thread-event",
-                        AudioThreadEvent::LoadProgress { position: 0. },
-                    );
-                    self.take_and_wait_thread();
-                    self.download_audio_in_thread(song_url.as_str(), song_size)
+                    self.emit_event(AudioThreadEvent::LoadProgress { position: 0. });
+                    self.cancel_download_task();
+                    self.download_audio_in_task(song_url.as_str(), song_size)
                 }
                 DownloadStatus::DownloadingAudio(p) => {
-                    let _ = self.app.emit_all(
-                        "on-audio-thread-event",
-                        AudioThreadEvent::LoadProgress { position: p },
-                    );
+                    self.emit_event(AudioThreadEvent::LoadProgress { position: p });
                     let output_file_reader = std::fs::OpenOptions::new()
                         .read(true)
                         .open(&self.audio_current_tmp_file)
@@ -419,170 +936,463 @@ impl AudioPlayer {
                             DecodeError::Unsupported(_)
                             | DecodeError::DecodeError(_)
                             | DecodeError::IoError(_) => {
+                                // 数据还不够识别格式时等待下载线程的下一次通知
                                 if self.get_download_state().get_download_progress() == 1. {
                                     self.set_download_state(DownloadStatus::Downloaded);
-                                } else {
-                                    std::thread::sleep(std::time::Duration::from_millis(10));
                                 }
                             }
                             _ => {
-                                self.take_and_wait_thread();
+                                self.cancel_download_task();
                                 self.set_download_state(DownloadStatus::Idle);
                             }
                         },
                     }
                 }
                 DownloadStatus::Downloaded => {
-                    let _ = self.app.emit_all(
-                        "on-audio-thread-event",
-                        AudioThreadEvent::LoadProgress { position: 1. },
-                    );
-                    self.take_and_wait_thread();
+                    self.emit_event(AudioThreadEvent::LoadProgress { position: 1. });
+                    self.cancel_download_task();
                     self.set_download_state(DownloadStatus::Idle);
                 }
                 DownloadStatus::Error(err) => {
                     println!("下载失败，播放下一首歌: {err}");
-                    let _ = self.app.emit_all(
-                        "on-audio-thread-event",
-                        AudioThreadEvent::LoadError { error: err },
-                    );
+                    self.emit_event(AudioThreadEvent::LoadError { error: err });
                     self.set_download_state(DownloadStatus::Idle);
-                    self.take_and_wait_thread();
+                    self.cancel_download_task();
                 }
             }
         }
+        if let Some(error) = load_error {
+            // 无法解码的歌曲当作加载失败处理，跳过并播放下一首歌
+            println!(
+                "无法播放歌曲 {}，播放下一首歌: {error}",
+                self.current_song.ncm_id
+            );
+            self.emit_event(AudioThreadEvent::LoadError { error });
+            self.format_result = None;
+            self.decoder = None;
+            self.cancel_download_task();
+            self.set_download_state(DownloadStatus::Idle);
+        }
+        if let Some(start) = loop_to {
+            if let Err(err) = self.seek_to(start) {
+                println!("[WARN][AT] 无法跳回循环起点 {err}");
+            }
+        }
+        if is_new_track {
+            self.position_markers.clear();
+            self.play_position = 0.;
+            self.time_stretcher.reset();
+            self.loop_range = None;
+            self.loop_crossfade.clear();
+            self.seek_target = None;
+            self.setup_normalization();
+        }
+        if !self.peaks_requested
+            && self.decoder.is_some()
+            && self.get_download_state().get_download_progress() == 1.
+        {
+            // 文件完整后再计算，在线播放的歌曲要等到下载完成，
+            // 等新歌曲的 ReplayGain 标签读取完毕后再决定是否需要测量响度
+            self.peaks_requested = true;
+            self.start_peaks_job();
+        }
+        if is_song_completed
+            && self
+                .sleep_timer
+                .as_mut()
+                .is_some_and(|x| x.on_track_completed())
+        {
+            self.finish_sleep_timer();
+        }
         if is_song_finished {
             self.format_result = None;
             self.decoder = None;
         }
     }
 
-    fn take_and_wait_thread(&mut self) {
-        self.stop_download_atom
-            .store(true, core::sync::atomic::Ordering::SeqCst);
-        if let Some(h) = self.download_thread_handle.take() {
-            let _ = h.join();
+    /// 精确跳转到指定位置
+    ///
+    /// 格式读取器只能跳转到目标位置之前的数据包，多出来的部分会在解码后根据 `seek_target` 丢弃。
+    fn seek_to(&mut self, position: f64) -> Result<(), AudioError> {
+        let (Some(format_result), Some(decoder)) =
+            (self.format_result.as_mut(), self.decoder.as_mut())
+        else {
+            return Err(AudioError::NoTrackLoaded);
+        };
+        let track_id = format_result.format.default_track().map(|x| x.id);
+        match format_result.format.seek(
+            SeekMode::Accurate,
+            SeekTo::Time {
+                time: Time::from(position),
+                track_id,
+            },
+        ) {
+            Ok(seeked) => {
+                decoder.reset();
+                let time = self.timebase.calc_time(seeked.required_ts);
+                self.seek_target = Some(time.seconds as f64 + time.frac);
+                Ok(())
+            }
+            Err(err) => Err(AudioError::SeekFailed {
+                error: format!("{position:.2}s {err}"),
+            }),
         }
-        self.stop_download_atom
-            .store(false, core::sync::atomic::Ordering::SeqCst);
     }
 
-    fn get_audio_url_in_thread(&mut self) {
-        let post_data = format!(
-            "{{\"ids\":\"[{}]\",\"level\":\"hires\",\"encodeType\":\"flac\"}}",
-            self.current_song.ncm_id
-        );
-        let bytes = concat_string::concat_string!(
-            "params=",
-            crate::eapi::eapi_encrypt_for_request("/api/song/enhance/player/url/v1", &post_data)
+    /// 读取新歌曲的 ReplayGain 标签
+    fn setup_normalization(&mut self) {
+        self.dsp_chain.reset();
+        let Some(format_result) = self.format_result.as_mut() else {
+            return;
+        };
+        self.replay_gain = ReplayGainInfo::read(format_result);
+        self.update_normalization_gain();
+    }
+
+    /// 既没有 ReplayGain 标签也没有缓存过的响度时，需要在后台解码整首歌来测量响度
+    fn needs_loudness_measurement(&self) -> bool {
+        !self.current_song.ncm_id.is_empty()
+            && self.replay_gain.is_empty()
+            && self.loudness_cache.get(&self.current_song.ncm_id).is_none()
+    }
+
+    fn update_normalization_gain(&mut self) {
+        let gain = if self.normalization == NormalizationMode::Off {
+            None
+        } else {
+            self.replay_gain.gain_for(self.normalization).or_else(|| {
+                self.loudness_cache
+                    .get(&self.current_song.ncm_id)
+                    .map(|x| REFERENCE_LOUDNESS - x)
+            })
+        };
+        self.dsp_settings.normalization_gain = gain.unwrap_or(0.);
+        self.dsp_chain.update(&self.dsp_settings);
+    }
+
+    /// 将后台测得的综合响度写入缓存，如果仍在播放这首歌则立即应用到响度标准化
+    pub fn store_measured_loudness(&mut self, ncm_id: String, loudness: f64) {
+        let is_current = ncm_id == self.current_song.ncm_id;
+        self.loudness_cache.insert(ncm_id, loudness);
+        if is_current && self.decoder.is_some() {
+            self.update_normalization_gain();
+            self.send_sync_status();
+        }
+    }
+
+    /// 歌曲下载完成后在后台计算波形和响度，本地文件直接读取，在线播放的歌曲读取临时文件
+    fn start_peaks_job(&self) {
+        let Some(app) = self.app() else {
+            return;
+        };
+        let local_file = std::path::Path::new(&self.current_song.local_file);
+        let source = if local_file.is_file() {
+            local_file
+        } else {
+            self.audio_current_tmp_file.as_path()
+        };
+        super::peaks::spawn_peaks_job(
+            app,
+            &self.current_song.ncm_id,
+            &self.current_song.local_file,
+            source,
+            self.needs_loudness_measurement(),
         );
-        let req = self
-            .session
-            .post("https://interface.music.163.com/eapi/song/enhance/player/url/v1")
-            .header("content-type", "application/x-www-form-urlencoded")
-            .bytes(bytes.as_bytes().to_vec());
+    }
+
+    /// 取消正在进行的请求或下载，并等待任务结束
+    fn cancel_download_task(&mut self) {
+        self.download_cancel.cancel();
+        if let Some(h) = self.download_task.take() {
+            let _ = tauri::async_runtime::block_on(h);
+        }
+        self.download_cancel = CancellationToken::new();
+    }
+
+    fn query_audio_url_in_task(&mut self) {
+        let Ok(ncm_id) = self.current_song.ncm_id.parse::<u64>() else {
+            self.set_download_state(DownloadStatus::Error(format!(
+                "歌曲 ID 不合法 {}",
+                self.current_song.ncm_id
+            )));
+            return;
+        };
+        let Some(app) = self.app() else {
+            self.set_download_state(DownloadStatus::Error("没有可用的网络会话".to_owned()));
+            return;
+        };
+        let api = NCMApi::new(app.clone()).with_cancel(self.download_cancel.clone());
 
         let mut state = self.download_state.lock().unwrap();
         *state = DownloadStatus::QueryingUrl;
         drop(state);
 
         let state = self.download_state.clone();
-        let stop_downloaded_atom = self.stop_download_atom.clone();
-        self.download_thread_handle = Some(spawn(move || {
+        self.download_task = Some(tauri::async_runtime::spawn(async move {
             println!("正在请求播放元数据");
-            match recv_json::<NCMResponse<Vec<NCMSongResponse>>>(req) {
+            match api.player_url(&[ncm_id], "hires").await {
                 Ok(res) => {
-                    if stop_downloaded_atom.load(Ordering::SeqCst) {
-                        return;
-                    }
                     let song_url = res
-                        .data
-                        .as_ref()
-                        .map(|x| {
-                            x.first()
-                                .map(|y| y.url.to_owned().unwrap_or_default())
-                                .unwrap_or_default()
-                        })
-                        .unwrap_or_default();
-                    let song_size = res
-                        .data
-                        .as_ref()
-                        .map(|x| x.first().map(|y| y.size).unwrap_or_default())
+                        .first()
+                        .and_then(|x| x.url.to_owned())
                         .unwrap_or_default();
+                    let song_size = res.first().map(|x| x.size).unwrap_or_default();
                     *state.lock().unwrap() = DownloadStatus::GetUrl(song_url, song_size);
+                    super::notify_download_progress();
                 }
+                Err(NCMApiError::Cancelled) => {}
                 Err(err) => {
-                    if stop_downloaded_atom.load(Ordering::SeqCst) {
-                        return;
-                    }
                     *state.lock().unwrap() = DownloadStatus::Error(err.to_string());
+                    super::notify_download_progress();
                 }
             }
         }));
     }
 
-    fn download_audio_in_thread(&mut self, song_url: &str, song_size: usize) {
+    fn download_audio_in_task(&mut self, song_url: &str, song_size: usize) {
         if song_url.is_empty() {
             self.set_download_state(DownloadStatus::Idle);
             println!("未找到音乐下载链接，跳过");
             return;
         }
+        let Some(app) = self.app() else {
+            self.set_download_state(DownloadStatus::Error("没有可用的网络会话".to_owned()));
+            return;
+        };
         println!("正在流式播放 {song_url}");
         self.set_download_state(DownloadStatus::DownloadingAudio(0.0));
-        let req = self.session.get(song_url);
+        let req = app.state::<crate::AppState>().http.get(song_url);
         let state = self.download_state.clone();
-        let stop_downloaded_atom = self.stop_download_atom.clone();
-        let mut output_file = std::fs::OpenOptions::new()
+        let cancel = self.download_cancel.clone();
+        let output_file = std::fs::OpenOptions::new()
             .create(true)
             .truncate(true)
             .write(true)
             .open(&self.audio_current_tmp_file)
             .unwrap();
-        self.download_thread_handle = Some(spawn(move || match req.send() {
-            Ok(mut song_res) => {
-                if stop_downloaded_atom.load(Ordering::SeqCst) {
-                    return;
-                }
-                let mut buf = [0u8; 1024];
-                let mut downloaded = 0;
-                while let Ok(size) = song_res.read(&mut buf) {
-                    let should_stopped =
-                        stop_downloaded_atom.load(core::sync::atomic::Ordering::SeqCst);
-                    if size == 0 || should_stopped {
-                        if should_stopped {
-                            println!("音频下载中断");
-                        } else {
-                            println!("音频下载完成");
-                        }
-                        break;
-                    } else {
-                        if let Err(err) = output_file.write_all(&buf[..size]) {
-                            *state.lock().unwrap() = DownloadStatus::Error(err.to_string());
-                            return;
-                        }
-                        if downloaded == 0 {
-                            output_file.sync_all().unwrap();
-                        }
-                    }
-                    downloaded += size;
-                    *state.lock().unwrap() =
-                        DownloadStatus::DownloadingAudio(downloaded as f64 / song_size as f64);
+        self.download_task = Some(tauri::async_runtime::spawn(async move {
+            let download = download_audio(req, output_file, song_size, &state);
+            let status = match cancel.run_until_cancelled(download).await {
+                Some(Ok(())) => {
+                    println!("音频下载完成");
+                    DownloadStatus::Downloaded
                 }
-                *state.lock().unwrap() = DownloadStatus::Downloaded;
-            }
-            Err(err) => {
-                if stop_downloaded_atom.load(Ordering::SeqCst) {
+                Some(Err(err)) => DownloadStatus::Error(format!("{err:#}")),
+                None => {
+                    println!("音频下载中断");
                     return;
                 }
-                *state.lock().unwrap() = DownloadStatus::Error(err.to_string());
-            }
+            };
+            *state.lock().unwrap() = status;
+            super::notify_download_progress();
         }));
     }
 }
 
-fn recv_json<D: DeserializeOwned>(
-    req: RequestBuilder<impl attohttpc::body::Body>,
-) -> Result<D, attohttpc::Error> {
-    let res = req.send()?;
-    res.json()
+/// 把音频下载到 `output_file` 中，每下载 1% 通知一次音频线程，避免过于频繁地唤醒
+async fn download_audio(
+    req: reqwest::RequestBuilder,
+    mut output_file: std::fs::File,
+    song_size: usize,
+    state: &Mutex<DownloadStatus>,
+) -> anyhow::Result<()> {
+    let mut song_res = req
+        .send()
+        .await
+        .and_then(|x| x.error_for_status())
+        .context("无法请求音频文件")?;
+    let mut downloaded = 0;
+    while let Some(chunk) = song_res.chunk().await.context("音频下载失败")? {
+        output_file
+            .write_all(&chunk)
+            .context("无法写入音频临时文件")?;
+        if downloaded == 0 {
+            output_file.sync_all().context("无法写入音频临时文件")?;
+        }
+        let last_percent = downloaded * 100 / song_size.max(1);
+        downloaded += chunk.len();
+        *state.lock().unwrap() =
+            DownloadStatus::DownloadingAudio(downloaded as f64 / song_size as f64);
+        if downloaded * 100 / song_size.max(1) != last_percent {
+            super::notify_download_progress();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::eapi::prepare_request;
+    use crate::mock_server::{audio_data, MockServer};
+    use crate::ncm_api::SongUrl;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mrbncm-test-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn download_audio_from_player_url() {
+        let server = MockServer::start().await;
+        let app_state = server.app_state();
+        let res = prepare_request(
+            &app_state,
+            &server.url("/eapi/song/enhance/player/url/v1"),
+            json!({ "ids": "[1901371647]", "level": "hires", "encodeType": "flac" }),
+        )
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+        let song_urls: Vec<SongUrl> = serde_json::from_value(res["data"].to_owned()).unwrap();
+        let song_url = song_urls[0].url.to_owned().unwrap();
+        assert!(song_url.starts_with(&server.base_url), "{song_url}");
+
+        let path = temp_file("download.flac");
+        let state = Mutex::new(DownloadStatus::GetUrl(
+            song_url.to_owned(),
+            song_urls[0].size,
+        ));
+        download_audio(
+            app_state.http.get(&song_url),
+            std::fs::File::create(&path).unwrap(),
+            song_urls[0].size,
+            &state,
+        )
+        .await
+        .unwrap();
+        let downloaded = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(downloaded == audio_data(), "下载的音频内容不一致");
+        assert!(*state.lock().unwrap() == DownloadStatus::DownloadingAudio(1.));
+    }
+
+    #[tokio::test]
+    async fn download_audio_reports_http_errors() {
+        let server = MockServer::start().await;
+        let app_state = server.app_state();
+        let path = temp_file("missing.flac");
+        let state = Mutex::new(DownloadStatus::DownloadingAudio(0.));
+        let err = download_audio(
+            app_state.http.get(&server.url("/missing.flac")),
+            std::fs::File::create(&path).unwrap(),
+            1,
+            &state,
+        )
+        .await
+        .unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert!(format!("{err:#}").contains("404"), "{err:#}");
+        assert!(*state.lock().unwrap() == DownloadStatus::DownloadingAudio(0.));
+    }
+
+    /// 读取 `FileSink` 写出的 32 位浮点 WAV 文件，返回采样率、声道数和交错的采样
+    fn read_float_wav(data: &[u8]) -> (u32, usize, Vec<f32>) {
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        assert!(&data[0..4] == b"RIFF" && &data[8..16] == b"WAVEfmt ");
+        assert_eq!(u16_at(20), 3, "不是浮点格式");
+        assert_eq!(
+            u32_at(40) as usize,
+            data.len() - 44,
+            "文件头中的长度没有回写"
+        );
+        let samples = data[44..]
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        (u32_at(24), u16_at(22) as usize, samples)
+    }
+
+    /// 用 `wav:` 输出播放 `tests/fixtures/audio` 中的音频，返回输出文件的采样率、声道数和采样
+    fn play_into_wav_sink(fixture: &str) -> (u32, usize, Vec<f32>) {
+        let cache_dir = temp_file(&format!("player-cache-{fixture}"));
+        let output = temp_file(&format!("player-output-{fixture}"));
+        let (mut player, events) =
+            AudioPlayer::mock(&format!("wav:{}", output.display()), cache_dir.clone());
+        let fixture = format!(
+            "{}/tests/fixtures/audio/{fixture}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        player
+            .process_message(AudioThreadMessage::SetVolume { volume: 1. })
+            .unwrap();
+        player
+            .process_message(AudioThreadMessage::SetPlaylist {
+                songs: vec![SongData {
+                    ncm_id: "1".to_owned(),
+                    local_file: fixture,
+                    duration: 0,
+                    orig_order: 0,
+                }],
+            })
+            .unwrap();
+        player
+            .process_message(AudioThreadMessage::NextSong {})
+            .unwrap();
+
+        let mut loaded = false;
+        for _ in 0..10000 {
+            player.process_audio();
+            if player.decoder.is_some() {
+                loaded = true;
+            } else if loaded {
+                break;
+            }
+        }
+        assert!(loaded && player.decoder.is_none(), "歌曲没有播放完毕");
+        let written = player.player.written_duration();
+        drop(player);
+
+        let duration = events.lock().unwrap().iter().find_map(|x| match x {
+            AudioThreadEvent::LoadAudio { ncm_id, duration } if ncm_id == "1" => Some(*duration),
+            _ => None,
+        });
+        assert_eq!(duration, Some(0.5));
+
+        let data = std::fs::read(&output).unwrap();
+        let _ = std::fs::remove_file(&output);
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        let (rate, channels, samples) = read_float_wav(&data);
+        let frames = samples.len() / channels;
+        assert!(
+            (written.as_secs_f64() - frames as f64 / rate as f64).abs() < 1e-6,
+            "输出时长 {written:?} 和文件中的 {frames} 帧不一致"
+        );
+        (rate, channels, samples)
+    }
+
+    #[test]
+    fn play_fixture_into_wav_sink() {
+        // 44.1kHz 16 位，0.5 秒、振幅 0.5 的 440Hz 正弦波，分别为双声道和单声道
+        for fixture in ["sine-440hz.wav", "sine-440hz-mono.wav"] {
+            let (rate, channels, samples) = play_into_wav_sink(fixture);
+            assert_eq!((rate, channels), (48000, 2), "{fixture}");
+            let frames = samples.len() / channels;
+            assert!(
+                (0.45..=0.55).contains(&(frames as f64 / rate as f64)),
+                "{fixture} 输出时长不正确 {frames}"
+            );
+
+            // 跳过开头增益平滑和重采样器预热的部分，单声道会复制到左右声道
+            let left = samples
+                .chunks_exact(channels)
+                .skip(rate as usize / 20)
+                .map(|x| {
+                    assert!((x[0] - x[1]).abs() < 1e-6, "{fixture} 左右声道不一致");
+                    x[0]
+                })
+                .collect::<Vec<_>>();
+            let peak = left.iter().fold(0f32, |a, x| a.max(x.abs()));
+            assert!(
+                (0.48..=0.52).contains(&peak),
+                "{fixture} 输出振幅不正确 {peak}"
+            );
+            let crossings = left.windows(2).filter(|x| x[0] < 0. && x[1] >= 0.).count();
+            let freq = crossings as f64 * rate as f64 / left.len() as f64;
+            assert!((freq - 440.).abs() < 5., "{fixture} 输出频率不正确 {freq}");
+        }
+    }
 }
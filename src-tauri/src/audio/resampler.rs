@@ -5,12 +5,112 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal, SignalSpec};
+use symphonia::core::audio::SignalSpec;
 use symphonia::core::conv::{FromSample, IntoSample};
 use symphonia::core::sample::Sample;
 
+use super::buffer::PlanarBuffer;
+
+/// 重采样器的质量设置
+///
+/// - `Fft`：默认的 FFT 同步重采样，音质与性能较为平衡
+/// - `Sinc`：带窗 Sinc 插值重采样，`sinc_len` 越长音质越好，但占用也越高
+/// - `Linear`：简单线性插值，音质最差，但几乎不占用性能，适合低功耗设备
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ResamplerQuality {
+    #[default]
+    Fft,
+    #[serde(rename_all = "camelCase")]
+    Sinc {
+        sinc_len: usize,
+        window: SincWindow,
+    },
+    Linear,
+}
+
+/// 对应 [`rubato::WindowFunction`] 的可序列化版本
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SincWindow {
+    Blackman,
+    Blackman2,
+    BlackmanHarris,
+    #[default]
+    BlackmanHarris2,
+    Hann,
+    Hann2,
+}
+
+impl From<SincWindow> for rubato::WindowFunction {
+    fn from(value: SincWindow) -> Self {
+        match value {
+            SincWindow::Blackman => Self::Blackman,
+            SincWindow::Blackman2 => Self::Blackman2,
+            SincWindow::BlackmanHarris => Self::BlackmanHarris,
+            SincWindow::BlackmanHarris2 => Self::BlackmanHarris2,
+            SincWindow::Hann => Self::Hann,
+            SincWindow::Hann2 => Self::Hann2,
+        }
+    }
+}
+
+enum ResamplerKind {
+    Fft(rubato::FftFixedIn<f32>),
+    Sinc(rubato::SincFixedIn<f32>),
+    Linear(LinearResampler),
+}
+
+/// 最简单的线性插值重采样器，会记住上一块输入的最后一帧以保证块与块之间连续
+struct LinearResampler {
+    /// 每输出一帧需要前进的输入帧数
+    step: f64,
+    /// 下一帧输出在当前输入块中的位置，-1 到 0 之间表示落在上一块的最后一帧与本块第一帧之间
+    pos: f64,
+    last: Vec<f32>,
+}
+
+impl LinearResampler {
+    fn new(from_sample_rate: usize, to_sample_rate: usize, num_channels: usize) -> Self {
+        Self {
+            step: from_sample_rate as f64 / to_sample_rate as f64,
+            pos: 0.,
+            last: vec![0.; num_channels],
+        }
+    }
+
+    fn process(&mut self, input: &[&[f32]], output: &mut [Vec<f32>]) {
+        let frames = input[0].len();
+        for channel in output.iter_mut() {
+            channel.clear();
+        }
+        if frames == 0 {
+            return;
+        }
+        while self.pos < (frames - 1) as f64 {
+            let index = self.pos.floor();
+            let frac = (self.pos - index) as f32;
+            let index = index as isize;
+            for (ch, out) in output.iter_mut().enumerate() {
+                let a = if index < 0 {
+                    self.last[ch]
+                } else {
+                    input[ch][index as usize]
+                };
+                let b = input[ch][(index + 1) as usize];
+                out.push(a + (b - a) * frac);
+            }
+            self.pos += self.step;
+        }
+        self.pos -= frames as f64;
+        for (ch, last) in self.last.iter_mut().enumerate() {
+            *last = input[ch][frames - 1];
+        }
+    }
+}
+
 pub struct Resampler<T> {
-    resampler: rubato::FftFixedIn<f32>,
+    resampler: ResamplerKind,
     input: Vec<Vec<f32>>,
     output: Vec<Vec<f32>>,
     interleaved: Vec<T>,
@@ -21,7 +121,8 @@ impl<T> Resampler<T>
 where
     T: Sample + FromSample<f32> + IntoSample<f32>,
 {
-    fn resample_inner(&mut self) -> &[T] {
+    /// 重采样输入缓冲区开头的 `duration` 帧，并把交错格式的结果追加到 `interleaved` 后面
+    fn resample_chunk(&mut self) {
         {
             let mut input: arrayvec::ArrayVec<&[f32], 32> = Default::default();
 
@@ -30,13 +131,27 @@ where
             }
 
             // Resample.
-            rubato::Resampler::process_into_buffer(
-                &mut self.resampler,
-                &input,
-                &mut self.output,
-                None,
-            )
-            .unwrap();
+            match &mut self.resampler {
+                ResamplerKind::Fft(resampler) => {
+                    rubato::Resampler::process_into_buffer(
+                        resampler,
+                        &input,
+                        &mut self.output,
+                        None,
+                    )
+                    .unwrap();
+                }
+                ResamplerKind::Sinc(resampler) => {
+                    rubato::Resampler::process_into_buffer(
+                        resampler,
+                        &input,
+                        &mut self.output,
+                        None,
+                    )
+                    .unwrap();
+                }
+                ResamplerKind::Linear(resampler) => resampler.process(&input, &mut self.output),
+            }
         }
 
         // Remove consumed samples from the input buffer.
@@ -46,16 +161,29 @@ where
 
         // Interleave the planar samples from Rubato.
         let num_channels = self.output.len();
+        let start = self.interleaved.len();
 
         self.interleaved
-            .resize(num_channels * self.output[0].len(), T::MID);
+            .resize(start + num_channels * self.output[0].len(), T::MID);
 
-        for (i, frame) in self.interleaved.chunks_exact_mut(num_channels).enumerate() {
+        for (i, frame) in self.interleaved[start..]
+            .chunks_exact_mut(num_channels)
+            .enumerate()
+        {
             for (ch, s) in frame.iter_mut().enumerate() {
                 *s = self.output[ch][i].into_sample();
             }
         }
+    }
 
+    /// 重采样输入缓冲区中所有完整的分块
+    ///
+    /// 变速时每次写入的帧数可能超过一个分块，只处理一块会让输入越积越多。
+    fn resample_inner(&mut self) -> &[T] {
+        self.interleaved.clear();
+        while self.input[0].len() >= self.duration {
+            self.resample_chunk();
+        }
         &self.interleaved
     }
 }
@@ -64,20 +192,54 @@ impl<T> Resampler<T>
 where
     T: Sample + FromSample<f32> + IntoSample<f32>,
 {
-    pub fn new(spec: SignalSpec, to_sample_rate: usize, duration: u64) -> Self {
+    pub fn new(
+        spec: SignalSpec,
+        to_sample_rate: usize,
+        duration: u64,
+        quality: ResamplerQuality,
+    ) -> Self {
         let duration = duration as usize;
         let num_channels = spec.channels.count();
 
-        let resampler = rubato::FftFixedIn::<f32>::new(
-            spec.rate as usize,
-            to_sample_rate,
-            duration,
-            2,
-            num_channels,
-        )
-        .unwrap();
-
-        let output = rubato::Resampler::output_buffer_allocate(&resampler);
+        let (resampler, output) = match quality {
+            ResamplerQuality::Fft => {
+                let resampler = rubato::FftFixedIn::<f32>::new(
+                    spec.rate as usize,
+                    to_sample_rate,
+                    duration,
+                    2,
+                    num_channels,
+                )
+                .unwrap();
+                let output = rubato::Resampler::output_buffer_allocate(&resampler);
+                (ResamplerKind::Fft(resampler), output)
+            }
+            ResamplerQuality::Sinc { sinc_len, window } => {
+                let params = rubato::SincInterpolationParameters {
+                    sinc_len: sinc_len.clamp(8, 2048),
+                    f_cutoff: 0.95,
+                    oversampling_factor: 128,
+                    interpolation: rubato::SincInterpolationType::Cubic,
+                    window: window.into(),
+                };
+                let resampler = rubato::SincFixedIn::<f32>::new(
+                    to_sample_rate as f64 / spec.rate as f64,
+                    1.0,
+                    params,
+                    duration,
+                    num_channels,
+                )
+                .unwrap();
+                let output = rubato::Resampler::output_buffer_allocate(&resampler);
+                (ResamplerKind::Sinc(resampler), output)
+            }
+            ResamplerQuality::Linear => {
+                let resampler =
+                    LinearResampler::new(spec.rate as usize, to_sample_rate, num_channels);
+                let output = vec![Vec::with_capacity(duration * 2); num_channels];
+                (ResamplerKind::Linear(resampler), output)
+            }
+        };
 
         let input = vec![Vec::with_capacity(duration); num_channels];
 
@@ -93,9 +255,11 @@ where
     /// Resamples a planar/non-interleaved input.
     ///
     /// Returns the resampled samples in an interleaved format.
-    pub fn resample(&mut self, input: AudioBufferRef<'_>) -> Option<&[T]> {
-        // Copy and convert samples into input buffer.
-        convert_samples_any(&input, &mut self.input);
+    pub fn resample(&mut self, input: &PlanarBuffer) -> Option<&[T]> {
+        // Copy samples into input buffer.
+        for (dst, src) in self.input.iter_mut().zip(input.planes()) {
+            dst.extend_from_slice(src);
+        }
 
         // Check if more samples are required.
         if self.input[0].len() < self.duration {
@@ -105,6 +269,23 @@ where
         Some(self.resample_inner())
     }
 
+    /// 已经输入但还没有被重采样的帧数
+    pub fn pending_frames(&self) -> usize {
+        self.input.first().map(|x| x.len()).unwrap_or_default()
+    }
+
+    /// 取出还没有被重采样的输入，用于重建重采样器时接上这部分音频
+    pub fn take_pending(&mut self) -> Vec<Vec<f32>> {
+        self.input.iter_mut().map(std::mem::take).collect()
+    }
+
+    /// 把 [`Resampler::take_pending`] 取出的输入放回输入缓冲区的开头
+    pub fn restore_pending(&mut self, pending: Vec<Vec<f32>>) {
+        for (dst, src) in self.input.iter_mut().zip(pending) {
+            dst.splice(0..0, src);
+        }
+    }
+
     /// Resample any remaining samples in the resample buffer.
     pub fn flush(&mut self) -> Option<&[T]> {
         let len = self.input[0].len();
@@ -126,28 +307,3 @@ where
         Some(self.resample_inner())
     }
 }
-
-fn convert_samples_any(input: &AudioBufferRef<'_>, output: &mut [Vec<f32>]) {
-    match input {
-        AudioBufferRef::U8(input) => convert_samples(input, output),
-        AudioBufferRef::U16(input) => convert_samples(input, output),
-        AudioBufferRef::U24(input) => convert_samples(input, output),
-        AudioBufferRef::U32(input) => convert_samples(input, output),
-        AudioBufferRef::S8(input) => convert_samples(input, output),
-        AudioBufferRef::S16(input) => convert_samples(input, output),
-        AudioBufferRef::S24(input) => convert_samples(input, output),
-        AudioBufferRef::S32(input) => convert_samples(input, output),
-        AudioBufferRef::F32(input) => convert_samples(input, output),
-        AudioBufferRef::F64(input) => convert_samples(input, output),
-    }
-}
-
-fn convert_samples<S>(input: &AudioBuffer<S>, output: &mut [Vec<f32>])
-where
-    S: Sample + IntoSample<f32>,
-{
-    for (c, dst) in output.iter_mut().enumerate() {
-        let src = input.chan(c);
-        dst.extend(src.iter().map(|&s| s.into_sample()));
-    }
-}
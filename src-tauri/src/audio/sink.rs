@@ -0,0 +1,473 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use byteorder::{WriteBytesExt, LE};
+use cpal::{SampleFormat, SampleRate, StreamConfig};
+use symphonia::core::audio::Channels;
+
+use super::{
+    buffer::PlanarBuffer,
+    output::{AudioOutput, OutputResampler},
+    resampler::ResamplerQuality,
+    volume::{GainProcessor, OutputVolume},
+};
+
+/// 文件和空输出所使用的固定输出格式
+const SINK_SAMPLE_RATE: u32 = 48000;
+const SINK_CHANNELS: u16 = 2;
+/// 空输出允许领先实际时间的长度，和声卡输出的环形缓冲区大小保持一致
+const NULL_SINK_BUFFER: Duration = Duration::from_millis(200);
+
+fn sink_config() -> StreamConfig {
+    StreamConfig {
+        channels: SINK_CHANNELS,
+        sample_rate: SampleRate(SINK_SAMPLE_RATE),
+        buffer_size: cpal::BufferSize::Default,
+    }
+}
+
+/// 是否是由 [`init_sink`] 处理的特殊输出设备名称
+pub fn is_sink_name(output_device_name: &str) -> bool {
+    output_device_name == "null"
+        || output_device_name.starts_with("wav:")
+        || output_device_name.starts_with("raw:")
+}
+
+/// 根据设备名称创建特殊输出，如果不是特殊名称则返回 `None`
+pub fn init_sink(output_device_name: &str) -> Option<Box<dyn AudioOutput>> {
+    if output_device_name == "null" {
+        println!("已初始化空输出设备");
+        return Some(Box::<NullSink>::default());
+    }
+    let (format, path) = if let Some(path) = output_device_name.strip_prefix("wav:") {
+        (FileSinkFormat::Wav, path)
+    } else if let Some(path) = output_device_name.strip_prefix("raw:") {
+        (FileSinkFormat::Raw, path)
+    } else {
+        return None;
+    };
+    match FileSink::new(path, format) {
+        Ok(sink) => {
+            println!("已初始化文件输出设备 {path}");
+            Some(Box::new(sink))
+        }
+        Err(err) => {
+            println!("[WARN][AT] 无法创建输出文件 {path}：{err:?}，将使用默认输出设备");
+            None
+        }
+    }
+}
+
+/// 声道混合到左右声道的系数
+fn stereo_weights(channel: Channels) -> (f32, f32) {
+    const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    let left = Channels::FRONT_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH
+        | Channels::REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT;
+    let right = Channels::FRONT_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH
+        | Channels::REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT;
+    if channel == Channels::FRONT_LEFT {
+        (1., 0.)
+    } else if channel == Channels::FRONT_RIGHT {
+        (0., 1.)
+    } else if (Channels::LFE1 | Channels::LFE2).contains(channel) {
+        (0., 0.)
+    } else if left.contains(channel) {
+        (H, 0.)
+    } else if right.contains(channel) {
+        (0., H)
+    } else {
+        // 中置和顶部中央等声道平均分到左右两边
+        (H, H)
+    }
+}
+
+/// 把交错格式的音频混合为文件输出固定使用的双声道
+///
+/// 单声道会复制到左右声道，环绕声按 ITU-R BS.775 的系数混入左右声道，低音声道会被丢弃。
+fn mix_to_stereo(input: &[f32], layout: Channels, output: &mut Vec<f32>) {
+    let channels = layout.count();
+    if layout == Channels::FRONT_LEFT | Channels::FRONT_RIGHT {
+        output.extend_from_slice(input);
+        return;
+    }
+    if channels == 0 {
+        return;
+    }
+    let weights: arrayvec::ArrayVec<(f32, f32), 32> = if channels == 1 {
+        [(1., 1.)].into_iter().collect()
+    } else {
+        layout.iter().map(stereo_weights).collect()
+    };
+    for frame in input.chunks_exact(channels) {
+        let (l, r) = frame
+            .iter()
+            .zip(weights.iter())
+            .fold((0., 0.), |(l, r), (x, (wl, wr))| (l + x * wl, r + x * wr));
+        output.push(l);
+        output.push(r);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FileSinkFormat {
+    /// 32 位浮点 WAV 文件
+    Wav,
+    /// 无文件头的 32 位浮点小端序交错 PCM
+    Raw,
+}
+
+/// 将音频写入文件的输出，写入速度不受实时限制
+pub struct FileSink {
+    config: StreamConfig,
+    format: FileSinkFormat,
+    file: BufWriter<File>,
+    data_len: u64,
+    volume: OutputVolume,
+    gain_processor: GainProcessor,
+    resampler: OutputResampler<f32>,
+    scratch: Vec<f32>,
+}
+
+impl FileSink {
+    pub fn new(path: impl AsRef<Path>, format: FileSinkFormat) -> anyhow::Result<Self> {
+        let config = sink_config();
+        let mut file = BufWriter::new(File::create(path)?);
+        if format == FileSinkFormat::Wav {
+            write_wav_header(&mut file, &config, 0)?;
+        }
+        let volume = OutputVolume::default();
+        Ok(Self {
+            format,
+            file,
+            data_len: 0,
+            gain_processor: GainProcessor::new(
+                volume.shared_gain(),
+                config.sample_rate.0,
+                config.channels as _,
+            ),
+            volume,
+            resampler: OutputResampler::new(config.sample_rate.0),
+            scratch: Vec::new(),
+            config,
+        })
+    }
+
+    /// 回写 WAV 文件头中的长度信息并刷新缓冲区
+    fn finalize(&mut self) -> std::io::Result<()> {
+        if self.format == FileSinkFormat::Wav {
+            self.file.seek(SeekFrom::Start(0))?;
+            write_wav_header(&mut self.file, &self.config, self.data_len)?;
+            self.file.seek(SeekFrom::End(0))?;
+        }
+        self.file.flush()
+    }
+}
+
+fn write_wav_header(
+    w: &mut impl Write,
+    config: &StreamConfig,
+    data_len: u64,
+) -> std::io::Result<()> {
+    let data_len = data_len.min(u32::MAX as u64 - 36) as u32;
+    let block_align = config.channels * 4;
+    w.write_all(b"RIFF")?;
+    w.write_u32::<LE>(36 + data_len)?;
+    w.write_all(b"WAVE")?;
+    w.write_all(b"fmt ")?;
+    w.write_u32::<LE>(16)?;
+    // WAVE_FORMAT_IEEE_FLOAT
+    w.write_u16::<LE>(3)?;
+    w.write_u16::<LE>(config.channels)?;
+    w.write_u32::<LE>(config.sample_rate.0)?;
+    w.write_u32::<LE>(config.sample_rate.0 * block_align as u32)?;
+    w.write_u16::<LE>(block_align)?;
+    w.write_u16::<LE>(32)?;
+    w.write_all(b"data")?;
+    w.write_u32::<LE>(data_len)?;
+    Ok(())
+}
+
+impl AudioOutput for FileSink {
+    fn stream_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        SampleFormat::F32
+    }
+
+    fn play(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn pause(&mut self) -> anyhow::Result<()> {
+        Ok(self.file.flush()?)
+    }
+
+    fn is_dead(&self) -> bool {
+        false
+    }
+
+    fn set_volume(&mut self, volume: f64) {
+        self.volume.set_volume(volume);
+    }
+
+    fn volume(&self) -> f64 {
+        self.volume.volume()
+    }
+
+    fn set_preamp(&mut self, preamp_db: f64) {
+        self.volume.set_preamp(preamp_db);
+    }
+
+    fn preamp(&self) -> f64 {
+        self.volume.preamp()
+    }
+
+    fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.resampler.set_quality(quality);
+    }
+
+    fn resampler_quality(&self) -> ResamplerQuality {
+        self.resampler.quality()
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.resampler.set_speed(speed);
+    }
+
+    fn set_fade_duration(&mut self, _duration: Duration) {}
+
+    fn clear(&mut self) {
+        self.resampler.clear();
+    }
+
+    fn write(&mut self, decoded: &PlanarBuffer) {
+        let Some(buf) = self.resampler.resample(decoded) else {
+            return;
+        };
+        // 重采样器输出的是原始声道数，文件头中写的是固定的双声道
+        self.scratch.clear();
+        mix_to_stereo(buf, decoded.spec().channels, &mut self.scratch);
+        self.gain_processor.process(&mut self.scratch);
+        for s in self.scratch.iter() {
+            if let Err(err) = self.file.write_f32::<LE>(*s) {
+                println!("[WARN][AT] 无法写入输出文件 {err}");
+                return;
+            }
+        }
+        self.data_len += self.scratch.len() as u64 * 4;
+    }
+
+    fn poll_write(&mut self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn poll_fade(&mut self) -> Option<Duration> {
+        None
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.finalize() {
+            println!("[WARN][AT] 无法刷新输出文件 {err}");
+        }
+    }
+
+    fn written_duration(&self) -> Duration {
+        let frames = self.data_len / (4 * self.config.channels as u64);
+        Duration::from_secs_f64(frames as f64 / self.config.sample_rate.0 as f64)
+    }
+
+    /// 写入文件的速度不受实时限制，写入即视为已经播放
+    fn played_duration(&self) -> Duration {
+        self.written_duration()
+    }
+
+    fn pending_input(&self) -> Duration {
+        self.resampler.pending_input()
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        let _ = self.finalize();
+    }
+}
+
+/// 丢弃所有音频数据的输出，但会按照音频时长限制写入速度，以模拟真实设备的播放速度
+pub struct NullSink {
+    config: StreamConfig,
+    volume: OutputVolume,
+    resampler_quality: ResamplerQuality,
+    speed: f64,
+    /// 开始计时的时刻，暂停时为空
+    clock_start: Option<Instant>,
+    /// 开始计时之前已经播放的时长
+    played_base: Duration,
+    written: Duration,
+}
+
+impl Default for NullSink {
+    fn default() -> Self {
+        Self {
+            config: sink_config(),
+            volume: OutputVolume::default(),
+            resampler_quality: ResamplerQuality::default(),
+            speed: 1.,
+            clock_start: None,
+            played_base: Duration::ZERO,
+            written: Duration::ZERO,
+        }
+    }
+}
+
+impl AudioOutput for NullSink {
+    fn stream_config(&self) -> &StreamConfig {
+        &self.config
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        SampleFormat::F32
+    }
+
+    fn play(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn pause(&mut self) -> anyhow::Result<()> {
+        self.played_base = self.played_duration();
+        self.clock_start = None;
+        Ok(())
+    }
+
+    fn is_dead(&self) -> bool {
+        false
+    }
+
+    fn set_volume(&mut self, volume: f64) {
+        self.volume.set_volume(volume);
+    }
+
+    fn volume(&self) -> f64 {
+        self.volume.volume()
+    }
+
+    fn set_preamp(&mut self, preamp_db: f64) {
+        self.volume.set_preamp(preamp_db);
+    }
+
+    fn preamp(&self) -> f64 {
+        self.volume.preamp()
+    }
+
+    fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.resampler_quality = quality;
+    }
+
+    fn resampler_quality(&self) -> ResamplerQuality {
+        self.resampler_quality
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    fn set_fade_duration(&mut self, _duration: Duration) {}
+
+    fn clear(&mut self) {
+        // 视为已经写入的数据都已经播放完毕
+        self.played_base = self.written;
+        self.clock_start = None;
+    }
+
+    fn write(&mut self, decoded: &PlanarBuffer) {
+        let rate = decoded.spec().rate;
+        if rate == 0 {
+            return;
+        }
+        let elapsed = self.clock_start.map(|x| x.elapsed()).unwrap_or_default();
+        if self.clock_start.is_none() || self.played_base + elapsed > self.written {
+            // 刚开始播放或者数据供应不上时，从已写入的位置重新开始计时
+            self.played_base = self.written;
+            self.clock_start = Some(Instant::now());
+        }
+        self.written +=
+            Duration::from_secs_f64(decoded.frames() as f64 / (rate as f64 * self.speed));
+    }
+
+    fn poll_write(&mut self) -> Duration {
+        if self.clock_start.is_none() {
+            // 还没有开始计时，下次写入时才会开始
+            return Duration::ZERO;
+        }
+        let played = self.played_duration();
+        self.written.saturating_sub(played + NULL_SINK_BUFFER)
+    }
+
+    fn poll_fade(&mut self) -> Option<Duration> {
+        None
+    }
+
+    fn flush(&mut self) {}
+
+    fn written_duration(&self) -> Duration {
+        self.written
+    }
+
+    fn played_duration(&self) -> Duration {
+        let elapsed = self.clock_start.map(|x| x.elapsed()).unwrap_or_default();
+        (self.played_base + elapsed).min(self.written)
+    }
+
+    fn pending_input(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_to_stereo_follows_channel_layout() {
+        let mut output = Vec::new();
+        mix_to_stereo(&[0.5, -0.25], Channels::FRONT_LEFT, &mut output);
+        assert_eq!(output, [0.5, 0.5, -0.25, -0.25]);
+
+        // 5.1：左、右、中置、低音、后左、后右
+        let layout = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        output.clear();
+        mix_to_stereo(&[0.1, 0.2, 0.4, 1., 0.3, 0.], layout, &mut output);
+        let h = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = [0.1 + 0.4 * h + 0.3 * h, 0.2 + 0.4 * h];
+        assert!(
+            output.len() == 2
+                && output
+                    .iter()
+                    .zip(expected)
+                    .all(|(x, y)| (x - y).abs() < 1e-6),
+            "{output:?}"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+/// 睡眠定时器结束前逐渐降低音量的时长
+pub const SLEEP_FADE_DURATION: Duration = Duration::from_secs(15);
+
+/// 睡眠定时器的结束条件
+///
+/// - `Duration`：经过指定时长后停止
+/// - `EndOfTrack`：当前歌曲播放完后停止
+/// - `AfterNTracks`：再播放完指定数量的歌曲后停止，当前歌曲也计算在内
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SleepTimerAfter {
+    Duration(Duration),
+    EndOfTrack,
+    AfterNTracks(usize),
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct SleepTimerStatus {
+    pub after: SleepTimerAfter,
+    /// 距离停止播放的剩余秒数，按歌曲数量计时且不是最后一首时无法得知
+    pub remaining: Option<f64>,
+    /// 停止前还需要播放完的歌曲数量，按时长计时时为空
+    pub remaining_tracks: Option<usize>,
+}
+
+/// 由音频线程自己执行的睡眠定时器，即使前端页面已经关闭也能生效
+pub struct SleepTimer {
+    after: SleepTimerAfter,
+    deadline: Option<Instant>,
+    tracks_left: usize,
+}
+
+impl SleepTimer {
+    pub fn new(after: SleepTimerAfter) -> Self {
+        let (deadline, tracks_left) = match after {
+            SleepTimerAfter::Duration(duration) => (Some(Instant::now() + duration), 0),
+            SleepTimerAfter::EndOfTrack => (None, 1),
+            SleepTimerAfter::AfterNTracks(n) => (None, n.max(1)),
+        };
+        Self {
+            after,
+            deadline,
+            tracks_left,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+
+    /// 一首歌完整播放结束，返回是否应该停止播放
+    pub fn on_track_completed(&mut self) -> bool {
+        if self.deadline.is_some() {
+            return false;
+        }
+        self.tracks_left = self.tracks_left.saturating_sub(1);
+        self.tracks_left == 0
+    }
+
+    /// 距离停止播放的剩余秒数，`track_remaining` 为当前歌曲的剩余秒数
+    pub fn remaining(&self, track_remaining: Option<f64>) -> Option<f64> {
+        match self.deadline {
+            Some(deadline) => Some(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_secs_f64(),
+            ),
+            None if self.tracks_left == 1 => track_remaining.map(|x| x.max(0.)),
+            None => None,
+        }
+    }
+
+    /// 音量系数，在停止前的 [`SLEEP_FADE_DURATION`] 内从 1 逐渐降到 0
+    pub fn fade_factor(&self, track_remaining: Option<f64>) -> f64 {
+        self.remaining(track_remaining).map_or(1., |x| {
+            (x / SLEEP_FADE_DURATION.as_secs_f64()).clamp(0., 1.)
+        })
+    }
+
+    pub fn status(&self, track_remaining: Option<f64>) -> SleepTimerStatus {
+        SleepTimerStatus {
+            after: self.after,
+            remaining: self.remaining(track_remaining),
+            remaining_tracks: self.deadline.is_none().then_some(self.tracks_left),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use super::buffer::PlanarBuffer;
+
+/// 每个合成帧的长度
+const FRAME_SECS: f64 = 0.04;
+/// 寻找最相似片段时允许偏离理想位置的范围
+const SEEK_SECS: f64 = 0.01;
+/// 计算相似度时的采样间隔，用于降低运算量
+const CORRELATION_STRIDE: usize = 4;
+/// 粗略搜索时候选位置和采样的间隔，之后只在粗略结果附近逐个位置细化
+const COARSE_STEP: usize = 8;
+
+/// 播放速度的调节方式
+///
+/// - `Resample`：直接改变重采样比例，音调会随速度一起变化
+/// - `TimeStretch`：使用 WSOLA 算法进行时间伸缩，保持音调不变
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum PlaybackRateMode {
+    #[default]
+    Resample,
+    TimeStretch,
+}
+
+/// 基于 WSOLA（波形相似叠加）的时间伸缩器，在不改变音调的前提下改变播放速度
+///
+/// 以固定的合成步长输出加窗后的帧，每一帧从输入中理想位置附近挑选和上一帧的自然延续最相似的片段，
+/// 以避免相位不连续产生的杂音。速度为 1 时直接透传数据。
+pub struct TimeStretcher {
+    rate: f64,
+    sample_rate: u32,
+    frame_len: usize,
+    seek_len: usize,
+    window: Vec<f32>,
+    input: Vec<Vec<f32>>,
+    /// 下一帧在输入缓冲区中的理想位置
+    input_pos: f64,
+    /// 上一帧所选片段的自然延续在输入缓冲区中的位置
+    continuation: Option<usize>,
+    overlap: Vec<Vec<f32>>,
+    /// 寻找相似片段时使用的单声道混合数据
+    mono: Vec<f32>,
+}
+
+impl Default for TimeStretcher {
+    fn default() -> Self {
+        Self {
+            rate: 1.,
+            sample_rate: 0,
+            frame_len: 0,
+            seek_len: 0,
+            window: Vec::new(),
+            input: Vec::new(),
+            input_pos: 0.,
+            continuation: None,
+            overlap: Vec::new(),
+            mono: Vec::new(),
+        }
+    }
+}
+
+impl TimeStretcher {
+    pub fn set_rate(&mut self, rate: f64) {
+        if self.rate != rate {
+            self.rate = rate;
+            if rate == 1. {
+                self.reset();
+            }
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// 已经输入但还没有输出的音频时长
+    pub fn pending_duration(&self) -> Duration {
+        if self.rate == 1. || self.sample_rate == 0 {
+            return Duration::ZERO;
+        }
+        let frames = self.input.first().map(|x| x.len()).unwrap_or_default() as f64;
+        Duration::from_secs_f64((frames - self.input_pos).max(0.) / self.sample_rate as f64)
+    }
+
+    /// 清空缓存的输入和叠加数据，用于切歌或跳转后
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|x| x.clear());
+        self.overlap.iter_mut().for_each(|x| x.fill(0.));
+        self.input_pos = 0.;
+        self.continuation = None;
+    }
+
+    fn setup(&mut self, sample_rate: u32, channels: usize) {
+        self.sample_rate = sample_rate;
+        // 帧长取偶数，保证合成步长恰好为一半
+        self.frame_len = ((sample_rate as f64 * FRAME_SECS) as usize / 2 * 2).max(2);
+        self.seek_len = (sample_rate as f64 * SEEK_SECS) as usize;
+        // 周期 Hann 窗在 50% 重叠时叠加结果恒为 1
+        self.window = (0..self.frame_len)
+            .map(|i| {
+                let phase = std::f64::consts::TAU * i as f64 / self.frame_len as f64;
+                (0.5 - 0.5 * phase.cos()) as f32
+            })
+            .collect();
+        self.input = vec![Vec::new(); channels];
+        self.overlap = vec![vec![0.; self.frame_len]; channels];
+        self.input_pos = 0.;
+        self.continuation = None;
+    }
+
+    /// 在 `[lo, hi]` 中寻找与 `target` 开始的片段最相似的位置
+    ///
+    /// 先按 `COARSE_STEP` 的间隔粗略搜索，再在最佳位置附近逐个位置细化
+    fn find_best_pos(&mut self, lo: usize, hi: usize, target: usize) -> usize {
+        let len = self.frame_len;
+        // 先把用到的范围混合为单声道，避免每次比较都重新混合所有声道
+        let start = lo.min(target);
+        let end = hi.max(target) + len;
+        self.mono.clear();
+        self.mono
+            .extend((start..end).map(|i| self.input.iter().map(|x| x[i]).sum::<f32>()));
+
+        let mono = &self.mono;
+        let reference = &mono[target - start..][..len];
+        let score = |pos: usize, stride: usize| -> f32 {
+            let (corr, energy) = mono[pos - start..][..len]
+                .iter()
+                .zip(reference)
+                .step_by(stride)
+                .fold((0f32, 0f32), |(corr, energy), (a, b)| {
+                    (corr + a * b, energy + a * a)
+                });
+            corr / (energy + 1e-9).sqrt()
+        };
+        let search = |lo: usize, hi: usize, step: usize, stride: usize| -> usize {
+            let mut best_pos = lo;
+            let mut best_score = f32::MIN;
+            for pos in (lo..=hi).step_by(step) {
+                let score = score(pos, stride);
+                if score > best_score {
+                    best_score = score;
+                    best_pos = pos;
+                }
+            }
+            best_pos
+        };
+
+        let coarse = search(lo, hi, COARSE_STEP, COARSE_STEP);
+        search(
+            coarse.saturating_sub(COARSE_STEP - 1).max(lo),
+            (coarse + COARSE_STEP - 1).min(hi),
+            1,
+            CORRELATION_STRIDE,
+        )
+    }
+
+    /// 对缓冲区进行时间伸缩，输出的帧数会按播放速度变化，不足一帧的数据会留到下次处理
+    pub fn process(&mut self, buf: &mut PlanarBuffer) {
+        let channels = buf.channels();
+        if self.rate == 1. || channels == 0 {
+            return;
+        }
+        if self.sample_rate != buf.spec().rate || self.input.len() != channels {
+            self.setup(buf.spec().rate, channels);
+        }
+
+        let frames = buf.frames();
+        for (dst, src) in self.input.iter_mut().zip(buf.planes_mut().iter_mut()) {
+            dst.extend_from_slice(&src[..frames]);
+            src.clear();
+        }
+
+        let len = self.frame_len;
+        let hop = len / 2;
+        let analysis_hop = hop as f64 * self.rate;
+        let available = self.input[0].len();
+        loop {
+            let nominal = self.input_pos.round() as usize;
+            let lo = nominal.saturating_sub(self.seek_len);
+            let hi = nominal + self.seek_len;
+            let pos = match self.continuation {
+                Some(target) => {
+                    if hi + len > available || target + len > available {
+                        break;
+                    }
+                    self.find_best_pos(lo, hi, target)
+                }
+                None => {
+                    if nominal + len > available {
+                        break;
+                    }
+                    nominal
+                }
+            };
+
+            for ((overlap, input), output) in self
+                .overlap
+                .iter_mut()
+                .zip(self.input.iter())
+                .zip(buf.planes_mut().iter_mut())
+            {
+                for ((o, x), w) in overlap
+                    .iter_mut()
+                    .zip(&input[pos..pos + len])
+                    .zip(&self.window)
+                {
+                    *o += x * w;
+                }
+                output.extend(overlap.drain(..hop));
+                overlap.resize(len, 0.);
+            }
+
+            self.continuation = Some(pos + hop);
+            self.input_pos += analysis_hop;
+        }
+
+        // 丢弃之后不会再用到的输入
+        let next_lo = (self.input_pos.round() as usize).saturating_sub(self.seek_len);
+        let consumed = match self.continuation {
+            Some(target) => next_lo.min(target),
+            None => next_lo,
+        }
+        .min(available);
+        if consumed > 0 {
+            for input in self.input.iter_mut() {
+                input.drain(..consumed);
+            }
+            self.input_pos -= consumed as f64;
+            self.continuation = self.continuation.map(|x| x - consumed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal, SignalSpec};
+
+    use super::*;
+
+    /// 将 48kHz 的双声道 440Hz 正弦波分块送入时间伸缩器，返回左声道的输出
+    fn stretch_sine(rate: f64, chunks: usize) -> Vec<f32> {
+        const CHUNK: usize = 4800;
+        let spec = SignalSpec::new(48000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut stretcher = TimeStretcher::default();
+        stretcher.set_rate(rate);
+        let mut buf = PlanarBuffer::default();
+        let mut output = Vec::new();
+        for chunk in 0..chunks {
+            let mut input = AudioBuffer::<f32>::new(CHUNK as u64, spec);
+            input.render_reserved(Some(CHUNK));
+            for ch in 0..2 {
+                for (i, x) in input.chan_mut(ch).iter_mut().enumerate() {
+                    let t = (chunk * CHUNK + i) as f32 / 48000.;
+                    *x = 0.5 * (t * 440. * std::f32::consts::TAU).sin();
+                }
+            }
+            buf.load(&input.as_audio_buffer_ref());
+            stretcher.process(&mut buf);
+            output.extend_from_slice(&buf.planes()[0]);
+        }
+        output
+    }
+
+    #[test]
+    fn time_stretch_keeps_pitch() {
+        let output = stretch_sine(1.5, 20);
+        // 输入 2 秒，输出应约为 2 / 1.5 秒，只差尚未处理完的一帧左右
+        let expected = 2. * 48000. / 1.5;
+        assert!(
+            (output.len() as f64 - expected).abs() < 48000. * 0.1,
+            "{}",
+            output.len()
+        );
+
+        // 跳过开头淡入的半帧后，用过零次数估计频率
+        let steady = &output[960..];
+        let crossings = steady
+            .windows(2)
+            .filter(|x| x[0] < 0. && x[1] >= 0.)
+            .count();
+        let freq = crossings as f64 / (steady.len() as f64 / 48000.);
+        assert!((freq - 440.).abs() < 5., "{freq}");
+
+        // 拼接处相位连续时不会出现明显的幅度起伏
+        let peak = steady.iter().fold(0f32, |a, x| a.max(x.abs()));
+        assert!((peak - 0.5).abs() < 0.05, "{peak}");
+    }
+}
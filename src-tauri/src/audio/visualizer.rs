@@ -0,0 +1,321 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use realfft::RealFftPlanner;
+use symphonia::core::conv::IntoSample;
+use tauri::Manager;
+
+use super::output::AudioOutputSample;
+
+/// 保留的最近音频数据长度，单位为帧
+const HISTORY_LEN: usize = 8192;
+const FFT_SIZE: usize = 2048;
+/// 频谱显示的分贝范围，低于该范围的会显示为 0
+const SPECTRUM_DB_RANGE: f32 = 70.;
+/// 频谱每秒最多回落的高度，让跳动看起来更平滑
+const SPECTRUM_DECAY_PER_SEC: f32 = 1.5;
+const SPECTRUM_MIN_FREQ: f32 = 20.;
+const SPECTRUM_MAX_FREQ: f32 = 20000.;
+
+/// 可视化数据的类型
+///
+/// - `Spectrum`：按对数频率划分的频谱，每个数值为 0.0 - 1.0 的相对响度
+/// - `Waveform`：抽取后的单声道波形，每个数值为 -1.0 - 1.0 的采样值
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum VisualizerMode {
+    #[default]
+    Spectrum,
+    Waveform,
+}
+
+fn default_bands() -> usize {
+    64
+}
+
+fn default_fps() -> f64 {
+    30.
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct VisualizerConfig {
+    #[serde(default)]
+    pub mode: VisualizerMode,
+    /// 频谱的频段数量或波形的采样点数量
+    #[serde(default = "default_bands")]
+    pub bands: usize,
+    /// 每秒发送的帧数
+    #[serde(default = "default_fps")]
+    pub fps: f64,
+}
+
+/// 通过 `on-audio-visualizer` 事件发送给前端的一帧可视化数据
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VisualizerFrame {
+    mode: VisualizerMode,
+    data: Vec<f32>,
+}
+
+struct SampleHistory {
+    samples: Vec<f32>,
+    pos: usize,
+    sample_rate: u32,
+    /// 累计写入的帧数，用于判断是否有新数据
+    total: u64,
+}
+
+/// 在输出回调和可视化线程之间共享的最近一段单声道音频
+pub struct VisualizerTap {
+    enabled: AtomicBool,
+    history: Mutex<SampleHistory>,
+}
+
+impl Default for VisualizerTap {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            history: Mutex::new(SampleHistory {
+                samples: vec![0.; HISTORY_LEN],
+                pos: 0,
+                sample_rate: 0,
+                total: 0,
+            }),
+        }
+    }
+}
+
+impl VisualizerTap {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// 在输出回调中调用，将交错的音频混合为单声道后记录下来
+    ///
+    /// 拿不到锁时直接放弃这次数据，保证不会阻塞输出回调。
+    pub fn push<T: AudioOutputSample>(&self, data: &[T], channels: usize, sample_rate: u32) {
+        let Ok(mut history) = self.history.try_lock() else {
+            return;
+        };
+        let channels = channels.max(1);
+        history.sample_rate = sample_rate;
+        for frame in data.chunks_exact(channels) {
+            let mixed = frame
+                .iter()
+                .map(|&x| IntoSample::<f32>::into_sample(x))
+                .sum::<f32>()
+                / channels as f32;
+            let pos = history.pos;
+            history.samples[pos] = mixed;
+            history.pos = (pos + 1) % HISTORY_LEN;
+            history.total += 1;
+        }
+    }
+
+    /// 按时间顺序复制最近的 `len` 帧，返回采样率和累计写入的帧数
+    fn snapshot(&self, out: &mut Vec<f32>, len: usize) -> (u32, u64) {
+        let history = self.history.lock().unwrap();
+        let len = len.min(HISTORY_LEN);
+        let start = (history.pos + HISTORY_LEN - len) % HISTORY_LEN;
+        out.clear();
+        out.extend((0..len).map(|i| history.samples[(start + i) % HISTORY_LEN]));
+        (history.sample_rate, history.total)
+    }
+}
+
+/// 管理可视化线程，按需开启或关闭
+pub struct Visualizer {
+    tap: Arc<VisualizerTap>,
+    config: Option<VisualizerConfig>,
+    thread: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+impl Default for Visualizer {
+    fn default() -> Self {
+        Self {
+            tap: Arc::new(VisualizerTap::default()),
+            config: None,
+            thread: None,
+        }
+    }
+}
+
+impl Visualizer {
+    pub fn tap(&self) -> Arc<VisualizerTap> {
+        self.tap.clone()
+    }
+
+    pub fn config(&self) -> Option<VisualizerConfig> {
+        self.config
+    }
+
+    /// 设置可视化参数，为空时关闭可视化线程
+    pub fn set_config(&mut self, app: &tauri::AppHandle, config: Option<VisualizerConfig>) {
+        self.stop();
+        self.config = config;
+        if let Some(config) = config {
+            self.tap.enabled.store(true, Ordering::SeqCst);
+            let stop = Arc::new(AtomicBool::new(false));
+            let stop_c = stop.clone();
+            let tap = self.tap.clone();
+            let app = app.to_owned();
+            let handle = std::thread::spawn(move || run_visualizer(app, tap, config, stop_c));
+            self.thread = Some((stop, handle));
+        }
+    }
+
+    fn stop(&mut self) {
+        self.tap.enabled.store(false, Ordering::SeqCst);
+        if let Some((stop, handle)) = self.thread.take() {
+            stop.store(true, Ordering::SeqCst);
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Visualizer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run_visualizer(
+    app: tauri::AppHandle,
+    tap: Arc<VisualizerTap>,
+    config: VisualizerConfig,
+    stop: Arc<AtomicBool>,
+) {
+    let fps = config.fps.clamp(1., 120.);
+    let interval = Duration::from_secs_f64(1. / fps);
+    let bands = config.bands.clamp(1, 1024);
+    let mut spectrum = SpectrumAnalyzer::new(bands);
+    let mut window = Vec::with_capacity(HISTORY_LEN);
+    let mut last_total = 0;
+    let mut is_idle = true;
+    println!("可视化线程已开始运行 {config:?}");
+    while !stop.load(Ordering::SeqCst) {
+        let frame_start = Instant::now();
+        let len = match config.mode {
+            VisualizerMode::Spectrum => FFT_SIZE,
+            VisualizerMode::Waveform => HISTORY_LEN,
+        };
+        let (sample_rate, total) = tap.snapshot(&mut window, len);
+        let data = if total != last_total && sample_rate > 0 {
+            last_total = total;
+            is_idle = false;
+            Some(match config.mode {
+                VisualizerMode::Spectrum => {
+                    spectrum.process(&window, sample_rate, interval.as_secs_f32())
+                }
+                VisualizerMode::Waveform => {
+                    // 只显示最近一帧时间内的波形
+                    let len = ((sample_rate as f64 / fps) as usize).clamp(bands, window.len());
+                    decimate(&window[window.len() - len..], bands)
+                }
+            })
+        } else if !is_idle {
+            // 暂停后发送一帧静音，让前端的显示归零
+            is_idle = true;
+            spectrum.reset();
+            Some(vec![0.; bands])
+        } else {
+            None
+        };
+        if let Some(data) = data {
+            let _ = app.emit_all(
+                "on-audio-visualizer",
+                VisualizerFrame {
+                    mode: config.mode,
+                    data,
+                },
+            );
+        }
+        std::thread::sleep(interval.saturating_sub(frame_start.elapsed()));
+    }
+    println!("可视化线程已结束运行");
+}
+
+/// 将波形分成 `points` 段，每段取绝对值最大的采样
+fn decimate(samples: &[f32], points: usize) -> Vec<f32> {
+    let chunk = (samples.len() / points).max(1);
+    samples
+        .chunks(chunk)
+        .take(points)
+        .map(|x| {
+            x.iter()
+                .copied()
+                .fold(0f32, |a, b| if b.abs() > a.abs() { b } else { a })
+        })
+        .collect()
+}
+
+struct SpectrumAnalyzer {
+    fft: Arc<dyn realfft::RealToComplex<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    output: Vec<realfft::num_complex::Complex<f32>>,
+    levels: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    fn new(bands: usize) -> Self {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = std::f32::consts::TAU * i as f32 / FFT_SIZE as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            input: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            fft,
+            window,
+            levels: vec![0.; bands],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.levels.fill(0.);
+    }
+
+    fn process(&mut self, samples: &[f32], sample_rate: u32, elapsed_secs: f32) -> Vec<f32> {
+        for ((x, s), w) in self.input.iter_mut().zip(samples).zip(&self.window) {
+            *x = s * w;
+        }
+        if self.fft.process(&mut self.input, &mut self.output).is_err() {
+            return self.levels.clone();
+        }
+
+        // Hann 窗的相干增益为 0.5，换算回正弦波的振幅
+        let scale = 4. / FFT_SIZE as f32;
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let max_freq = SPECTRUM_MAX_FREQ.min(sample_rate as f32 / 2.);
+        let ratio = max_freq / SPECTRUM_MIN_FREQ;
+        let bands = self.levels.len();
+        let decay = SPECTRUM_DECAY_PER_SEC * elapsed_secs;
+        let last_bin = self.output.len() - 1;
+        for (i, level) in self.levels.iter_mut().enumerate() {
+            let lo = SPECTRUM_MIN_FREQ * ratio.powf(i as f32 / bands as f32);
+            let hi = SPECTRUM_MIN_FREQ * ratio.powf((i + 1) as f32 / bands as f32);
+            let lo_bin = ((lo / bin_width).round() as usize).min(last_bin);
+            let hi_bin = ((hi / bin_width).round() as usize).clamp(lo_bin, last_bin);
+            let magnitude = self.output[lo_bin..=hi_bin]
+                .iter()
+                .map(|x| x.norm())
+                .fold(0f32, f32::max)
+                * scale;
+            let db = 20. * magnitude.max(1e-9).log10();
+            let value = ((db + SPECTRUM_DB_RANGE) / SPECTRUM_DB_RANGE).clamp(0., 1.);
+            *level = value.max(*level - decay);
+        }
+        self.levels.clone()
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use symphonia::core::conv::IntoSample;
+
+use super::output::AudioOutputSample;
+
+/// 音量滑块从最大值拉到接近零时覆盖的分贝范围
+pub const VOLUME_DB_RANGE: f64 = 60.;
+/// 低于这个数值的音量会在对数曲线的基础上再线性衰减到完全静音
+const VOLUME_KNEE: f64 = 0.1;
+/// 软件前级增益的最大调节范围（正负）
+pub const MAX_PREAMP_DB: f64 = 12.;
+/// 增益变化的平滑时间，用于避免调节音量时产生“拉链”噪声
+const GAIN_SMOOTHING_SECS: f32 = 0.02;
+/// 软限幅器开始介入的电平（约 -1 dBFS）
+const LIMITER_THRESHOLD: f32 = 0.89;
+
+pub fn db_to_gain(db: f64) -> f32 {
+    10f64.powf(db / 20.) as f32
+}
+
+/// 将 0.0 - 1.0 的音量滑块数值按对数曲线映射为线性增益
+///
+/// 大部分范围按分贝线性变化，最低的 10% 会额外线性衰减到完全静音。
+pub fn volume_to_gain(volume: f64) -> f32 {
+    let volume = volume.clamp(0., 1.);
+    if volume <= 0. {
+        return 0.;
+    }
+    let gain = db_to_gain((volume - 1.) * VOLUME_DB_RANGE);
+    if volume < VOLUME_KNEE {
+        gain * (volume / VOLUME_KNEE) as f32
+    } else {
+        gain
+    }
+}
+
+/// 在音频线程和输出回调之间共享的目标增益，内部以 f32 的位模式存储
+#[derive(Debug)]
+pub struct SharedGain(AtomicU32);
+
+impl SharedGain {
+    pub fn new(gain: f32) -> Self {
+        Self(AtomicU32::new(gain.to_bits()))
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.0.store(gain.to_bits(), Ordering::SeqCst);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::SeqCst))
+    }
+}
+
+/// 在输出回调中使用的增益处理器
+///
+/// 每一帧都会将当前增益向目标增益平滑靠拢，当增益超过 0 dB 时还会对输出进行软限幅以防削波。
+pub struct GainProcessor {
+    target: Arc<SharedGain>,
+    current: f32,
+    smoothing: f32,
+    channels: usize,
+}
+
+impl GainProcessor {
+    pub fn new(target: Arc<SharedGain>, sample_rate: u32, channels: usize) -> Self {
+        let smoothing = 1. - (-1. / (GAIN_SMOOTHING_SECS * sample_rate as f32)).exp();
+        Self {
+            current: target.get(),
+            target,
+            smoothing,
+            channels: channels.max(1),
+        }
+    }
+
+    pub fn process<T: AudioOutputSample>(&mut self, data: &mut [T]) {
+        let target = self.target.get();
+        for frame in data.chunks_exact_mut(self.channels) {
+            self.current += (target - self.current) * self.smoothing;
+            let gain = self.current;
+            let should_limit = gain > 1.;
+            for x in frame.iter_mut() {
+                let s: f32 = (*x).into_sample();
+                let s = s * gain;
+                *x = if should_limit { soft_limit(s) } else { s }.into_sample();
+            }
+        }
+    }
+}
+
+/// 超过阈值的部分用 tanh 曲线平滑压缩到 1.0 以内
+fn soft_limit(s: f32) -> f32 {
+    let abs = s.abs();
+    if abs <= LIMITER_THRESHOLD {
+        s
+    } else {
+        let range = 1. - LIMITER_THRESHOLD;
+        let limited = LIMITER_THRESHOLD + range * ((abs - LIMITER_THRESHOLD) / range).tanh();
+        limited.copysign(s)
+    }
+}
+
+/// 输出端的音量与前级增益设置，换算后的目标增益会共享给输出回调使用
+pub struct OutputVolume {
+    volume: f64,
+    preamp_db: f64,
+    gain: Arc<SharedGain>,
+}
+
+impl Default for OutputVolume {
+    fn default() -> Self {
+        let volume = 0.5;
+        Self {
+            volume,
+            preamp_db: 0.,
+            gain: Arc::new(SharedGain::new(volume_to_gain(volume))),
+        }
+    }
+}
+
+impl OutputVolume {
+    pub fn shared_gain(&self) -> Arc<SharedGain> {
+        self.gain.clone()
+    }
+
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume.clamp(0., 1.);
+        self.update_gain();
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    pub fn set_preamp(&mut self, preamp_db: f64) {
+        self.preamp_db = preamp_db.clamp(-MAX_PREAMP_DB, MAX_PREAMP_DB);
+        self.update_gain();
+    }
+
+    pub fn preamp(&self) -> f64 {
+        self.preamp_db
+    }
+
+    fn update_gain(&self) {
+        self.gain
+            .set(volume_to_gain(self.volume) * db_to_gain(self.preamp_db));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain_db(volume: f64) -> f64 {
+        20. * (volume_to_gain(volume) as f64).log10()
+    }
+
+    #[test]
+    fn volume_curve_is_linear_in_db() {
+        assert_eq!(volume_to_gain(1.), 1.);
+        assert!(
+            (gain_db(0.5) + VOLUME_DB_RANGE / 2.).abs() < 0.01,
+            "{}",
+            gain_db(0.5)
+        );
+        // 拐点以上滑块每移动 10% 音量变化相同的分贝数
+        let step = VOLUME_DB_RANGE / 10.;
+        for i in 1..10 {
+            let volume = i as f64 / 10.;
+            let diff = gain_db(volume + 0.1) - gain_db(volume);
+            assert!((diff - step).abs() < 0.01, "{volume}: {diff}");
+        }
+    }
+
+    #[test]
+    fn volume_curve_is_continuous_and_reaches_silence() {
+        let knee_db = (VOLUME_KNEE - 1.) * VOLUME_DB_RANGE;
+        assert!((gain_db(VOLUME_KNEE) - knee_db).abs() < 0.01);
+        assert!((gain_db(VOLUME_KNEE - 1e-9) - knee_db).abs() < 0.01);
+        // 拐点以下在对数曲线的基础上额外线性衰减，滑块减半时额外降低约 6dB
+        let half_knee_db = (VOLUME_KNEE / 2. - 1.) * VOLUME_DB_RANGE - 6.02;
+        assert!((gain_db(VOLUME_KNEE / 2.) - half_knee_db).abs() < 0.01);
+        assert_eq!(volume_to_gain(0.), 0.);
+        let mut last = 0.;
+        for i in 1..=1000 {
+            let gain = volume_to_gain(i as f64 / 1000.);
+            assert!(gain > last, "音量曲线在 {i} 处不是单调递增的");
+            last = gain;
+        }
+    }
+}
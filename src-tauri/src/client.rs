@@ -0,0 +1,181 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use rand::Rng;
+use tauri::State;
+
+use crate::AppState;
+
+/// 客户端身份配置的保存文件名，位于应用配置文件夹中
+pub const CLIENT_PROFILE_FILE: &str = "client-profile.json";
+
+/// 由客户端身份决定的 Cookie 字段，发送请求时总是以配置中的值为准
+const IDENTITY_COOKIE_KEYS: &[&str] = &[
+    "os",
+    "appver",
+    "osver",
+    "deviceId",
+    "buildver",
+    "resolution",
+];
+
+/// 模拟的客户端身份
+///
+/// eapi 请求会把这些字段作为 `header` 对象放进加密参数中，
+/// 所有请求都会以 Cookie 的形式一并发送，缺少时部分接口会返回 301 或 -460。
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClientProfile {
+    pub os: String,
+    pub appver: String,
+    pub osver: String,
+    pub buildver: String,
+    pub resolution: String,
+    /// 首次启动时随机生成并保存，之后保持不变
+    pub device_id: String,
+}
+
+impl Default for ClientProfile {
+    fn default() -> Self {
+        Self {
+            os: "pc".into(),
+            appver: "2.10.7.200791".into(),
+            osver: "Microsoft-Windows-10-Professional-build-19045-64bit".into(),
+            buildver: "200791".into(),
+            resolution: "1920x1080".into(),
+            device_id: String::new(),
+        }
+    }
+}
+
+/// 从 Cookie 中取出指定字段的值，不存在时返回空字符串
+pub fn cookie_value<'a>(cookie: &'a str, name: &str) -> &'a str {
+    cookie
+        .split(';')
+        .filter_map(|x| x.trim().split_once('='))
+        .find(|x| x.0 == name)
+        .map(|x| x.1)
+        .unwrap_or_default()
+}
+
+/// 生成 52 位大写十六进制的设备 ID
+fn generate_device_id() -> String {
+    const HEX_CHARS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut rng = rand::thread_rng();
+    (0..52)
+        .map(|_| HEX_CHARS[rng.gen_range(0..16)] as char)
+        .collect()
+}
+
+/// 生成请求 ID，格式为毫秒时间戳加上四位随机数
+fn generate_request_id() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis())
+        .unwrap_or_default();
+    format!("{timestamp}_{:04}", rand::thread_rng().gen_range(0..1000))
+}
+
+impl ClientProfile {
+    /// 读取保存的客户端身份，不存在或无法解析时使用默认值，没有设备 ID 时会生成一个并保存
+    pub fn load(path: &Path) -> Self {
+        let mut profile: Self = std::fs::read(path)
+            .ok()
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default();
+        if profile.device_id.is_empty() {
+            profile.device_id = generate_device_id();
+            println!("已生成新的设备 ID {}", profile.device_id);
+            if let Err(err) = profile.save(path) {
+                println!("[WARN] 无法保存客户端身份配置 {err:?}");
+            }
+        }
+        profile
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("无法创建配置文件夹")?;
+        }
+        let data = serde_json::to_vec_pretty(self).context("无法序列化客户端身份配置")?;
+        std::fs::write(path, data).context("无法写入客户端身份配置")
+    }
+
+    pub fn user_agent(&self) -> String {
+        format!("Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Safari/537.36 Chrome/91.0.4472.164 NeteaseMusicDesktop/{}", self.appver)
+    }
+
+    fn identity_fields(&self) -> [(&'static str, &str); 6] {
+        [
+            ("os", &self.os),
+            ("appver", &self.appver),
+            ("osver", &self.osver),
+            ("deviceId", &self.device_id),
+            ("buildver", &self.buildver),
+            ("resolution", &self.resolution),
+        ]
+    }
+
+    /// eapi 请求参数中的 `header` 对象，登录状态从 `cookie` 中取出
+    pub fn eapi_header(&self, cookie: &str) -> serde_json::Value {
+        let mut header = serde_json::Map::new();
+        for (key, value) in self.identity_fields() {
+            header.insert(key.into(), value.into());
+        }
+        header.insert("requestId".into(), generate_request_id().into());
+        for key in ["MUSIC_U", "__csrf"] {
+            let value = cookie_value(cookie, key);
+            if !value.is_empty() {
+                header.insert(key.into(), value.into());
+            }
+        }
+        header.into()
+    }
+
+    /// 在用户的 Cookie 后附加客户端身份字段，用户 Cookie 中的同名字段会被替换
+    pub fn cookie_header(&self, cookie: &str) -> String {
+        let mut fields = cookie
+            .split(';')
+            .map(str::trim)
+            .filter(|x| {
+                !x.is_empty()
+                    && !x
+                        .split_once('=')
+                        .is_some_and(|(key, _)| IDENTITY_COOKIE_KEYS.contains(&key))
+            })
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for (key, value) in self.identity_fields() {
+            fields.push(format!("{key}={value}"));
+        }
+        fields.join("; ")
+    }
+}
+
+#[tauri::command]
+pub fn get_client_profile(app_state: State<'_, AppState>) -> ClientProfile {
+    app_state.http.client()
+}
+
+/// 修改客户端身份，传入的设备 ID 为空时保留原来的设备 ID
+#[tauri::command]
+pub fn set_client_profile(
+    app: tauri::AppHandle,
+    app_state: State<'_, AppState>,
+    mut profile: ClientProfile,
+) -> Result<ClientProfile, String> {
+    if profile.device_id.is_empty() {
+        profile.device_id = app_state.http.client().device_id;
+    }
+    let path = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or("无法获取配置文件夹")?
+        .join(CLIENT_PROFILE_FILE);
+    profile.save(&path).map_err(|x| format!("{x:?}"))?;
+    app_state.http.set_client(profile.to_owned());
+    Ok(profile)
+}
@@ -0,0 +1,522 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use libaes::Cipher;
+use rand::RngCore;
+
+/// 没有指定域名时导入的 Cookie 所属的域名
+pub const DEFAULT_COOKIE_DOMAIN: &str = "music.163.com";
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// 小写且不带开头的 `.`
+    pub domain: String,
+    /// 为真时只发送给与 `domain` 完全相同的主机，否则也发送给子域名
+    pub host_only: bool,
+    pub path: String,
+    /// 过期时间，单位为秒的 UNIX 时间戳，为空时是会话 Cookie
+    pub expires: Option<u64>,
+    pub secure: bool,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+/// 解析 Cookie 中的 HTTP 日期，支持 `Sun, 06 Nov 1994 08:49:37 GMT` 和 `Sunday, 06-Nov-94 08:49:37 GMT` 两种格式
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let date = date.split_once(',').map(|x| x.1).unwrap_or(date);
+    let mut parts = date.split([' ', '-']).filter(|x| !x.is_empty());
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|x| month.starts_with(x))? as u64 + 1;
+    let year: u64 = match parts.next()?.parse().ok()? {
+        x @ 0..=69 => x + 2000,
+        x @ 70..=99 => x + 1900,
+        x => x,
+    };
+    let mut time = parts.next()?.split(':').map(|x| x.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    // 公历日期转换为距离 1970-01-01 的天数
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// 按照 RFC 6265 第 5.1.4 节判断请求路径是否匹配 Cookie 的路径，
+/// `/api` 匹配 `/api` 和 `/api/song`，但不匹配 `/apifoo`
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// 请求链接中与 Cookie 匹配有关的部分
+struct RequestTarget {
+    host: String,
+    path: String,
+    secure: bool,
+}
+
+impl RequestTarget {
+    fn parse(url: &str) -> Option<Self> {
+        let url = url.parse::<tauri::http::Uri>().ok()?;
+        Some(Self {
+            host: url.host()?.to_ascii_lowercase(),
+            path: url.path().to_owned(),
+            secure: url.scheme_str() == Some("https"),
+        })
+    }
+}
+
+impl Cookie {
+    /// 解析响应中的一个 `Set-Cookie` 头，返回的 Cookie 已经过期时表示需要删除同名 Cookie
+    fn parse(set_cookie: &str, target: &RequestTarget, now: u64) -> Option<Self> {
+        let mut attrs = set_cookie.split(';');
+        let (name, value) = attrs.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Self {
+            name: name.to_owned(),
+            value: value.trim().trim_matches('"').to_owned(),
+            domain: target.host.to_owned(),
+            host_only: true,
+            path: "/".into(),
+            expires: None,
+            secure: false,
+        };
+        let mut max_age = None;
+        for attr in attrs {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    // 不接受为其它网站设置的 Cookie
+                    if !domain.is_empty() {
+                        if target.host != domain && !target.host.ends_with(&format!(".{domain}")) {
+                            return None;
+                        }
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_owned(),
+                "expires" => {
+                    if let Some(expires) = parse_http_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(secs) = value.parse::<i64>() {
+                        max_age = Some(now.saturating_add_signed(secs.max(-1)));
+                    }
+                }
+                "secure" => cookie.secure = true,
+                _ => {}
+            }
+        }
+        // Max-Age 的优先级比 Expires 高
+        if max_age.is_some() {
+            cookie.expires = max_age;
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|x| x <= now)
+    }
+
+    fn matches(&self, target: &RequestTarget) -> bool {
+        let domain_matches = if self.host_only {
+            target.host == self.domain
+        } else {
+            target.host == self.domain || target.host.ends_with(&format!(".{}", self.domain))
+        };
+        domain_matches && path_matches(&target.path, &self.path) && (target.secure || !self.secure)
+    }
+
+    fn same_key(&self, other: &Self) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// 保存响应中的 Cookie，并在请求时按照域名和路径附加，修改后会保存到文件中
+///
+/// 文件使用 AES-128-CBC 加密，开头的 16 字节是随机生成的 IV。密钥是随机生成的，
+/// 单独保存在另一个文件中，只能防止 Cookie 文件单独被复制或同步出去后被直接读取，
+/// 能同时读取两个文件的程序仍然可以解密。
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    file: Option<PathBuf>,
+    key: [u8; 16],
+}
+
+/// 读取密钥文件，不存在或者内容不正确时返回空
+fn read_key(path: &Path) -> Option<[u8; 16]> {
+    std::fs::read(path).ok()?.try_into().ok()
+}
+
+/// 随机生成密钥并写入密钥文件，在 Unix 上只允许当前用户读写
+fn create_key(path: &Path) -> anyhow::Result<[u8; 16]> {
+    let mut key = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut key);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("无法创建配置文件夹")?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).context("无法创建 Cookie 密钥文件")?;
+    file.write_all(&key).context("无法写入 Cookie 密钥文件")?;
+    Ok(key)
+}
+
+fn decrypt_cookies(data: &[u8], key: &[u8; 16]) -> Option<Vec<Cookie>> {
+    if data.len() < 16 {
+        return None;
+    }
+    let (iv, data) = data.split_at(16);
+    let data = Cipher::new_128(key).cbc_decrypt_checked(iv, data)?;
+    serde_json::from_slice(&data).ok()
+}
+
+impl CookieJar {
+    /// 从文件中读取 Cookie，之后的修改都会保存到这个文件中
+    ///
+    /// `key_file` 不存在时会随机生成新的密钥，文件不存在或者无法解密时会从空的 Cookie 开始。
+    pub fn load(path: &Path, key_file: &Path) -> Self {
+        let (key, cookies) = match read_key(key_file) {
+            Some(key) => {
+                let cookies = std::fs::read(path)
+                    .ok()
+                    .and_then(|x| decrypt_cookies(&x, &key));
+                (key, cookies)
+            }
+            None => {
+                let key = create_key(key_file).unwrap_or_else(|err| {
+                    println!("[WARN] 无法保存 Cookie 密钥，重启后需要重新登录 {err:?}");
+                    let mut key = [0u8; 16];
+                    rand::thread_rng().fill_bytes(&mut key);
+                    key
+                });
+                (key, None)
+            }
+        };
+        let now = now_secs();
+        Self {
+            cookies: cookies
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|x| !x.is_expired(now))
+                .collect(),
+            file: Some(path.to_owned()),
+            key,
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        // 会话 Cookie 不需要保存
+        let cookies = self
+            .cookies
+            .iter()
+            .filter(|x| x.expires.is_some())
+            .collect::<Vec<_>>();
+        let data = serde_json::to_vec(&cookies).context("无法序列化 Cookie")?;
+        let mut iv = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut iv);
+        let mut output = iv.to_vec();
+        output.extend(Cipher::new_128(&self.key).cbc_encrypt(&iv, &data));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("无法创建数据文件夹")?;
+        }
+        std::fs::write(path, output).context("无法写入 Cookie 文件")
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(err) = self.save() {
+            println!("[WARN] 无法保存 Cookie {err:?}");
+        }
+    }
+
+    fn insert(&mut self, cookie: Cookie, now: u64) {
+        self.cookies.retain(|x| !x.same_key(&cookie));
+        if !cookie.is_expired(now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// 保存响应中的 `Set-Cookie` 头，`url` 是发出请求的链接
+    pub fn store_response_cookies<'a>(
+        &mut self,
+        url: &str,
+        set_cookies: impl IntoIterator<Item = &'a str>,
+    ) {
+        let Some(target) = RequestTarget::parse(url) else {
+            return;
+        };
+        let now = now_secs();
+        let mut changed = false;
+        for set_cookie in set_cookies {
+            if let Some(cookie) = Cookie::parse(set_cookie, &target, now) {
+                self.insert(cookie, now);
+                changed = true;
+            }
+        }
+        if changed {
+            self.save_or_warn();
+        }
+    }
+
+    /// 导入 `a=1; b=2` 格式的 Cookie，归属于 [`DEFAULT_COOKIE_DOMAIN`] 及其子域名，会替换同名的 Cookie
+    pub fn import(&mut self, cookie: &str) {
+        let now = now_secs();
+        let mut changed = false;
+        for (name, value) in cookie.split(';').filter_map(|x| x.trim().split_once('=')) {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            self.cookies
+                .retain(|x| !(x.name == name && x.domain.ends_with(DEFAULT_COOKIE_DOMAIN)));
+            self.insert(
+                Cookie {
+                    name: name.to_owned(),
+                    value: value.trim().to_owned(),
+                    domain: DEFAULT_COOKIE_DOMAIN.into(),
+                    host_only: false,
+                    path: "/".into(),
+                    // 前端没有提供过期时间，保存一年，之后以服务器返回的为准
+                    expires: Some(now + 365 * 24 * 60 * 60),
+                    secure: false,
+                },
+                now,
+            );
+            changed = true;
+        }
+        if changed {
+            self.save_or_warn();
+        }
+    }
+
+    /// 发送到 `url` 时应该附加的 Cookie，格式为 `a=1; b=2`
+    pub fn cookie_header(&self, url: &str) -> String {
+        let Some(target) = RequestTarget::parse(url) else {
+            return String::new();
+        };
+        let now = now_secs();
+        let mut cookies = self
+            .cookies
+            .iter()
+            .filter(|x| !x.is_expired(now) && x.matches(&target))
+            .collect::<Vec<_>>();
+        // 路径更长的 Cookie 排在前面
+        cookies.sort_by_key(|x| std::cmp::Reverse(x.path.len()));
+        cookies
+            .into_iter()
+            .map(|x| concat_string::concat_string!(x.name, "=", x.value))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// 取出 [`DEFAULT_COOKIE_DOMAIN`] 下指定名称的 Cookie 的值，不存在时返回空字符串
+    pub fn value(&self, name: &str) -> String {
+        let now = now_secs();
+        self.cookies
+            .iter()
+            .find(|x| {
+                x.name == name && !x.is_expired(now) && x.domain.ends_with(DEFAULT_COOKIE_DOMAIN)
+            })
+            .map(|x| x.value.to_owned())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+        self.save_or_warn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn target(url: &str) -> RequestTarget {
+        RequestTarget::parse(url).unwrap()
+    }
+
+    fn parse(set_cookie: &str) -> Option<Cookie> {
+        Cookie::parse(set_cookie, &target("https://music.163.com/api/login"), NOW)
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("mrbncm-cookie-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn parse_http_date_formats() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784111777)
+        );
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(784111777)
+        );
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2000 00:00:00 GMT"),
+            Some(951782400)
+        );
+        assert_eq!(
+            parse_http_date("Tue, 01-Jan-30 00:00:00 GMT"),
+            Some(1893456000)
+        );
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn parse_set_cookie_attributes() {
+        let cookie = parse(
+            r#"MUSIC_U="abc=="; Domain=.163.com; Path=/api; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cookie,
+            Cookie {
+                name: "MUSIC_U".into(),
+                value: "abc==".into(),
+                domain: "163.com".into(),
+                host_only: false,
+                path: "/api".into(),
+                expires: Some(784111777),
+                secure: true,
+            }
+        );
+
+        let cookie = parse("__csrf=token").unwrap();
+        assert_eq!(cookie.domain, "music.163.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/");
+        assert_eq!(cookie.expires, None);
+        assert!(!cookie.secure);
+
+        // Max-Age 优先于 Expires，不以 `/` 开头的路径被忽略
+        let cookie =
+            parse("a=1; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Path=api").unwrap();
+        assert_eq!(cookie.expires, Some(NOW + 60));
+        assert_eq!(cookie.path, "/");
+
+        assert!(parse("=1").is_none());
+        assert!(parse("no-value").is_none());
+        assert!(parse("a=1; Domain=example.com").is_none());
+        assert!(parse("a=1; Domain=sic.163.com").is_none());
+    }
+
+    #[test]
+    fn expired_cookies_are_removed() {
+        let mut jar = CookieJar::default();
+        let url = "https://music.163.com/api/song";
+        jar.store_response_cookies(url, ["a=1; Max-Age=3600", "b=2"]);
+        assert_eq!(jar.cookie_header(url), "a=1; b=2");
+
+        // 服务器通过已经过期的 Cookie 删除同名 Cookie
+        jar.store_response_cookies(url, ["a=deleted; Max-Age=0"]);
+        assert_eq!(jar.cookie_header(url), "b=2");
+        jar.store_response_cookies(url, ["b=deleted; Expires=Thu, 01 Jan 1970 00:00:00 GMT"]);
+        assert_eq!(jar.cookie_header(url), "");
+
+        let cookie = parse("a=1; Max-Age=10").unwrap();
+        assert!(!cookie.is_expired(NOW + 9));
+        assert!(cookie.is_expired(NOW + 10));
+        assert!(!parse("a=1").unwrap().is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn domain_and_path_matching() {
+        let host_only = parse("a=1").unwrap();
+        assert!(host_only.matches(&target("https://music.163.com/")));
+        assert!(!host_only.matches(&target("https://interface.music.163.com/")));
+        assert!(!host_only.matches(&target("https://163.com/")));
+
+        let domain = parse("a=1; Domain=163.com").unwrap();
+        assert!(domain.matches(&target("https://163.com/")));
+        assert!(domain.matches(&target("https://interface.music.163.com/")));
+        assert!(!domain.matches(&target("https://not163.com/")));
+
+        let path = parse("a=1; Path=/api").unwrap();
+        assert!(path.matches(&target("https://music.163.com/api")));
+        assert!(path.matches(&target("https://music.163.com/api/")));
+        assert!(path.matches(&target("https://music.163.com/api/song")));
+        assert!(!path.matches(&target("https://music.163.com/apifoo")));
+        assert!(!path.matches(&target("https://music.163.com/")));
+
+        let dir = parse("a=1; Path=/api/").unwrap();
+        assert!(dir.matches(&target("https://music.163.com/api/song")));
+        assert!(!dir.matches(&target("https://music.163.com/api")));
+
+        let secure = parse("a=1; Secure").unwrap();
+        assert!(secure.matches(&target("https://music.163.com/")));
+        assert!(!secure.matches(&target("http://music.163.com/")));
+    }
+
+    #[test]
+    fn jar_is_saved_with_random_key_file() {
+        let dir = temp_dir();
+        let (file, key_file) = (dir.join("cookie-jar.bin"), dir.join("cookie-jar.key"));
+        let url = "https://music.163.com/api/song";
+        let mut jar = CookieJar::load(&file, &key_file);
+        jar.store_response_cookies(url, ["MUSIC_U=secret; Max-Age=3600", "session=1"]);
+
+        let key = std::fs::read(&key_file).unwrap();
+        assert_eq!(key.len(), 16);
+        let data = std::fs::read(&file).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("secret"));
+        // 会话 Cookie 不保存
+        let jar = CookieJar::load(&file, &key_file);
+        assert_eq!(jar.cookie_header(url), "MUSIC_U=secret");
+
+        std::fs::remove_file(&key_file).unwrap();
+        let jar = CookieJar::load(&file, &key_file);
+        assert_eq!(jar.cookie_header(url), "");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,11 +1,98 @@
+use std::{io::Read, sync::Arc, time::Duration};
+
 use anyhow::Context;
 use libaes::Cipher;
 use once_cell::unsync::Lazy;
+use reqwest::RequestBuilder;
 use tauri::State;
+use tokio_util::sync::CancellationToken;
 
+use crate::client::cookie_value;
+use crate::linuxapi::{linuxapi_encrypt_for_request, LINUXAPI_USER_AGENT};
+use crate::session::CookieStore;
+use crate::throttle::{backoff_delay, Throttle};
+use crate::weapi::weapi_encrypt;
 use crate::AppState;
 
 const EAPI_KEY: &[u8; 16] = b"e82ckenh8dichen8";
+/// 请求的默认超时时间，包括连接、发送和接收响应内容
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+/// 可以重试的请求最多重试的次数
+const MAX_RETRIES: u32 = 3;
+/// 网易云表示请求过于频繁的 `code`
+const THROTTLE_CODES: [i64; 3] = [-447, 405, 509];
+/// 会修改数据的接口路径中的片段，这些请求失败后不会自动重试，避免重复提交
+const NON_IDEMPOTENT_SEGMENTS: [&str; 14] = [
+    "like",
+    "login",
+    "logout",
+    "register",
+    "sms",
+    "captcha",
+    "subscribe",
+    "manipulate",
+    "create",
+    "delete",
+    "update",
+    "comment",
+    "share",
+    "sign",
+];
+
+/// 请求失败的类型，作为 `anyhow` 错误的上下文使用，用于判断是否需要重试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestFailure {
+    Timeout,
+    Connect,
+    /// 发送请求或者接收响应时出错
+    Network,
+    /// 服务器返回了错误的状态码，并且响应内容无法解析
+    Status(u16),
+    /// 触发了网易云的频率限制，值为响应中的 `code`
+    Throttled(i64),
+    /// 熔断器处于断开状态，值为大约还需要等待的秒数
+    Unavailable(u64),
+}
+
+impl std::fmt::Display for RequestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "请求超时"),
+            Self::Connect => write!(f, "无法连接到服务器"),
+            Self::Network => write!(f, "网络错误，请求没有完成"),
+            Self::Status(status) => write!(f, "服务器返回了错误状态 HTTP {status}"),
+            Self::Throttled(code) => write!(f, "请求过于频繁 {code}"),
+            Self::Unavailable(secs) => write!(f, "服务暂时不可用，请在 {secs} 秒后重试"),
+        }
+    }
+}
+
+impl std::error::Error for RequestFailure {}
+
+impl RequestFailure {
+    /// 从错误的上下文中找出失败类型
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.downcast_ref::<Self>().copied()
+    }
+
+    pub fn is_throttled(self) -> bool {
+        matches!(self, Self::Throttled(_) | Self::Status(429 | 509))
+    }
+
+    /// 服务器没有正常响应，计入熔断器的失败次数
+    fn is_server_failure(self) -> bool {
+        match self {
+            Self::Timeout | Self::Connect | Self::Network => true,
+            Self::Status(status) => status >= 500 && !self.is_throttled(),
+            Self::Throttled(_) | Self::Unavailable(_) => false,
+        }
+    }
+
+    fn is_retryable(self) -> bool {
+        self.is_server_failure() || self.is_throttled()
+    }
+}
+
 std::thread_local! {
     static EAPI_CIPHER: Lazy<Cipher> = Lazy::new(|| Cipher::new_128(EAPI_KEY));
 }
@@ -46,64 +133,559 @@ pub fn tauri_eapi_decrypt(data: &str) -> Result<String, String> {
     }
     let mut buf = vec![0; data.len() / 2];
     faster_hex::hex_decode(data.as_bytes(), &mut buf).map_err(|x| x.to_string())?;
-    Ok(eapi_decrypt(&buf))
+    eapi_decrypt(&buf)
+        .map(|x| String::from_utf8_lossy(&x).to_string())
+        .ok_or_else(|| "解密失败，数据长度或填充不正确".into())
 }
 
-pub fn eapi_decrypt(data: &[u8]) -> String {
-    EAPI_CIPHER.with(|c| String::from_utf8_lossy(&c.ebc_decrypt(data)).to_string())
+/// 解密 eapi 数据，密钥不对或数据损坏导致填充不正确时返回 `None`
+pub fn eapi_decrypt(data: &[u8]) -> Option<Vec<u8>> {
+    EAPI_CIPHER.with(|c| c.ebc_decrypt_checked(data))
 }
 
-pub async fn eapi_request(
-    app_state: State<'_, AppState>,
-    url: String,
-    data: serde_json::Value,
-) -> anyhow::Result<serde_json::Value> {
+/// 按照 `Content-Encoding` 解压响应内容，多个编码以逗号分隔，按照相反的顺序逐个解压
+fn decode_content_encoding(encoding: &str, mut body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    for encoding in encoding.rsplit(',').map(|x| x.trim().to_ascii_lowercase()) {
+        let mut decoded = Vec::with_capacity(body.len() * 4);
+        match encoding.as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => {
+                flate2::read::MultiGzDecoder::new(body.as_slice())
+                    .read_to_end(&mut decoded)
+                    .context("gzip 解压失败")?;
+            }
+            "deflate" => {
+                // 标准的 deflate 是带 zlib 头的，但也有服务器直接返回裸的 deflate 数据
+                if flate2::read::ZlibDecoder::new(body.as_slice())
+                    .read_to_end(&mut decoded)
+                    .is_err()
+                {
+                    decoded.clear();
+                    flate2::read::DeflateDecoder::new(body.as_slice())
+                        .read_to_end(&mut decoded)
+                        .context("deflate 解压失败")?;
+                }
+            }
+            "br" => {
+                brotli::Decompressor::new(body.as_slice(), 4096)
+                    .read_to_end(&mut decoded)
+                    .context("brotli 解压失败")?;
+            }
+            other => anyhow::bail!("不支持的响应编码 {other}"),
+        }
+        body = decoded;
+    }
+    Ok(body)
+}
+
+/// 请求数据中带有 `e_r` 时，服务器会返回加密后的响应内容
+fn wants_encrypted_response(data: &serde_json::Value) -> bool {
+    match data.get("e_r") {
+        Some(serde_json::Value::Bool(x)) => *x,
+        Some(serde_json::Value::String(x)) => x == "true",
+        _ => false,
+    }
+}
+
+/// 响应内容的摘要，用于错误信息中，只显示前 32 字节的十六进制
+fn body_preview(status: u16, body: &[u8]) -> String {
+    format!(
+        "HTTP {status}，响应内容（共 {} 字节）{}",
+        body.len(),
+        faster_hex::hex_string(&body[..body.len().min(32)])
+    )
+}
+
+/// 解析响应内容
+///
+/// 请求带有 `e_r` 时优先解密，否则优先按照 JSON 解析。没有要求加密时，
+/// 只有长度是 16 的倍数且不是文本的响应才会尝试解密，HTML 错误页等直接视为无法解析。
+/// 状态码不是 2xx 且无法解析时会带上 [`RequestFailure::Status`]。
+fn parse_response(status: u16, body: &[u8], encrypted: bool) -> anyhow::Result<serde_json::Value> {
+    let status_ok = (200..300).contains(&status);
+    if body.is_empty() {
+        if !status_ok {
+            return Err(anyhow::anyhow!("{}", body_preview(status, body))
+                .context(RequestFailure::Status(status)));
+        }
+        return Ok(serde_json::Value::Null);
+    }
+    let looks_like_json = matches!(body.first(), Some(b'{' | b'['));
+    // `usize::is_multiple_of` 需要较新的编译器
+    #[allow(unknown_lints, clippy::manual_is_multiple_of)]
+    let maybe_encrypted = encrypted || (body.len() % 16 == 0 && std::str::from_utf8(body).is_err());
+    let parse_json = || {
+        if looks_like_json {
+            serde_json::from_slice(body).ok()
+        } else {
+            None
+        }
+    };
+    let parse_encrypted = || {
+        if maybe_encrypted {
+            eapi_decrypt(body).and_then(|x| serde_json::from_slice(&x).ok())
+        } else {
+            None
+        }
+    };
+    let result = if encrypted {
+        parse_encrypted().or_else(parse_json)
+    } else {
+        parse_json().or_else(parse_encrypted)
+    };
+    if let Some(result) = result {
+        return Ok(result);
+    }
+    let err = if maybe_encrypted && eapi_decrypt(body).is_none() {
+        anyhow::anyhow!(
+            "响应解密失败，数据长度或填充不正确，{}",
+            body_preview(status, body)
+        )
+    } else {
+        anyhow::anyhow!("无法解析响应数据，{}", body_preview(status, body))
+    };
+    if status_ok {
+        Err(err)
+    } else {
+        Err(err.context(RequestFailure::Status(status)))
+    }
+}
+
+/// 响应中的 `code` 表示请求过于频繁时返回这个 `code`
+fn throttle_code(res: &serde_json::Value) -> Option<i64> {
+    res.get("code")
+        .and_then(|x| x.as_i64())
+        .filter(|x| THROTTLE_CODES.contains(x))
+}
+
+/// 路径中没有会修改数据的片段时，认为请求可以安全地重试
+fn is_idempotent(path: &str) -> bool {
+    !path.split('/').any(|segment| {
+        let segment = segment.to_ascii_lowercase();
+        NON_IDEMPOTENT_SEGMENTS
+            .iter()
+            .any(|x| segment.starts_with(x))
+    })
+}
+
+/// 根据链接路径的前缀选择加密方式后发送请求
+///
+/// - `/eapi/*`：eapi 加密，实际接口为 `/api/*`
+/// - `/weapi/*`：weapi 加密，会自动附加 `csrf_token`
+/// - `/api/linux/forward/*`：linuxapi 加密，实际接口为 `/api/*`，统一转发到 `/api/linux/forward`
+/// - 其它路径：直接以 JSON 提交
+///
+/// 所有请求都会附加 Cookie 罐中的 Cookie 和客户端身份，eapi 请求还会在参数中附加 `header` 对象。
+/// 不会修改数据的请求在网络错误、服务器错误和触发频率限制时会自动重试。
+pub fn prepare_request(
+    app_state: &AppState,
+    url: &str,
+    mut data: serde_json::Value,
+) -> anyhow::Result<PreparedRequest> {
+    let http = &app_state.http;
+    let cookie = http.cookie_jar().cookie_header(url);
+    let client = http.client();
     let url = url.parse::<tauri::http::Uri>().context("请求链接不合法")?;
-    let should_encrypt = url.path().starts_with("/eapi");
-    let req = app_state.session.lock().unwrap().post(url.to_string());
-    let req = if should_encrypt {
-        req.form(&[(
-            "params",
-            &eapi_encrypt_for_request(
-                &concat_string::concat_string!("/api", url.path().trim_start_matches("/eapi")),
-                serde_json::to_string(&data)
-                    .context("无法序列化提交数据")?
-                    .as_str(),
-            ),
-        )])
-        .context("无法序列化提交数据")?
+    let encrypted = wants_encrypted_response(&data);
+    let path = url.path();
+    let idempotent = is_idempotent(path);
+    let mut target = url.to_string();
+    let req = if let Some(api_path) = path.strip_prefix("/eapi") {
+        if let Some(obj) = data.as_object_mut() {
+            obj.entry("header")
+                .or_insert_with(|| client.eapi_header(&cookie));
+        }
+        let params = eapi_encrypt_for_request(
+            &concat_string::concat_string!("/api", api_path),
+            serde_json::to_string(&data)
+                .context("无法序列化提交数据")?
+                .as_str(),
+        );
+        http.post(&target).form(&[("params", &params)])
+    } else if path.starts_with("/weapi") {
+        if let Some(obj) = data.as_object_mut() {
+            obj.entry("csrf_token")
+                .or_insert_with(|| cookie_value(&cookie, "__csrf").into());
+        }
+        let form = weapi_encrypt(
+            serde_json::to_string(&data)
+                .context("无法序列化提交数据")?
+                .as_str(),
+        );
+        http.post(&target)
+            .form(&[("params", &form.params), ("encSecKey", &form.enc_sec_key)])
+    } else if let Some(api_path) = path.strip_prefix("/api/linux/forward") {
+        let origin = concat_string::concat_string!(
+            url.scheme_str().unwrap_or("https"),
+            "://",
+            url.authority().map(|x| x.as_str()).unwrap_or_default()
+        );
+        let eparams = linuxapi_encrypt_for_request(
+            &concat_string::concat_string!(origin, "/api", api_path),
+            &data,
+        );
+        target = concat_string::concat_string!(origin, "/api/linux/forward");
+        http.post_with_user_agent(&target, LINUXAPI_USER_AGENT)
+            .form(&[("eparams", &eparams)])
     } else {
-        req.json(&data).context("无法序列化提交数据")?
+        http.post(&target).json(&data)
     };
-    let res = tauri::async_runtime::spawn_blocking(move || req.send().map(|x| x.bytes()))
-        .await
-        .context("响应线程执行出错")?
-        .context("无法发送请求")?
-        .context("响应接收失败")?;
-    if let Some(b) = res.first().copied() {
-        if b == 123 {
-            if let Ok(obj) = serde_json::from_slice(&res) {
-                return Ok(obj);
+    Ok(PreparedRequest {
+        req: req
+            .header("accept-encoding", "gzip, deflate, br")
+            .timeout(REQUEST_TIMEOUT),
+        url: target,
+        encrypted,
+        idempotent,
+        cookies: http.cookie_store(),
+        throttle: http.throttle(),
+    })
+}
+
+/// 已经加密并设置好请求头的请求，不再依赖 `AppState`，可以移动到其它任务中发送
+pub struct PreparedRequest {
+    req: RequestBuilder,
+    /// 实际发出请求的链接，用于保存响应中的 Cookie
+    url: String,
+    encrypted: bool,
+    /// 失败后是否可以自动重试
+    idempotent: bool,
+    cookies: CookieStore,
+    throttle: Arc<Throttle>,
+}
+
+impl PreparedRequest {
+    /// 替换默认的超时时间 [`REQUEST_TIMEOUT`]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.req = self.req.timeout(timeout);
+        self
+    }
+
+    /// 发送请求并解析响应内容，可以重试的请求失败时会等待一段时间后重试
+    pub async fn send(self) -> anyhow::Result<serde_json::Value> {
+        let max_retries = if self.idempotent { MAX_RETRIES } else { 0 };
+        let mut attempt = 0;
+        loop {
+            let req = self.req.try_clone().context("请求内容无法重复发送")?;
+            let result = self.send_once(req).await;
+            let failure = result.as_ref().err().and_then(RequestFailure::of);
+            match failure {
+                Some(RequestFailure::Unavailable(_)) => {}
+                Some(failure) if failure.is_server_failure() => {
+                    self.throttle.breaker.record_failure()
+                }
+                Some(failure) if failure.is_throttled() => self.throttle.throttled(),
+                // 收到了响应就说明服务是可用的，即使响应内容有问题
+                _ => self.throttle.breaker.record_success(),
+            }
+            match failure {
+                Some(failure) if failure.is_retryable() && attempt < max_retries => {
+                    let delay = backoff_delay(attempt);
+                    attempt += 1;
+                    println!(
+                        "[WARN] {failure}，{} 毫秒后第 {attempt} 次重试 {}",
+                        delay.as_millis(),
+                        self.url
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return result,
             }
         }
     }
 
-    if res.is_empty() {
-        return Ok(serde_json::Value::Null);
+    /// 经过熔断器和限流器后发送一次请求
+    async fn send_once(&self, req: RequestBuilder) -> anyhow::Result<serde_json::Value> {
+        if let Err(wait) = self.throttle.breaker.check() {
+            return Err(RequestFailure::Unavailable(wait.as_secs().max(1)).into());
+        }
+        self.throttle.bucket.acquire().await;
+        let res = req.send().await.map_err(describe_error)?;
+        self.cookies.store_cookies(&self.url, &res);
+        let status = res.status().as_u16();
+        let encoding = res
+            .headers()
+            .get("content-encoding")
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let body = res.bytes().await.map_err(describe_error)?.to_vec();
+        let preview = body_preview(status, &body);
+        let body = decode_content_encoding(&encoding, body)
+            .with_context(|| format!("无法解压响应内容，{preview}"))?;
+        let res = parse_response(status, &body, self.encrypted)?;
+        if let Some(code) = throttle_code(&res) {
+            let message = ["message", "msg"]
+                .into_iter()
+                .find_map(|x| res.get(x).and_then(|x| x.as_str()))
+                .unwrap_or_default();
+            return Err(anyhow::anyhow!("{message}").context(RequestFailure::Throttled(code)));
+        }
+        Ok(res)
     }
 
-    let res = eapi_decrypt(&res);
+    /// 发送请求，`token` 被取消时立即放弃请求并返回错误
+    pub async fn send_with_cancel(
+        self,
+        token: &CancellationToken,
+    ) -> anyhow::Result<serde_json::Value> {
+        match token.run_until_cancelled(self.send()).await {
+            Some(result) => result,
+            None => anyhow::bail!("请求已取消"),
+        }
+    }
+}
 
-    serde_json::from_str(&res).context("无法解析响应数据")
+/// 给网络错误加上失败类型，超时和连接失败分开提示
+fn describe_error(err: reqwest::Error) -> anyhow::Error {
+    let failure = if err.is_timeout() {
+        RequestFailure::Timeout
+    } else if err.is_connect() {
+        RequestFailure::Connect
+    } else {
+        RequestFailure::Network
+    };
+    anyhow::Error::new(err).context(failure)
 }
 
+/// 发送请求，参见 [`prepare_request`]
+pub async fn eapi_request(
+    app_state: State<'_, AppState>,
+    url: String,
+    data: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    prepare_request(&app_state, &url, data)?.send().await
+}
+
+/// 前端使用的请求命令
+///
+/// 指定 `request_id` 时可以通过 `cancel_request` 取消请求，`timeout_ms` 可以替换默认的超时时间。
 #[tauri::command]
 pub async fn tauri_eapi_request(
     app_state: State<'_, AppState>,
     url: String,
     data: serde_json::Value,
+    request_id: Option<String>,
+    timeout_ms: Option<u64>,
 ) -> Result<serde_json::Value, String> {
-    eapi_request(app_state, url, data)
+    let result = async {
+        let mut req = prepare_request(&app_state, &url, data)?;
+        if let Some(timeout_ms) = timeout_ms {
+            req = req.timeout(Duration::from_millis(timeout_ms));
+        }
+        match &request_id {
+            Some(request_id) => {
+                let (seq, token) = app_state.http.register_request(request_id);
+                let result = req.send_with_cancel(&token).await;
+                app_state.http.finish_request(request_id, seq);
+                result
+            }
+            None => req.send().await,
+        }
+    }
+    .await;
+    result.map_err(|x| x.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_server::{decrypt_params, MockServer};
+
+    /// 以下加密结果由 OpenSSL 的 AES-128-ECB 和 PKCS#7 填充独立计算得到
+    const EMPTY_ENCRYPTED: &str = "6aa3b102fbe7296ab0db9ea5c46ad12b";
+    const CODE_200_ENCRYPTED: &str = "51b05e35c69b2f9ff4967735ded68881";
+    const PLAYER_URL_PATH: &str = "/api/song/enhance/player/url/v1";
+    const PLAYER_URL_DATA: &str = r#"{"ids":"[1]","level":"hires"}"#;
+    const PLAYER_URL_PARAMS: &str = concat!(
+        "fa90b329e9614f79e79598f37dc2edb487f00d1bc4c9b24cd57e6c318b907356",
+        "9338432cd7d98d1a3626e997a2c53121f2652556b9caa141000d39a0c1d1ae12",
+        "bd6f5db420bf4156b963051800486c0d6a37c74e90f3b87f49a604fc424d1e5b",
+        "1a4332908eab56d75fbb40e6a33cdc85b357d7e2c7f1996da01aeb03ace25269",
+    );
+
+    #[test]
+    fn eapi_encrypt_known_answers() {
+        assert_eq!(faster_hex::hex_string(&eapi_encrypt("")), EMPTY_ENCRYPTED);
+        assert_eq!(tauri_eapi_encrypt(r#"{"code":200}"#), CODE_200_ENCRYPTED);
+    }
+
+    #[test]
+    fn eapi_encrypt_for_request_known_answer() {
+        assert_eq!(
+            eapi_encrypt_for_request(PLAYER_URL_PATH, PLAYER_URL_DATA),
+            PLAYER_URL_PARAMS
+        );
+        let (path, data) = decrypt_params(PLAYER_URL_PARAMS).unwrap();
+        assert_eq!(path, PLAYER_URL_PATH);
+        assert_eq!(data, json!({ "ids": "[1]", "level": "hires" }));
+    }
+
+    #[test]
+    fn eapi_decrypt_known_answers() {
+        assert_eq!(
+            tauri_eapi_decrypt(CODE_200_ENCRYPTED).unwrap(),
+            r#"{"code":200}"#
+        );
+        assert_eq!(tauri_eapi_decrypt(EMPTY_ENCRYPTED).unwrap(), "");
+        assert_eq!(tauri_eapi_decrypt("").unwrap(), "");
+        let data = "中文和 emoji 🎵 也能还原".repeat(5);
+        assert_eq!(eapi_decrypt(&eapi_encrypt(&data)).unwrap(), data.as_bytes());
+    }
+
+    #[test]
+    fn eapi_decrypt_rejects_corrupted_data() {
+        let mut buf = eapi_encrypt(r#"{"code":200}"#);
+        assert!(eapi_decrypt(&buf[..15]).is_none());
+        let last = buf.len() - 1;
+        buf[last] ^= 0xff;
+        assert!(eapi_decrypt(&buf).is_none());
+        assert!(tauri_eapi_decrypt("not hex").is_err());
+    }
+
+    #[test]
+    fn decrypt_params_checks_md5() {
+        let plain = format!(
+            "{PLAYER_URL_PATH}-36cd479b6b5-{PLAYER_URL_DATA}-36cd479b6b5-{}",
+            "0".repeat(32)
+        );
+        let params = faster_hex::hex_string(&eapi_encrypt(&plain));
+        let err = decrypt_params(&params).unwrap_err();
+        assert!(err.to_string().contains("md5"), "{err:#}");
+    }
+
+    #[test]
+    fn parse_response_handles_plain_and_encrypted_bodies() {
+        let encrypted = eapi_encrypt(r#"{"code":200}"#);
+        for wants_encrypted in [true, false] {
+            assert_eq!(
+                parse_response(200, &encrypted, wants_encrypted).unwrap(),
+                json!({ "code": 200 })
+            );
+            assert_eq!(
+                parse_response(200, br#"{"code":301}"#, wants_encrypted).unwrap(),
+                json!({ "code": 301 })
+            );
+        }
+        assert_eq!(
+            parse_response(200, b"", false).unwrap(),
+            serde_json::Value::Null
+        );
+        let err = parse_response(502, b"<html>Bad Gateway</html>", false).unwrap_err();
+        assert_eq!(RequestFailure::of(&err), Some(RequestFailure::Status(502)));
+    }
+
+    #[tokio::test]
+    async fn eapi_request_against_mock_server() {
+        let server = MockServer::start().await;
+        let state = server.app_state();
+        let cases = [
+            (
+                "/eapi/song/enhance/player/url/v1",
+                json!({ "ids": "[1901371647]", "level": "hires", "encodeType": "flac" }),
+            ),
+            (
+                "/eapi/v3/song/detail",
+                json!({ "c": r#"[{"id":1901371647,"v":0}]"# }),
+            ),
+            (
+                "/eapi/v6/playlist/detail",
+                json!({ "id": 3778678, "n": 100000, "s": 8 }),
+            ),
+        ];
+        for (path, mut data) in cases {
+            data["e_r"] = true.into();
+            let res = prepare_request(&state, &server.api_url(path), data.to_owned())
+                .unwrap()
+                .send()
+                .await
+                .unwrap();
+            let api_path = path.replacen("/eapi", "/api", 1);
+            assert_eq!(res, server.fixture(&api_path));
+
+            let req = server.requests().pop().unwrap();
+            assert_eq!(req.path, api_path);
+            assert_eq!(req.data["e_r"], true);
+            for (key, value) in data.as_object().unwrap() {
+                assert_eq!(&req.data[key], value, "{path} 的 {key} 不一致");
+            }
+            // eapi 请求会在参数和 Cookie 中附加客户端信息
+            assert!(req.data["header"].is_object(), "{path} 没有 header");
+            assert!(req.cookie.contains("appver="), "{}", req.cookie);
+        }
+    }
+
+    #[tokio::test]
+    async fn prepare_request_routes_by_path_prefix() {
+        let server = MockServer::start().await;
+        let state = server.app_state();
+        state.http.cookie_jar().store_response_cookies(
+            &server.base_url,
+            ["__csrf=csrf-value; Path=/; Max-Age=3600"],
+        );
+        let data = json!({ "id": 1 });
+        for path in [
+            "/weapi/song/lyric",
+            "/api/linux/forward/song/lyric",
+            "/eapi/song/lyric",
+        ] {
+            let _ = prepare_request(&state, &server.url(path), data.to_owned())
+                .unwrap()
+                .send()
+                .await;
+        }
+        let reqs = server.http_requests();
+        let form = |i: usize, key: &str| -> String {
+            let body = String::from_utf8_lossy(&reqs[i].body).to_string();
+            body.split('&')
+                .find_map(|x| x.strip_prefix(key)?.strip_prefix('='))
+                .map(String::from)
+                .unwrap_or_default()
+        };
+        assert_eq!(reqs.len(), 3);
+
+        assert_eq!(reqs[0].path, "/weapi/song/lyric");
+        assert!(!form(0, "params").is_empty());
+        assert_eq!(form(0, "encSecKey").len(), 256);
+        assert!(form(0, "eparams").is_empty());
+
+        // linuxapi 统一转发到 /api/linux/forward，真正的接口在加密的 eparams 中
+        assert_eq!(reqs[1].path, "/api/linux/forward");
+        let user_agents = reqs[1]
+            .headers
+            .iter()
+            .filter(|x| x.0.eq_ignore_ascii_case("user-agent"))
+            .map(|x| x.1.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(user_agents, [LINUXAPI_USER_AGENT]);
+        assert_eq!(
+            form(1, "eparams"),
+            linuxapi_encrypt_for_request(&server.url("/api/song/lyric"), &data)
+        );
+
+        assert_eq!(reqs[2].path, "/eapi/song/lyric");
+        assert!(form(2, "encSecKey").is_empty());
+        let (api_path, sent) = decrypt_params(&form(2, "params")).unwrap();
+        assert_eq!(api_path, "/api/song/lyric");
+        assert_eq!(sent["id"], 1);
+        assert!(sent["header"].is_object());
+    }
+
+    #[tokio::test]
+    async fn eapi_request_reports_unknown_api() {
+        let server = MockServer::start().await;
+        let state = server.app_state();
+        let res = prepare_request(
+            &state,
+            &server.url("/eapi/not/exist"),
+            json!({ "e_r": true }),
+        )
+        .unwrap()
+        .send()
         .await
-        .map_err(|x| x.to_string())
+        .unwrap();
+        assert_eq!(res["code"], 404);
+    }
 }
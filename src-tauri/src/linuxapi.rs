@@ -0,0 +1,50 @@
+use libaes::Cipher;
+use once_cell::unsync::Lazy;
+
+const LINUXAPI_KEY: &[u8; 16] = b"rFgB&h#%2?^eDg:Q";
+/// linuxapi 请求需要使用 Linux 客户端的 UA，否则会被拒绝
+pub const LINUXAPI_USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/60.0.3112.90 Safari/537.36";
+std::thread_local! {
+    static LINUXAPI_CIPHER: Lazy<Cipher> = Lazy::new(|| Cipher::new_128(LINUXAPI_KEY));
+}
+
+/// 加密后转换为大写十六进制字符串
+pub fn linuxapi_encrypt(data: &str) -> String {
+    LINUXAPI_CIPHER.with(|c| faster_hex::hex_string(&c.ebc_encrypt(data.as_bytes())).to_uppercase())
+}
+
+/// 将真正要请求的接口和参数包装后加密，作为 `eparams` 字段提交到 `/api/linux/forward`
+pub fn linuxapi_encrypt_for_request(url: &str, params: &serde_json::Value) -> String {
+    let envelope = serde_json::json!({
+        "method": "POST",
+        "url": url,
+        "params": params,
+    });
+    linuxapi_encrypt(&envelope.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// 由 OpenSSL 的 AES-128-ECB 独立计算得到，明文为
+    /// `{"method":"POST","params":{"id":1},"url":"https://music.163.com/api/song/lyric"}`
+    const LYRIC_EPARAMS: &str = concat!(
+        "A0D9583F4C5FF68DE851D2893A49DE986BDC9600830840B64DD89587B2E6895C",
+        "C981F2F7ABE5ECFBBA8487F6A7CD170E4ACA2781E12D493F813B3A51892C6AE1",
+        "F7BC213500789A6C4CF5F66B407B1A2BE254137CD4DCFEF7E51A9955D586B94F",
+    );
+
+    #[test]
+    fn linuxapi_encrypt_for_request_known_answer() {
+        assert_eq!(
+            linuxapi_encrypt_for_request(
+                "https://music.163.com/api/song/lyric",
+                &json!({ "id": 1 })
+            ),
+            LYRIC_EPARAMS
+        );
+    }
+}
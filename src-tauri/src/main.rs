@@ -1,31 +1,26 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio;
+mod client;
+mod cookie_jar;
 mod eapi;
+mod linuxapi;
+#[cfg(test)]
+mod mock_server;
 mod ncm;
+mod ncm_api;
+mod network;
 mod rc4;
+mod session;
+mod throttle;
+mod weapi;
 
-use std::sync::Mutex;
-
-use attohttpc::Session;
+use session::HttpSession;
 use tauri::*;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct AppState {
-    pub cookie: Mutex<String>,
-    pub session: Mutex<Session>,
-}
-
-impl Default for AppState {
-    fn default() -> Self {
-        let mut session = Session::new();
-        session.header("origin", "orpheus://orpheus");
-        session.header("user-agent", "Mozilla/5.0 (Windows NT 10.0; WOW64) AppleWebKit/537.36 (KHTML, like Gecko) Safari/537.36 Chrome/91.0.4472.164 NeteaseMusicDesktop/2.10.7.200791");
-        Self {
-            cookie: Mutex::new("".into()),
-            session: Mutex::new(session),
-        }
-    }
+    pub http: HttpSession,
 }
 
 fn recreate_window(app: &AppHandle) {
@@ -79,7 +74,36 @@ fn main() {
             eapi::tauri_eapi_request,
             eapi::tauri_eapi_encrypt_for_request,
             audio::init_audio_thread,
+            audio::get_audio_peaks,
             audio::send_msg_to_audio_thread,
+            client::get_client_profile,
+            client::set_client_profile,
+            session::set_cookie,
+            session::clear_cookies,
+            session::cancel_request,
+            network::get_network_settings,
+            network::set_network_settings,
+            ncm_api::ncm_get_account,
+            ncm_api::ncm_get_song_detail,
+            ncm_api::ncm_get_player_url,
+            ncm_api::ncm_get_lyric,
+            ncm_api::ncm_get_playlist_detail,
+            ncm_api::ncm_get_playlist_tracks,
+            ncm_api::ncm_get_user_playlists,
+            ncm_api::ncm_get_liked_songs,
+            ncm_api::ncm_like_song,
+            ncm_api::ncm_search,
+            ncm_api::ncm_get_album,
+            ncm_api::ncm_get_artist,
+            ncm_api::ncm_get_daily_songs,
+            ncm_api::ncm_login_qr_create,
+            ncm_api::ncm_login_qr_wait,
+            ncm_api::ncm_login_cellphone,
+            ncm_api::ncm_login_email,
+            ncm_api::ncm_captcha_send,
+            ncm_api::ncm_captcha_verify,
+            ncm_api::ncm_login_refresh,
+            ncm_api::ncm_logout,
         ])
         .on_system_tray_event(|app, event| match event {
             tauri::SystemTrayEvent::DoubleClick { .. } => {
@@ -103,6 +127,12 @@ fn main() {
             _ => {}
         })
         .setup(|app| {
+            let path_resolver = app.path_resolver();
+            if let (Some(config_dir), Some(data_dir)) =
+                (path_resolver.app_config_dir(), path_resolver.app_data_dir())
+            {
+                app.state::<AppState>().http.load(&config_dir, &data_dir);
+            }
             recreate_window(&app.handle());
             Ok(())
         })
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::eapi::{eapi_decrypt, eapi_encrypt};
+use crate::network::NetworkSettings;
+use crate::session::API_DOMAIN;
+use crate::AppState;
+
+/// 录制的响应，键为 eapi 参数中的接口路径，`{{base}}` 会被替换为服务器地址
+const FIXTURES: [(&str, &str); 3] = [
+    (
+        "/api/song/enhance/player/url/v1",
+        include_str!("../tests/fixtures/eapi/player_url.json"),
+    ),
+    (
+        "/api/v3/song/detail",
+        include_str!("../tests/fixtures/eapi/song_detail.json"),
+    ),
+    (
+        "/api/v6/playlist/detail",
+        include_str!("../tests/fixtures/eapi/playlist_detail.json"),
+    ),
+];
+/// `/audio/` 下的测试音频大小，与 `player_url.json` 中的 `size` 相同
+const AUDIO_SIZE: usize = 256 * 1024;
+
+/// 测试音频的内容
+pub fn audio_data() -> Vec<u8> {
+    (0..AUDIO_SIZE).map(|x| (x % 251) as u8).collect()
+}
+
+/// 解密 eapi 请求的 `params`，校验 md5 后返回接口路径和提交的数据
+pub fn decrypt_params(params: &str) -> anyhow::Result<(String, serde_json::Value)> {
+    let mut buf = vec![0; params.len() / 2];
+    faster_hex::hex_decode(params.as_bytes(), &mut buf).context("params 不是十六进制")?;
+    let plain = eapi_decrypt(&buf).context("params 解密失败")?;
+    let plain = String::from_utf8(plain).context("params 不是 UTF-8")?;
+    let mut parts = plain.split("-36cd479b6b5-");
+    let (Some(path), Some(data), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("params 格式不正确 {plain}");
+    };
+    let expected = md5::compute(concat_string::concat_string!(
+        "nobody",
+        path,
+        "use",
+        data,
+        "md5forencrypt"
+    ));
+    anyhow::ensure!(
+        hash == faster_hex::hex_string(expected.as_slice()),
+        "params 的 md5 不匹配 {hash}"
+    );
+    let data = serde_json::from_str(data).context("params 中的数据不是 JSON")?;
+    Ok((path.to_owned(), data))
+}
+
+/// 服务器收到的 eapi 请求
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    /// 加密参数中的接口路径，例如 `/api/v3/song/detail`
+    pub path: String,
+    pub data: serde_json::Value,
+    pub cookie: String,
+}
+
+/// 模拟网易云 eapi 接口的本地 HTTP 服务器，仅用于测试
+///
+/// `/eapi/*` 的请求会解密 `params` 并校验 md5，然后返回 eapi 加密后的录制响应，
+/// 与请求带有 `e_r` 时服务器的行为相同。`/audio/*` 返回 [`audio_data`]。
+/// 通过 [`MockServer::respond`] 设置的响应优先于录制的响应。
+/// `/weapi/*` 和 `/api/linux/forward` 无法在服务器端解密，只记录请求并返回 `{"code":200}`。
+pub struct MockServer {
+    pub base_url: String,
+    state: Arc<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    requests: Mutex<Vec<MockRequest>>,
+    http_requests: Mutex<Vec<HttpRequest>>,
+    responses: Mutex<HashMap<String, MockResponse>>,
+}
+
+/// 测试中指定的接口响应
+#[derive(Debug, Clone)]
+struct MockResponse {
+    body: serde_json::Value,
+    set_cookies: Vec<String>,
+}
+
+/// 服务器收到的原始 HTTP 请求，`path` 是请求行中的链接，经过代理时为完整的链接
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// 不区分大小写地查找请求头，不存在时返回空字符串
+    pub fn header(&self, name: &str) -> &str {
+        self.headers
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(name))
+            .map(|x| x.1.as_str())
+            .unwrap_or_default()
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<HttpRequest> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        anyhow::ensure!(n > 0, "连接在请求头结束前关闭");
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(x) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+            break x;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let path = request_line.next().unwrap_or_default().to_owned();
+    let headers = lines
+        .filter_map(|x| x.split_once(':'))
+        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+        .collect::<Vec<_>>();
+    let mut req = HttpRequest {
+        method,
+        path,
+        headers,
+        body: buf[head_end + 4..].to_vec(),
+    };
+    let len = req.header("content-length").parse::<usize>().unwrap_or(0);
+    while req.body.len() < len {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        anyhow::ensure!(n > 0, "连接在请求内容结束前关闭");
+        req.body.extend_from_slice(&chunk[..n]);
+    }
+    Ok(req)
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: u16,
+    set_cookies: &[String],
+    body: &[u8],
+) -> anyhow::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {status} MOCK\r\ncontent-length: {}\r\n",
+        body.len()
+    );
+    for cookie in set_cookies {
+        head.push_str(&format!("set-cookie: {cookie}\r\n"));
+    }
+    head.push_str("connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    Ok(())
+}
+
+impl MockServer {
+    /// 在随机端口上启动服务器，需要在 tokio 运行时中调用
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("无法启动模拟服务器");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(MockState::default());
+        let server = Self {
+            base_url: base_url.to_owned(),
+            state: state.clone(),
+        };
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let base_url = base_url.to_owned();
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let Ok(req) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    state.http_requests.lock().unwrap().push(req.to_owned());
+                    let (status, set_cookies, body) = Self::handle(&base_url, &state, req);
+                    let _ = write_response(&mut stream, status, &set_cookies, &body).await;
+                });
+            }
+        });
+        server
+    }
+
+    fn handle(base_url: &str, state: &MockState, req: HttpRequest) -> (u16, Vec<String>, Vec<u8>) {
+        if req.method == "GET" && req.path.starts_with("/audio/") {
+            return (200, vec![], audio_data());
+        }
+        if req.method == "POST"
+            && (req.path.starts_with("/weapi/") || req.path == "/api/linux/forward")
+        {
+            return (200, vec![], br#"{"code":200}"#.to_vec());
+        }
+        if req.method != "POST" || !req.path.starts_with("/eapi/") {
+            return (404, vec![], b"not found".to_vec());
+        }
+        let params = String::from_utf8_lossy(&req.body)
+            .split('&')
+            .find_map(|x| x.strip_prefix("params="))
+            .unwrap_or_default()
+            .to_owned();
+        let (path, data) = match decrypt_params(&params) {
+            Ok(x) => x,
+            Err(err) => return (400, vec![], format!("{err:#}").into_bytes()),
+        };
+        if req.path.strip_prefix("/eapi") != path.strip_prefix("/api") {
+            return (
+                400,
+                vec![],
+                format!("链接与 params 中的路径不一致 {path}").into_bytes(),
+            );
+        }
+        let encrypted = data["e_r"] == true;
+        state.requests.lock().unwrap().push(MockRequest {
+            path: path.to_owned(),
+            data,
+            cookie: req.header("cookie").to_owned(),
+        });
+        if let Some(res) = state.responses.lock().unwrap().get(&path) {
+            let body = res.body.to_string();
+            let body = if encrypted {
+                eapi_encrypt(&body)
+            } else {
+                body.into_bytes()
+            };
+            return (200, res.set_cookies.to_owned(), body);
+        }
+        match FIXTURES.iter().find(|x| x.0 == path) {
+            Some((_, fixture)) => (
+                200,
+                vec![],
+                eapi_encrypt(&fixture.replace("{{base}}", base_url)),
+            ),
+            None => (
+                404,
+                vec![],
+                eapi_encrypt(r#"{"code":404,"message":"接口不存在"}"#),
+            ),
+        }
+    }
+
+    /// 之后 `api_path` 接口返回 `body` 和 `set_cookies` 中的 Cookie，请求带有 `e_r` 时会加密
+    pub fn respond(&self, api_path: &str, body: serde_json::Value, set_cookies: &[&str]) {
+        self.state.responses.lock().unwrap().insert(
+            api_path.to_owned(),
+            MockResponse {
+                body,
+                set_cookies: set_cookies.iter().map(|x| x.to_string()).collect(),
+            },
+        );
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        concat_string::concat_string!(self.base_url, path)
+    }
+
+    /// 以接口服务器的域名访问模拟服务器的链接，需要使用 [`MockServer::app_state`] 中的域名解析覆盖
+    pub fn api_url(&self, path: &str) -> String {
+        let port = self.base_url.rsplit(':').next().unwrap();
+        format!("http://{API_DOMAIN}:{port}{path}")
+    }
+
+    /// 录制的响应，`{{base}}` 已经替换为服务器地址
+    pub fn fixture(&self, path: &str) -> serde_json::Value {
+        let fixture = FIXTURES
+            .iter()
+            .find(|x| x.0 == path)
+            .expect("没有这个接口的录制响应")
+            .1
+            .replace("{{base}}", &self.base_url);
+        serde_json::from_str(&fixture).unwrap()
+    }
+
+    /// 到目前为止收到的 eapi 请求
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.requests.lock().unwrap().to_owned()
+    }
+
+    /// 到目前为止收到的全部 HTTP 请求，包括无法解析的请求
+    pub fn http_requests(&self) -> Vec<HttpRequest> {
+        self.state.http_requests.lock().unwrap().to_owned()
+    }
+
+    /// 不使用任何代理的会话，避免测试环境中的代理环境变量影响结果，接口服务器的域名会解析到本机
+    pub fn app_state(&self) -> AppState {
+        let state = AppState::default();
+        state
+            .http
+            .set_network(NetworkSettings {
+                direct: true,
+                dns_overrides: [(API_DOMAIN.to_owned(), "127.0.0.1".to_owned())].into(),
+                ..Default::default()
+            })
+            .unwrap();
+        state
+    }
+}
+
+/// 转发到 [`MockServer`] 的 HTTP 代理，记录收到的请求，仅用于测试
+///
+/// 只支持 `http://` 链接的转发，不支持 `CONNECT`。不论请求的是哪个主机，都会转发到同一个服务器。
+pub struct MockProxy {
+    pub url: String,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+}
+
+impl MockProxy {
+    /// 在随机端口上启动代理，`upstream` 为服务器的地址，例如 [`MockServer::base_url`]
+    pub async fn start(upstream: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("无法启动模拟代理");
+        let upstream = upstream
+            .strip_prefix("http://")
+            .expect("只支持 http:// 的服务器")
+            .to_owned();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let proxy = Self {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            requests: requests.clone(),
+        };
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let upstream = upstream.to_owned();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let Ok(req) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    requests.lock().unwrap().push(req.to_owned());
+                    if let Err(err) = Self::forward(&upstream, &mut stream, req).await {
+                        let _ =
+                            write_response(&mut stream, 502, &[], err.to_string().as_bytes()).await;
+                    }
+                });
+            }
+        });
+        proxy
+    }
+
+    async fn forward(
+        upstream: &str,
+        stream: &mut TcpStream,
+        req: HttpRequest,
+    ) -> anyhow::Result<()> {
+        // 代理收到的是完整的链接，转发时只保留路径
+        let path = req
+            .path
+            .strip_prefix("http://")
+            .and_then(|x| x.find('/').map(|i| &x[i..]))
+            .context("代理请求的链接不是完整的 http:// 链接")?;
+        let mut head = format!("{} {path} HTTP/1.1\r\n", req.method);
+        for (key, value) in &req.headers {
+            if !key.eq_ignore_ascii_case("proxy-connection") {
+                head.push_str(&format!("{key}: {value}\r\n"));
+            }
+        }
+        head.push_str("\r\n");
+        let mut upstream = TcpStream::connect(upstream).await?;
+        upstream.write_all(head.as_bytes()).await?;
+        upstream.write_all(&req.body).await?;
+        // 服务器的响应带有 `connection: close`，原样转发直到连接关闭
+        tokio::io::copy(&mut upstream, stream).await?;
+        Ok(())
+    }
+
+    /// 代理收到的请求，`path` 为完整的链接
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().to_owned()
+    }
+}
@@ -0,0 +1,454 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{check_code, run, AccountInfo, NCMApi, NCMApiError, NCMResult};
+
+/// 查询二维码状态的间隔
+const QR_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// 没有指定国家码时使用中国大陆的国家码
+const DEFAULT_COUNTRY_CODE: &str = "86";
+
+/// 新创建的登录二维码，前端需要把 `url` 生成二维码后让用户用手机客户端扫描
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QrLogin {
+    pub unikey: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct QrKeyResponse {
+    unikey: String,
+}
+
+/// 二维码登录的状态
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum QrLoginStatus {
+    /// 等待扫码
+    Waiting,
+    /// 已扫码，等待用户在手机上确认
+    #[serde(rename_all = "camelCase")]
+    Scanned {
+        nickname: Option<String>,
+        avatar_url: Option<String>,
+    },
+    /// 已确认登录，Cookie 已经保存到会话中
+    Confirmed,
+    /// 二维码已过期，需要重新创建
+    Expired,
+}
+
+/// 二维码状态变化时发送给前端的 `on-qr-login-event` 事件
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QrLoginEvent {
+    pub unikey: String,
+    pub status: QrLoginStatus,
+}
+
+fn md5_hex(data: &str) -> String {
+    faster_hex::hex_string(md5::compute(data).as_slice())
+}
+
+impl NCMApi {
+    /// 创建登录二维码
+    pub async fn qr_login_create(&self) -> NCMResult<QrLogin> {
+        let res: QrKeyResponse = self
+            .request(
+                "https://music.163.com/eapi/login/qrcode/unikey",
+                json!({ "type": 3 }),
+            )
+            .await?;
+        Ok(QrLogin {
+            url: format!("https://music.163.com/login?codekey={}", res.unikey),
+            unikey: res.unikey,
+        })
+    }
+
+    /// 查询一次二维码的状态，确认登录时响应中的 Cookie 会保存到会话中
+    pub async fn qr_login_check(&self, unikey: &str) -> NCMResult<QrLoginStatus> {
+        let res = self
+            .request_raw(
+                "https://music.163.com/eapi/login/qrcode/client/login",
+                json!({
+                    "key": unikey,
+                    "type": 3,
+                }),
+            )
+            .await?;
+        let text = |key: &str| res.get(key).and_then(|x| x.as_str()).map(String::from);
+        match res.get("code").and_then(|x| x.as_i64()) {
+            Some(800) => Ok(QrLoginStatus::Expired),
+            Some(801) => Ok(QrLoginStatus::Waiting),
+            Some(802) => Ok(QrLoginStatus::Scanned {
+                nickname: text("nickname"),
+                avatar_url: text("avatarUrl"),
+            }),
+            Some(803) => Ok(QrLoginStatus::Confirmed),
+            _ => {
+                check_code(&res)?;
+                Err(NCMApiError::Decode {
+                    error: format!("未知的二维码状态 {res}"),
+                })
+            }
+        }
+    }
+
+    /// 轮询二维码状态直到确认登录或者二维码过期，状态变化时发送 `on-qr-login-event` 事件
+    pub async fn qr_login_wait(&self, unikey: &str) -> NCMResult<AccountInfo> {
+        let mut last_status = None;
+        loop {
+            let status = self.qr_login_check(unikey).await?;
+            if last_status.as_ref() != Some(&status) {
+                self.emit(
+                    "on-qr-login-event",
+                    QrLoginEvent {
+                        unikey: unikey.to_owned(),
+                        status: status.to_owned(),
+                    },
+                );
+            }
+            match status {
+                QrLoginStatus::Confirmed => return self.account().await,
+                QrLoginStatus::Expired => {
+                    return Err(NCMApiError::Api {
+                        code: 800,
+                        message: Some("二维码已过期".into()),
+                    })
+                }
+                _ => {
+                    self.cancellable(tokio::time::sleep(QR_POLL_INTERVAL))
+                        .await?
+                }
+            }
+            last_status = Some(status);
+        }
+    }
+
+    /// 手机号登录，`captcha` 不为空时使用短信验证码登录，否则使用密码登录
+    pub async fn login_cellphone(
+        &self,
+        phone: &str,
+        country_code: &str,
+        password: &str,
+        captcha: &str,
+    ) -> NCMResult<AccountInfo> {
+        let mut data = json!({
+            "type": "1",
+            "https": "true",
+            "phone": phone,
+            "countrycode": country_code,
+            "remember": "true",
+        });
+        if captcha.is_empty() {
+            data["password"] = md5_hex(password).into();
+        } else {
+            data["captcha"] = captcha.into();
+        }
+        self.request("https://music.163.com/eapi/login/cellphone", data)
+            .await
+    }
+
+    /// 网易邮箱登录
+    pub async fn login_email(&self, email: &str, password: &str) -> NCMResult<AccountInfo> {
+        self.request(
+            "https://music.163.com/eapi/login",
+            json!({
+                "type": "0",
+                "https": "true",
+                "username": email,
+                "password": md5_hex(password),
+                "rememberLogin": "true",
+            }),
+        )
+        .await
+    }
+
+    /// 向手机发送短信验证码
+    pub async fn captcha_send(&self, phone: &str, country_code: &str) -> NCMResult<()> {
+        self.request::<serde_json::Value>(
+            "https://music.163.com/weapi/sms/captcha/sent",
+            json!({
+                "cellphone": phone,
+                "ctcode": country_code,
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// 校验短信验证码，验证码不正确时返回接口错误
+    pub async fn captcha_verify(
+        &self,
+        phone: &str,
+        country_code: &str,
+        captcha: &str,
+    ) -> NCMResult<()> {
+        self.request::<serde_json::Value>(
+            "https://music.163.com/weapi/sms/captcha/verify",
+            json!({
+                "cellphone": phone,
+                "captcha": captcha,
+                "ctcode": country_code,
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// 刷新登录状态，延长 Cookie 的有效期
+    pub async fn login_refresh(&self) -> NCMResult<()> {
+        self.request::<serde_json::Value>(
+            "https://music.163.com/eapi/login/token/refresh",
+            json!({}),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// 退出登录并清空会话中的 Cookie，请求失败时也会清空
+    pub async fn logout(&self) {
+        if let Err(err) = self
+            .request::<serde_json::Value>("https://music.163.com/eapi/logout", json!({}))
+            .await
+        {
+            println!("[WARN] 退出登录请求失败 {err}");
+        }
+        self.with_state(|state| state.http.cookie_jar().clear());
+    }
+}
+
+#[tauri::command]
+pub async fn ncm_login_qr_create(app: tauri::AppHandle) -> NCMResult<QrLogin> {
+    NCMApi::new(app).qr_login_create().await
+}
+
+/// 等待扫码登录，可以通过 `request_id` 取消等待
+#[tauri::command]
+pub async fn ncm_login_qr_wait(
+    app: tauri::AppHandle,
+    unikey: String,
+    request_id: Option<String>,
+) -> NCMResult<AccountInfo> {
+    run(app, request_id, |api| async move {
+        api.qr_login_wait(&unikey).await
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn ncm_login_cellphone(
+    app: tauri::AppHandle,
+    phone: String,
+    country_code: Option<String>,
+    password: Option<String>,
+    captcha: Option<String>,
+) -> NCMResult<AccountInfo> {
+    NCMApi::new(app)
+        .login_cellphone(
+            &phone,
+            country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE),
+            password.as_deref().unwrap_or_default(),
+            captcha.as_deref().unwrap_or_default(),
+        )
+        .await
+}
+
+#[tauri::command]
+pub async fn ncm_login_email(
+    app: tauri::AppHandle,
+    email: String,
+    password: String,
+) -> NCMResult<AccountInfo> {
+    NCMApi::new(app).login_email(&email, &password).await
+}
+
+#[tauri::command]
+pub async fn ncm_captcha_send(
+    app: tauri::AppHandle,
+    phone: String,
+    country_code: Option<String>,
+) -> NCMResult<()> {
+    NCMApi::new(app)
+        .captcha_send(
+            &phone,
+            country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE),
+        )
+        .await
+}
+
+#[tauri::command]
+pub async fn ncm_captcha_verify(
+    app: tauri::AppHandle,
+    phone: String,
+    country_code: Option<String>,
+    captcha: String,
+) -> NCMResult<()> {
+    NCMApi::new(app)
+        .captcha_verify(
+            &phone,
+            country_code.as_deref().unwrap_or(DEFAULT_COUNTRY_CODE),
+            &captcha,
+        )
+        .await
+}
+
+#[tauri::command]
+pub async fn ncm_login_refresh(app: tauri::AppHandle) -> NCMResult<()> {
+    NCMApi::new(app).login_refresh().await
+}
+
+#[tauri::command]
+pub async fn ncm_logout(app: tauri::AppHandle) {
+    NCMApi::new(app).logout().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::cookie_jar::CookieJar;
+    use crate::mock_server::MockServer;
+
+    const QR_CHECK_PATH: &str = "/api/login/qrcode/client/login";
+
+    fn account_response() -> serde_json::Value {
+        json!({
+            "code": 200,
+            "account": { "id": 10086, "userName": "1_13800000000" },
+            "profile": { "userId": 10086, "nickname": "测试用户" },
+        })
+    }
+
+    #[tokio::test]
+    async fn qr_login_check_maps_status_codes() {
+        let server = MockServer::start().await;
+        let api = NCMApi::mock(Arc::new(server.app_state()), &server.base_url);
+        let cases = [
+            (
+                json!({ "code": 800, "message": "二维码不存在或已过期" }),
+                QrLoginStatus::Expired,
+            ),
+            (
+                json!({ "code": 801, "message": "等待扫码" }),
+                QrLoginStatus::Waiting,
+            ),
+            (
+                json!({ "code": 802, "nickname": "测试用户", "avatarUrl": "https://p1.music.126.net/a.jpg" }),
+                QrLoginStatus::Scanned {
+                    nickname: Some("测试用户".into()),
+                    avatar_url: Some("https://p1.music.126.net/a.jpg".into()),
+                },
+            ),
+            (
+                json!({ "code": 803, "message": "授权登陆成功" }),
+                QrLoginStatus::Confirmed,
+            ),
+        ];
+        for (res, expected) in cases {
+            server.respond(QR_CHECK_PATH, res, &[]);
+            assert_eq!(api.qr_login_check("qr-key").await.unwrap(), expected);
+        }
+        let req = server.requests().pop().unwrap();
+        assert_eq!(req.path, QR_CHECK_PATH);
+        assert_eq!(req.data["key"], "qr-key");
+        assert_eq!(req.data["type"], 3);
+
+        server.respond(
+            QR_CHECK_PATH,
+            json!({ "code": 8821, "message": "风控" }),
+            &[],
+        );
+        assert!(matches!(
+            api.qr_login_check("qr-key").await,
+            Err(NCMApiError::Api { code: 8821, .. })
+        ));
+        server.respond(QR_CHECK_PATH, json!({ "code": 200 }), &[]);
+        assert!(matches!(
+            api.qr_login_check("qr-key").await,
+            Err(NCMApiError::Decode { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn login_hashes_password_with_md5() {
+        let server = MockServer::start().await;
+        let api = NCMApi::mock(Arc::new(server.app_state()), &server.base_url);
+        server.respond("/api/login/cellphone", account_response(), &[]);
+        server.respond("/api/login", account_response(), &[]);
+
+        let account = api
+            .login_cellphone("13800000000", "86", "password", "")
+            .await
+            .unwrap();
+        assert_eq!(account.account.unwrap().id, 10086);
+        let req = server.requests().pop().unwrap();
+        assert_eq!(req.path, "/api/login/cellphone");
+        assert_eq!(req.data["phone"], "13800000000");
+        assert_eq!(req.data["countrycode"], "86");
+        assert_eq!(req.data["password"], "5f4dcc3b5aa765d61d8327deb882cf99");
+        assert!(req.data.get("captcha").is_none());
+
+        api.login_cellphone("13800000000", "86", "", "1234")
+            .await
+            .unwrap();
+        let req = server.requests().pop().unwrap();
+        assert_eq!(req.data["captcha"], "1234");
+        assert!(req.data.get("password").is_none());
+
+        api.login_email("test@163.com", "中文密码").await.unwrap();
+        let req = server.requests().pop().unwrap();
+        assert_eq!(req.path, "/api/login");
+        assert_eq!(req.data["username"], "test@163.com");
+        assert_eq!(req.data["password"], "3d4acf94adca8562b4990599b15488de");
+    }
+
+    #[tokio::test]
+    async fn login_cookies_are_saved_and_cleared_on_logout() {
+        let dir = std::env::temp_dir().join(format!("mrbncm-login-{}", rand::random::<u64>()));
+        let load_jar = || CookieJar::load(&dir.join("cookie-jar.bin"), &dir.join("cookie-jar.key"));
+        let server = MockServer::start().await;
+        let state = Arc::new(server.app_state());
+        *state.http.cookie_jar() = load_jar();
+        let api = NCMApi::mock(state.clone(), &server.base_url);
+        server.respond(
+            "/api/login/cellphone",
+            account_response(),
+            &[
+                "MUSIC_U=secret; Max-Age=3600; Path=/; HttpOnly",
+                "__csrf=token; Max-Age=3600; Path=/",
+            ],
+        );
+        server.respond("/api/login/token/refresh", json!({ "code": 200 }), &[]);
+        let api_url = server.url("/eapi/login/token/refresh");
+
+        api.login_cellphone("13800000000", "86", "password", "")
+            .await
+            .unwrap();
+        // 重新读取文件，确认 Cookie 已经保存下来
+        let saved = load_jar().cookie_header(&api_url);
+        assert!(saved.contains("MUSIC_U=secret"), "{saved}");
+        assert!(saved.contains("__csrf=token"), "{saved}");
+
+        api.login_refresh().await.unwrap();
+        let req = server.requests().pop().unwrap();
+        assert!(req.cookie.contains("MUSIC_U=secret"), "{}", req.cookie);
+
+        // 没有设置退出登录的响应，接口返回 404，Cookie 也应该被清空
+        api.logout().await;
+        assert_eq!(state.http.cookie_jar().cookie_header(&api_url), "");
+        assert_eq!(load_jar().cookie_header(&api_url), "");
+        api.login_refresh().await.unwrap();
+        let req = server.requests().pop().unwrap();
+        assert!(!req.cookie.contains("MUSIC_U"), "{}", req.cookie);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
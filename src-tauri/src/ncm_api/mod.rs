@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+#[cfg(test)]
+use std::sync::Arc;
+
+use futures_util::future::try_join_all;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tauri::Manager;
+use tokio_util::sync::CancellationToken;
+
+use crate::eapi::RequestFailure;
+use crate::AppState;
+
+mod login;
+mod models;
+
+pub use login::*;
+pub use models::*;
+
+/// 带有 `data` 字段的接口响应
+#[derive(serde::Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct NCMResponse<T> {
+    pub data: Option<T>,
+    pub code: i32,
+}
+
+impl<T> NCMResponse<T> {
+    pub fn into_data(self) -> Result<T, NCMApiError> {
+        self.data.ok_or(NCMApiError::EmptyData)
+    }
+}
+
+/// 调用网易云接口时可能发生的错误
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", tag = "type", content = "data")]
+pub enum NCMApiError {
+    /// 请求没有发送成功，或者响应无法解密解析
+    #[serde(rename_all = "camelCase")]
+    Request { error: String },
+    /// 接口返回的 `code` 不是 200
+    #[serde(rename_all = "camelCase")]
+    Api { code: i32, message: Option<String> },
+    /// 接口返回了 301，需要登录后才能使用
+    NeedLogin,
+    /// 响应的结构和预期的不一致
+    #[serde(rename_all = "camelCase")]
+    Decode { error: String },
+    /// 响应中缺少 `data` 字段
+    EmptyData,
+    /// 请求被前端或音频线程取消
+    Cancelled,
+    /// 重试后仍然触发了网易云的频率限制
+    Throttled,
+    /// 连续请求失败，暂时不再发送请求，`retry_after` 为大约还需要等待的秒数
+    #[serde(rename_all = "camelCase")]
+    Unavailable { retry_after: u64 },
+}
+
+impl std::fmt::Display for NCMApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request { error } => write!(f, "请求失败 {error}"),
+            Self::Api {
+                code,
+                message: Some(message),
+            } => write!(f, "接口返回错误 {code} {message}"),
+            Self::Api {
+                code,
+                message: None,
+            } => write!(f, "接口返回错误 {code}"),
+            Self::NeedLogin => write!(f, "需要登录"),
+            Self::Decode { error } => write!(f, "无法解析响应数据 {error}"),
+            Self::EmptyData => write!(f, "响应中没有数据"),
+            Self::Cancelled => write!(f, "请求已取消"),
+            Self::Throttled => write!(f, "请求过于频繁，请稍后再试"),
+            Self::Unavailable { retry_after } => {
+                write!(f, "服务暂时不可用，请在 {retry_after} 秒后重试")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NCMApiError {}
+
+impl From<anyhow::Error> for NCMApiError {
+    fn from(err: anyhow::Error) -> Self {
+        match RequestFailure::of(&err) {
+            Some(failure) if failure.is_throttled() => Self::Throttled,
+            Some(RequestFailure::Unavailable(retry_after)) => Self::Unavailable { retry_after },
+            _ => Self::Request {
+                error: format!("{err:#}"),
+            },
+        }
+    }
+}
+
+pub type NCMResult<T> = Result<T, NCMApiError>;
+
+/// 检查响应中的 `code`，不是 200 时返回对应的错误，没有 `code` 的响应视为成功
+fn check_code(res: &serde_json::Value) -> NCMResult<()> {
+    let Some(code) = res.get("code").and_then(|x| x.as_i64()) else {
+        return Ok(());
+    };
+    match code {
+        200 => Ok(()),
+        301 => Err(NCMApiError::NeedLogin),
+        code => Err(NCMApiError::Api {
+            code: code as i32,
+            message: ["message", "msg"]
+                .into_iter()
+                .find_map(|x| res.get(x).and_then(|x| x.as_str()))
+                .map(String::from),
+        }),
+    }
+}
+
+/// 网易云音乐接口，使用 `AppState` 中的会话、Cookie 和客户端身份发送请求
+///
+/// 所有方法都是异步的，在 Tauri 的异步运行时上执行，不会占用阻塞线程池。
+#[derive(Clone)]
+pub struct NCMApi {
+    backend: Backend,
+    cancel: Option<CancellationToken>,
+}
+
+/// 请求使用的会话，以及接收事件的前端
+#[derive(Clone)]
+enum Backend {
+    App(tauri::AppHandle),
+    /// 测试时使用独立的会话，并把 `https://music.163.com` 的请求发到模拟服务器
+    #[cfg(test)]
+    Mock {
+        state: Arc<AppState>,
+        base_url: String,
+    },
+}
+
+impl NCMApi {
+    pub fn new(app: tauri::AppHandle) -> Self {
+        Self {
+            backend: Backend::App(app),
+            cancel: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn mock(state: Arc<AppState>, base_url: &str) -> Self {
+        Self {
+            backend: Backend::Mock {
+                state,
+                base_url: base_url.to_owned(),
+            },
+            cancel: None,
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&AppState) -> R) -> R {
+        match &self.backend {
+            Backend::App(app) => f(&app.state::<AppState>()),
+            #[cfg(test)]
+            Backend::Mock { state, .. } => f(state),
+        }
+    }
+
+    /// 实际请求的链接，测试时替换为模拟服务器的地址
+    fn resolve_url<'a>(&self, url: &'a str) -> std::borrow::Cow<'a, str> {
+        match &self.backend {
+            Backend::App(_) => url.into(),
+            #[cfg(test)]
+            Backend::Mock { base_url, .. } => {
+                url.replacen("https://music.163.com", base_url, 1).into()
+            }
+        }
+    }
+
+    /// 向前端发送事件，测试时忽略
+    fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) {
+        match &self.backend {
+            Backend::App(app) => {
+                let _ = app.emit_all(event, payload);
+            }
+            #[cfg(test)]
+            Backend::Mock { .. } => {}
+        }
+    }
+
+    /// 之后的请求在 `token` 被取消时立即放弃，并返回 [`NCMApiError::Cancelled`]
+    pub fn with_cancel(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// 发送请求，不检查响应中的 `code`
+    pub async fn request_raw(
+        &self,
+        url: &str,
+        data: serde_json::Value,
+    ) -> NCMResult<serde_json::Value> {
+        let url = self.resolve_url(url);
+        let req = self.with_state(|state| crate::eapi::prepare_request(state, &url, data))?;
+        Ok(self.cancellable(req.send()).await??)
+    }
+
+    /// 执行 `fut`，设置了取消令牌并且被取消时返回 [`NCMApiError::Cancelled`]
+    async fn cancellable<T>(&self, fut: impl std::future::Future<Output = T>) -> NCMResult<T> {
+        match &self.cancel {
+            Some(token) => token
+                .run_until_cancelled(fut)
+                .await
+                .ok_or(NCMApiError::Cancelled),
+            None => Ok(fut.await),
+        }
+    }
+
+    /// 发送请求并检查 `code`，再把响应解析成 `T`
+    pub async fn request<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        data: serde_json::Value,
+    ) -> NCMResult<T> {
+        let res = self.request_raw(url, data).await?;
+        check_code(&res)?;
+        serde_json::from_value(res).map_err(|err| NCMApiError::Decode {
+            error: err.to_string(),
+        })
+    }
+
+    pub async fn account(&self) -> NCMResult<AccountInfo> {
+        self.request("https://music.163.com/api/nuser/account/get", json!({}))
+            .await
+    }
+
+    /// 获取歌曲信息，每次请求最多 1000 首，超出时会同时发出多个请求，返回顺序与 `ids` 不一定相同
+    pub async fn song_detail(&self, ids: &[u64]) -> NCMResult<Vec<SongDetail>> {
+        let batches = ids.chunks(1000).map(|ids| {
+            let c = ids
+                .iter()
+                .map(|id| json!({ "id": id, "v": 0 }))
+                .collect::<Vec<_>>();
+            self.request::<SongDetailResponse>(
+                "https://music.163.com/eapi/v3/song/detail",
+                json!({
+                    "c": serde_json::Value::from(c).to_string(),
+                    "e_r": true,
+                }),
+            )
+        });
+        Ok(try_join_all(batches)
+            .await?
+            .into_iter()
+            .flat_map(|x| x.songs)
+            .collect())
+    }
+
+    /// 获取歌曲的播放链接，`level` 为音质等级，例如 `standard`、`exhigh`、`lossless`、`hires`
+    pub async fn player_url(&self, ids: &[u64], level: &str) -> NCMResult<Vec<SongUrl>> {
+        let ids = ids.iter().map(u64::to_string).collect::<Vec<_>>().join(",");
+        self.request::<NCMResponse<Vec<SongUrl>>>(
+            "https://interface.music.163.com/eapi/song/enhance/player/url/v1",
+            json!({
+                "ids": format!("[{ids}]"),
+                "level": level,
+                "encodeType": "flac",
+            }),
+        )
+        .await?
+        .into_data()
+    }
+
+    pub async fn lyric(&self, id: u64) -> NCMResult<Lyric> {
+        self.request(
+            "https://interface.music.163.com/eapi/song/lyric/v1",
+            json!({
+                "id": id,
+                "cp": false,
+                "lv": 0,
+                "tv": 0,
+                "rv": 0,
+                "yv": 0,
+            }),
+        )
+        .await
+    }
+
+    pub async fn playlist_detail(&self, id: u64) -> NCMResult<PlaylistDetail> {
+        let res: PlaylistDetailResponse = self
+            .request(
+                "https://music.163.com/eapi/v6/playlist/detail",
+                json!({
+                    "id": id,
+                    "n": 100000,
+                    "s": 8,
+                }),
+            )
+            .await?;
+        Ok(res.playlist)
+    }
+
+    /// 获取歌单中从 `offset` 开始的 `limit` 首歌曲，按照歌单中的顺序返回
+    pub async fn playlist_tracks(
+        &self,
+        id: u64,
+        offset: usize,
+        limit: usize,
+    ) -> NCMResult<Vec<SongDetail>> {
+        let playlist = self.playlist_detail(id).await?;
+        let ids = playlist
+            .track_ids
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        let order = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, i))
+            .collect::<HashMap<_, _>>();
+        let mut songs = self.song_detail(&ids).await?;
+        songs.sort_by_key(|x| order.get(&x.id).copied());
+        Ok(songs)
+    }
+
+    pub async fn user_playlists(
+        &self,
+        uid: u64,
+        offset: usize,
+        limit: usize,
+    ) -> NCMResult<UserPlaylists> {
+        self.request(
+            "https://music.163.com/eapi/user/playlist",
+            json!({
+                "uid": uid,
+                "limit": limit,
+                "offset": offset,
+                "includeVideo": true,
+            }),
+        )
+        .await
+    }
+
+    /// 获取用户喜欢的歌曲 ID 列表
+    pub async fn liked_songs(&self, uid: u64) -> NCMResult<Vec<u64>> {
+        let res: LikedSongsResponse = self
+            .request(
+                "https://music.163.com/eapi/song/like/get",
+                json!({ "uid": uid }),
+            )
+            .await?;
+        Ok(res.ids)
+    }
+
+    /// 喜欢或者取消喜欢一首歌曲
+    pub async fn like_song(&self, id: u64, like: bool) -> NCMResult<()> {
+        self.request::<serde_json::Value>(
+            "https://music.163.com/eapi/song/like",
+            json!({
+                "trackId": id,
+                "like": like,
+                "alg": "itembased",
+                "time": 3,
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        keywords: &str,
+        search_type: SearchType,
+        offset: usize,
+        limit: usize,
+    ) -> NCMResult<SearchResult> {
+        let res: SearchResponse = self
+            .request(
+                "https://interface.music.163.com/eapi/cloudsearch/pc",
+                json!({
+                    "s": keywords,
+                    "type": search_type.code(),
+                    "limit": limit,
+                    "offset": offset,
+                    "total": true,
+                }),
+            )
+            .await?;
+        Ok(res.result)
+    }
+
+    pub async fn album(&self, id: u64) -> NCMResult<Album> {
+        self.request(
+            &format!("https://music.163.com/eapi/v1/album/{id}"),
+            json!({}),
+        )
+        .await
+    }
+
+    pub async fn artist(&self, id: u64) -> NCMResult<Artist> {
+        self.request(
+            &format!("https://music.163.com/eapi/v1/artist/{id}"),
+            json!({}),
+        )
+        .await
+    }
+
+    /// 获取每日推荐歌曲，需要登录
+    pub async fn daily_songs(&self) -> NCMResult<Vec<SongDetail>> {
+        let res = self
+            .request::<NCMResponse<DailySongsResponse>>(
+                "https://music.163.com/eapi/v3/discovery/recommend/songs",
+                json!({}),
+            )
+            .await?
+            .into_data()?;
+        Ok(res.daily_songs)
+    }
+}
+
+/// 调用接口，指定 `request_id` 时可以通过 `cancel_request` 取消
+async fn run<T, F>(
+    app: tauri::AppHandle,
+    request_id: Option<String>,
+    f: impl FnOnce(NCMApi) -> F,
+) -> NCMResult<T>
+where
+    F: std::future::Future<Output = NCMResult<T>>,
+{
+    let Some(request_id) = request_id else {
+        return f(NCMApi::new(app)).await;
+    };
+    let (seq, token) = app.state::<AppState>().http.register_request(&request_id);
+    let result = f(NCMApi::new(app.clone()).with_cancel(token)).await;
+    app.state::<AppState>()
+        .http
+        .finish_request(&request_id, seq);
+    result
+}
+
+#[tauri::command]
+pub async fn ncm_get_account(app: tauri::AppHandle) -> NCMResult<AccountInfo> {
+    NCMApi::new(app).account().await
+}
+
+/// `ids` 很多时会分成多个请求同时发送，可以通过 `request_id` 取消
+#[tauri::command]
+pub async fn ncm_get_song_detail(
+    app: tauri::AppHandle,
+    ids: Vec<u64>,
+    request_id: Option<String>,
+) -> NCMResult<Vec<SongDetail>> {
+    run(
+        app,
+        request_id,
+        |api| async move { api.song_detail(&ids).await },
+    )
+    .await
+}
+
+#[tauri::command]
+pub async fn ncm_get_player_url(
+    app: tauri::AppHandle,
+    ids: Vec<u64>,
+    level: String,
+) -> NCMResult<Vec<SongUrl>> {
+    NCMApi::new(app).player_url(&ids, &level).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_lyric(app: tauri::AppHandle, id: u64) -> NCMResult<Lyric> {
+    NCMApi::new(app).lyric(id).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_playlist_detail(app: tauri::AppHandle, id: u64) -> NCMResult<PlaylistDetail> {
+    NCMApi::new(app).playlist_detail(id).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_playlist_tracks(
+    app: tauri::AppHandle,
+    id: u64,
+    offset: usize,
+    limit: usize,
+    request_id: Option<String>,
+) -> NCMResult<Vec<SongDetail>> {
+    run(app, request_id, |api| async move {
+        api.playlist_tracks(id, offset, limit).await
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn ncm_get_user_playlists(
+    app: tauri::AppHandle,
+    uid: u64,
+    offset: usize,
+    limit: usize,
+) -> NCMResult<UserPlaylists> {
+    NCMApi::new(app).user_playlists(uid, offset, limit).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_liked_songs(app: tauri::AppHandle, uid: u64) -> NCMResult<Vec<u64>> {
+    NCMApi::new(app).liked_songs(uid).await
+}
+
+#[tauri::command]
+pub async fn ncm_like_song(app: tauri::AppHandle, id: u64, like: bool) -> NCMResult<()> {
+    NCMApi::new(app).like_song(id, like).await
+}
+
+/// 搜索框输入变化时可以通过 `request_id` 取消上一次的搜索
+#[tauri::command]
+pub async fn ncm_search(
+    app: tauri::AppHandle,
+    keywords: String,
+    search_type: SearchType,
+    offset: usize,
+    limit: usize,
+    request_id: Option<String>,
+) -> NCMResult<SearchResult> {
+    run(app, request_id, |api| async move {
+        api.search(&keywords, search_type, offset, limit).await
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn ncm_get_album(app: tauri::AppHandle, id: u64) -> NCMResult<Album> {
+    NCMApi::new(app).album(id).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_artist(app: tauri::AppHandle, id: u64) -> NCMResult<Artist> {
+    NCMApi::new(app).artist(id).await
+}
+
+#[tauri::command]
+pub async fn ncm_get_daily_songs(app: tauri::AppHandle) -> NCMResult<Vec<SongDetail>> {
+    NCMApi::new(app).daily_songs().await
+}
@@ -0,0 +1,234 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Account {
+    pub id: u64,
+    pub user_name: String,
+    pub vip_type: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserProfile {
+    pub user_id: u64,
+    pub nickname: String,
+    pub avatar_url: String,
+    pub signature: Option<String>,
+    pub vip_type: i32,
+}
+
+/// `/api/nuser/account/get` 的响应，未登录时两个字段都为空
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccountInfo {
+    pub account: Option<Account>,
+    pub profile: Option<UserProfile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArtistBrief {
+    pub id: u64,
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlbumBrief {
+    pub id: u64,
+    pub name: Option<String>,
+    pub pic_url: Option<String>,
+    pub tns: Vec<String>,
+}
+
+/// 歌曲信息，`ar` 为歌手，`al` 为专辑，`dt` 为时长（毫秒）
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SongDetail {
+    pub id: u64,
+    pub name: String,
+    pub ar: Vec<ArtistBrief>,
+    pub al: AlbumBrief,
+    pub dt: u64,
+    pub alia: Vec<String>,
+    pub tns: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct SongDetailResponse {
+    pub songs: Vec<SongDetail>,
+}
+
+/// 歌曲的播放链接，没有版权或需要付费时 `url` 为空
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SongUrl {
+    pub id: u64,
+    pub url: Option<String>,
+    pub br: usize,
+    pub size: usize,
+    pub md5: Option<String>,
+    #[serde(rename = "type")]
+    pub audio_type: Option<String>,
+    pub encode_type: Option<String>,
+    pub time: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LyricContent {
+    pub version: u64,
+    pub lyric: String,
+}
+
+/// 歌词，依次为原文、翻译、音译和逐字歌词，不存在时为空
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Lyric {
+    pub lrc: Option<LyricContent>,
+    pub tlyric: Option<LyricContent>,
+    pub romalrc: Option<LyricContent>,
+    pub yrc: Option<LyricContent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrackId {
+    pub id: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PlaylistBrief {
+    pub id: u64,
+    pub name: String,
+    pub cover_img_url: String,
+    pub track_count: u64,
+    pub play_count: u64,
+    pub subscribed: bool,
+    pub creator: Option<UserProfile>,
+}
+
+/// 歌单详情，`tracks` 只包含前面一部分歌曲，完整的歌曲列表需要根据 `track_ids` 获取
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PlaylistDetail {
+    pub id: u64,
+    pub name: String,
+    pub cover_img_url: String,
+    pub description: Option<String>,
+    pub track_count: u64,
+    pub play_count: u64,
+    pub subscribed: bool,
+    pub creator: Option<UserProfile>,
+    pub track_ids: Vec<TrackId>,
+    pub tracks: Vec<SongDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct PlaylistDetailResponse {
+    pub playlist: PlaylistDetail,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserPlaylists {
+    pub playlist: Vec<PlaylistBrief>,
+    pub more: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct LikedSongsResponse {
+    pub ids: Vec<u64>,
+}
+
+/// 搜索类型，对应接口的 `type` 参数
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SearchType {
+    #[default]
+    Song,
+    Album,
+    Artist,
+    Playlist,
+}
+
+impl SearchType {
+    pub fn code(self) -> u32 {
+        match self {
+            SearchType::Song => 1,
+            SearchType::Album => 10,
+            SearchType::Artist => 100,
+            SearchType::Playlist => 1000,
+        }
+    }
+}
+
+/// 搜索结果，只有与搜索类型对应的字段有内容
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SearchResult {
+    pub songs: Vec<SongDetail>,
+    pub song_count: u64,
+    pub albums: Vec<AlbumDetail>,
+    pub album_count: u64,
+    pub artists: Vec<ArtistDetail>,
+    pub artist_count: u64,
+    pub playlists: Vec<PlaylistBrief>,
+    pub playlist_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct SearchResponse {
+    pub result: SearchResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlbumDetail {
+    pub id: u64,
+    pub name: String,
+    pub pic_url: Option<String>,
+    pub artists: Vec<ArtistBrief>,
+    pub publish_time: u64,
+    pub size: u64,
+    pub description: Option<String>,
+}
+
+/// `/api/v1/album/{id}` 的响应
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Album {
+    pub album: AlbumDetail,
+    pub songs: Vec<SongDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArtistDetail {
+    pub id: u64,
+    pub name: String,
+    pub pic_url: Option<String>,
+    pub brief_desc: Option<String>,
+    pub music_size: u64,
+    pub album_size: u64,
+}
+
+/// `/api/v1/artist/{id}` 的响应
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Artist {
+    pub artist: ArtistDetail,
+    pub hot_songs: Vec<SongDetail>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct DailySongsResponse {
+    pub daily_songs: Vec<SongDetail>,
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    Client, Proxy,
+};
+use tauri::State;
+
+use crate::AppState;
+
+/// 网络设置的保存文件名，位于应用配置文件夹中
+pub const NETWORK_SETTINGS_FILE: &str = "network-settings.json";
+/// 设置中没有指定代理时使用的环境变量
+const PROXY_ENV: &str = "MRBNCM_PROXY";
+/// 设置中没有指定 `X-Real-IP` 时使用的环境变量
+const REAL_IP_ENV: &str = "MRBNCM_REAL_IP";
+/// 建立连接的超时时间，整个请求的超时时间由各个请求自己设置
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// 空闲连接在连接池中保留的时间
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// 网络设置，接口请求和音频下载都会使用
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkSettings {
+    /// 代理地址，例如 `http://127.0.0.1:7890` 或 `socks5h://127.0.0.1:1080`
+    ///
+    /// 为空时依次使用环境变量 `MRBNCM_PROXY` 和系统的 `HTTP_PROXY`、`HTTPS_PROXY`。
+    pub proxy: String,
+    /// 为真时不使用任何代理，包括环境变量中的代理
+    pub direct: bool,
+    /// 附加到接口请求中的 `X-Real-IP`，用于访问有地区限制的接口，为空时使用环境变量 `MRBNCM_REAL_IP`
+    pub real_ip: String,
+    /// 域名解析覆盖，键为域名，值为 IP 地址
+    ///
+    /// 使用代理时由代理服务器解析域名，覆盖不会生效，所以不能和 `proxy` 或 `MRBNCM_PROXY` 同时设置。
+    /// 系统的 `HTTP_PROXY`、`HTTPS_PROXY` 同样会让覆盖失效，需要时可以开启 `direct`。
+    pub dns_overrides: BTreeMap<String, String>,
+}
+
+/// 根据网络设置创建的客户端
+#[derive(Debug, Clone)]
+pub struct NetworkClient {
+    pub client: Client,
+    pub real_ip: Option<IpAddr>,
+}
+
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+}
+
+impl NetworkSettings {
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|x| serde_json::from_slice(&x).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context("无法创建配置文件夹")?;
+        }
+        let data = serde_json::to_vec_pretty(self).context("无法序列化网络设置")?;
+        std::fs::write(path, data).context("无法写入网络设置")
+    }
+
+    /// 实际使用的代理地址，设置为空时使用环境变量
+    fn proxy_url(&self) -> Option<String> {
+        if self.direct {
+            return None;
+        }
+        Some(self.proxy.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .or_else(|| env_value(PROXY_ENV))
+    }
+
+    /// 实际使用的 `X-Real-IP`，设置为空时使用环境变量
+    fn real_ip(&self) -> Option<String> {
+        Some(self.real_ip.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .or_else(|| env_value(REAL_IP_ENV))
+    }
+
+    /// 按照设置创建客户端，代理地址或 IP 地址不合法时返回错误
+    pub fn build(&self) -> anyhow::Result<NetworkClient> {
+        let mut headers = HeaderMap::new();
+        headers.insert("origin", HeaderValue::from_static("orpheus://orpheus"));
+        let mut builder = Client::builder()
+            .default_headers(headers)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT);
+        if self.direct {
+            builder = builder.no_proxy();
+        } else if let Some(proxy) = self.proxy_url() {
+            anyhow::ensure!(
+                self.dns_overrides.is_empty(),
+                "使用代理 {proxy} 时域名解析覆盖不会生效，请清空域名解析覆盖或者不使用代理"
+            );
+            builder = builder
+                .proxy(Proxy::all(&proxy).with_context(|| format!("代理地址不合法 {proxy}"))?);
+        }
+        for (domain, ip) in &self.dns_overrides {
+            let ip = ip
+                .trim()
+                .parse::<IpAddr>()
+                .with_context(|| format!("{domain} 的 IP 地址不合法 {ip}"))?;
+            // 端口会被忽略，使用链接中的端口
+            builder = builder.resolve(domain.trim(), SocketAddr::new(ip, 0));
+        }
+        let real_ip = self
+            .real_ip()
+            .map(|x| {
+                x.parse::<IpAddr>()
+                    .with_context(|| format!("X-Real-IP 不是合法的 IP 地址 {x}"))
+            })
+            .transpose()?;
+        Ok(NetworkClient {
+            client: builder.build().context("无法创建 HTTP 客户端")?,
+            real_ip,
+        })
+    }
+}
+
+#[tauri::command]
+pub fn get_network_settings(app_state: State<'_, AppState>) -> NetworkSettings {
+    app_state.http.network()
+}
+
+/// 修改网络设置，设置不合法时不会保存，原来的设置继续生效
+#[tauri::command]
+pub fn set_network_settings(
+    app: tauri::AppHandle,
+    app_state: State<'_, AppState>,
+    settings: NetworkSettings,
+) -> Result<(), String> {
+    app_state
+        .http
+        .set_network(settings.to_owned())
+        .map_err(|x| format!("{x:#}"))?;
+    let path = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or("无法获取配置文件夹")?
+        .join(NETWORK_SETTINGS_FILE);
+    settings.save(&path).map_err(|x| format!("{x:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::eapi::prepare_request;
+    use crate::mock_server::{MockProxy, MockServer};
+    use crate::session::is_api_url;
+
+    const REAL_IP: &str = "211.161.244.70";
+
+    #[tokio::test]
+    async fn requests_use_proxy_and_real_ip() {
+        let server = MockServer::start().await;
+        let proxy = MockProxy::start(&server.base_url).await;
+        let state = AppState::default();
+        state
+            .http
+            .set_network(NetworkSettings {
+                proxy: proxy.url.to_owned(),
+                real_ip: REAL_IP.into(),
+                ..Default::default()
+            })
+            .unwrap();
+        // 域名不会在本地解析，而是交给代理
+        let url = "http://music.163.com/eapi/v3/song/detail";
+        let res = prepare_request(&state, url, json!({ "c": "[]", "e_r": true }))
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res, server.fixture("/api/v3/song/detail"));
+
+        let reqs = proxy.requests();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].path, url);
+        assert_eq!(reqs[0].header("host"), "music.163.com");
+        assert_eq!(reqs[0].header("x-real-ip"), REAL_IP);
+        assert_eq!(reqs[0].header("x-forwarded-for"), REAL_IP);
+        assert_eq!(server.requests()[0].path, "/api/v3/song/detail");
+    }
+
+    #[tokio::test]
+    async fn real_ip_and_identity_are_only_sent_to_api_hosts() {
+        assert!(is_api_url("https://music.163.com/eapi/v3/song/detail"));
+        assert!(is_api_url(
+            "https://interface.music.163.com/eapi/cloudsearch/pc"
+        ));
+        assert!(!is_api_url("http://m701.music.126.net/20240101/song.flac"));
+        assert!(!is_api_url("https://notmusic.163.com/"));
+
+        let server = MockServer::start().await;
+        let state = server.app_state();
+        state
+            .http
+            .set_network(NetworkSettings {
+                real_ip: REAL_IP.into(),
+                ..state.http.network()
+            })
+            .unwrap();
+        state.http.cookie_jar().import("MUSIC_U=secret");
+
+        state
+            .http
+            .get(&server.api_url("/audio/song.flac"))
+            .send()
+            .await
+            .unwrap();
+        let req = server.http_requests().pop().unwrap();
+        assert_eq!(req.header("x-real-ip"), REAL_IP);
+        assert_eq!(req.header("x-forwarded-for"), REAL_IP);
+        assert!(req.header("cookie").contains("MUSIC_U=secret"));
+        assert!(req.header("cookie").contains("deviceId="));
+
+        // 歌曲文件所在的 CDN 不是接口服务器
+        state
+            .http
+            .get(&server.url("/audio/song.flac"))
+            .send()
+            .await
+            .unwrap();
+        let req = server.http_requests().pop().unwrap();
+        assert_eq!(req.header("x-real-ip"), "");
+        assert_eq!(req.header("x-forwarded-for"), "");
+        assert_eq!(req.header("cookie"), "");
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let settings = |proxy: &str, real_ip: &str, dns: &[(&str, &str)]| NetworkSettings {
+            proxy: proxy.into(),
+            real_ip: real_ip.into(),
+            dns_overrides: dns
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let dns = [("music.163.com", "127.0.0.1")];
+        assert!(settings("http://127.0.0.1:7890", REAL_IP, &[])
+            .build()
+            .is_ok());
+        assert!(settings("", REAL_IP, &dns).build().is_ok());
+        assert!(settings("http://127.0.0.1:7890", "", &dns).build().is_err());
+        assert!(settings("not a proxy", "", &[]).build().is_err());
+        assert!(settings("", "not an ip", &[]).build().is_err());
+        assert!(settings("", "", &[("music.163.com", "not an ip")])
+            .build()
+            .is_err());
+        let direct = NetworkSettings {
+            direct: true,
+            ..settings("http://127.0.0.1:7890", "", &dns)
+        };
+        assert!(direct.build().is_ok());
+    }
+}
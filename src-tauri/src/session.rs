@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use reqwest::{Method, RequestBuilder, Response};
+use tauri::State;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::{ClientProfile, CLIENT_PROFILE_FILE};
+use crate::cookie_jar::CookieJar;
+use crate::network::{NetworkClient, NetworkSettings, NETWORK_SETTINGS_FILE};
+use crate::throttle::Throttle;
+use crate::AppState;
+
+/// Cookie 的保存文件名，位于应用数据文件夹中
+pub const COOKIE_JAR_FILE: &str = "cookie-jar.bin";
+/// Cookie 文件的密钥，位于配置文件夹中，与 Cookie 文件分开保存
+pub const COOKIE_KEY_FILE: &str = "cookie-jar.key";
+/// 接口服务器的域名，只有发往它和它的子域名的请求才会带上客户端身份和 `X-Real-IP`
+pub const API_DOMAIN: &str = "music.163.com";
+
+/// 链接是否指向接口服务器，歌曲文件所在的 CDN 等其它主机不需要客户端身份
+pub fn is_api_url(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.host_str()
+                .and_then(|host| host.strip_suffix(API_DOMAIN))
+                .map(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+        .unwrap_or(false)
+}
+
+/// 前端命令和音频线程共用的 HTTP 会话
+///
+/// 内部的 `reqwest` 客户端自带连接池，并且会在服务器支持时使用 HTTP/2。
+/// 每个请求都会带上客户端身份对应的 User-Agent，以及 Cookie 罐中与链接匹配的 Cookie，
+/// 响应中的 `Set-Cookie` 需要通过 [`CookieStore::store_cookies`] 保存。
+/// 代理、`X-Real-IP` 和域名解析由 [`NetworkSettings`] 决定，修改后会重新创建客户端。
+/// Cookie 中的客户端身份字段和 `X-Real-IP` 只会发给接口服务器，见 [`is_api_url`]。
+#[derive(Debug)]
+pub struct HttpSession {
+    network: Mutex<NetworkSettings>,
+    network_client: Mutex<NetworkClient>,
+    profile: Mutex<ClientProfile>,
+    cookies: Arc<Mutex<CookieJar>>,
+    throttle: Arc<Throttle>,
+    /// 前端发起的可以取消的请求，值中的数字用于区分相同 ID 的不同请求
+    pending: Mutex<HashMap<String, (u64, CancellationToken)>>,
+    next_request_seq: AtomicU64,
+}
+
+impl Default for HttpSession {
+    fn default() -> Self {
+        // 环境变量中的代理或 IP 地址不合法时不使用代理
+        let client = NetworkSettings::default().build().unwrap_or_else(|err| {
+            println!("[WARN] 无法按照环境变量设置网络 {err:?}");
+            NetworkSettings {
+                direct: true,
+                ..Default::default()
+            }
+            .build()
+            .expect("无法创建 HTTP 客户端")
+        });
+        Self {
+            network: Default::default(),
+            network_client: Mutex::new(client),
+            profile: Mutex::new(ClientProfile::default()),
+            cookies: Default::default(),
+            throttle: Default::default(),
+            pending: Default::default(),
+            next_request_seq: AtomicU64::new(0),
+        }
+    }
+}
+
+impl HttpSession {
+    /// 读取保存的网络设置、客户端身份和 Cookie，在应用启动时调用
+    pub fn load(&self, config_dir: &Path, data_dir: &Path) {
+        let network = NetworkSettings::load(&config_dir.join(NETWORK_SETTINGS_FILE));
+        if let Err(err) = self.set_network(network) {
+            println!("[WARN] 保存的网络设置无效 {err:?}");
+        }
+        let client = ClientProfile::load(&config_dir.join(CLIENT_PROFILE_FILE));
+        *self.cookie_jar() = CookieJar::load(
+            &data_dir.join(COOKIE_JAR_FILE),
+            &config_dir.join(COOKIE_KEY_FILE),
+        );
+        *self.profile.lock().unwrap() = client;
+    }
+
+    pub fn client(&self) -> ClientProfile {
+        self.profile.lock().unwrap().to_owned()
+    }
+
+    pub fn set_client(&self, client: ClientProfile) {
+        *self.profile.lock().unwrap() = client;
+    }
+
+    pub fn network(&self) -> NetworkSettings {
+        self.network.lock().unwrap().to_owned()
+    }
+
+    /// 按照新的网络设置重新创建客户端，设置不合法时保留原来的客户端
+    pub fn set_network(&self, settings: NetworkSettings) -> anyhow::Result<()> {
+        let client = settings.build()?;
+        *self.network_client.lock().unwrap() = client;
+        *self.network.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    pub fn cookie_jar(&self) -> MutexGuard<'_, CookieJar> {
+        self.cookies.lock().unwrap()
+    }
+
+    /// 用于在其它任务中保存响应 Cookie 的句柄
+    pub fn cookie_store(&self) -> CookieStore {
+        CookieStore(self.cookies.clone())
+    }
+
+    /// 所有接口请求共用的限流器和熔断器
+    pub fn throttle(&self) -> Arc<Throttle> {
+        self.throttle.clone()
+    }
+
+    /// `user_agent` 为空时使用客户端身份对应的 User-Agent
+    fn request(&self, method: Method, url: &str, user_agent: Option<&str>) -> RequestBuilder {
+        let network = self.network_client.lock().unwrap().to_owned();
+        let client = self.client();
+        let is_api = is_api_url(url);
+        let mut cookie = self.cookie_jar().cookie_header(url);
+        if is_api {
+            cookie = client.cookie_header(&cookie);
+        }
+        let user_agent = user_agent
+            .map(String::from)
+            .unwrap_or_else(|| client.user_agent());
+        let mut req = network
+            .client
+            .request(method, url)
+            .header("user-agent", user_agent);
+        if !cookie.is_empty() {
+            req = req.header("cookie", cookie);
+        }
+        if let Some(ip) = network.real_ip.filter(|_| is_api) {
+            let ip = ip.to_string();
+            req = req
+                .header("x-real-ip", ip.as_str())
+                .header("x-forwarded-for", ip);
+        }
+        req
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.request(Method::GET, url, None)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.request(Method::POST, url, None)
+    }
+
+    /// 替换 User-Agent 的 POST 请求，用于只接受特定客户端的接口
+    pub fn post_with_user_agent(&self, url: &str, user_agent: &str) -> RequestBuilder {
+        self.request(Method::POST, url, Some(user_agent))
+    }
+
+    /// 登记一个可以被前端取消的请求，相同 ID 的旧请求会被取消
+    ///
+    /// 返回的序号需要在请求完成后传给 [`HttpSession::finish_request`]。
+    pub fn register_request(&self, request_id: &str) -> (u64, CancellationToken) {
+        let seq = self.next_request_seq.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        if let Some((_, old)) = self
+            .pending
+            .lock()
+            .unwrap()
+            .insert(request_id.to_owned(), (seq, token.clone()))
+        {
+            old.cancel();
+        }
+        (seq, token)
+    }
+
+    /// 请求完成后移除登记，已经被相同 ID 的新请求替换时不做任何事
+    pub fn finish_request(&self, request_id: &str, seq: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.get(request_id).is_some_and(|x| x.0 == seq) {
+            pending.remove(request_id);
+        }
+    }
+
+    /// 取消一个请求，请求不存在或已经完成时返回假
+    pub fn cancel_request(&self, request_id: &str) -> bool {
+        match self.pending.lock().unwrap().remove(request_id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 可以移动到其它任务的 Cookie 罐句柄
+#[derive(Clone, Debug)]
+pub struct CookieStore(Arc<Mutex<CookieJar>>);
+
+impl CookieStore {
+    /// 保存响应中的 `Set-Cookie` 头，`url` 是实际发出请求的链接
+    pub fn store_cookies(&self, url: &str, res: &Response) {
+        let set_cookies = res
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .filter_map(|x| x.to_str().ok())
+            .collect::<Vec<_>>();
+        if !set_cookies.is_empty() {
+            self.0
+                .lock()
+                .unwrap()
+                .store_response_cookies(url, set_cookies);
+        }
+    }
+}
+
+/// 导入前端保存的 Cookie，格式为 `a=1; b=2`，会替换同名的 Cookie
+#[tauri::command]
+pub fn set_cookie(app_state: State<'_, AppState>, cookie: String) {
+    app_state.http.cookie_jar().import(&cookie);
+    println!("已导入 Cookie");
+}
+
+/// 清空所有 Cookie，用于退出登录
+#[tauri::command]
+pub fn clear_cookies(app_state: State<'_, AppState>) {
+    app_state.http.cookie_jar().clear();
+    println!("已清空 Cookie");
+}
+
+/// 取消通过 `tauri_eapi_request` 发起并指定了 `requestId` 的请求
+#[tauri::command]
+pub fn cancel_request(app_state: State<'_, AppState>, request_id: String) -> bool {
+    app_state.http.cancel_request(&request_id)
+}
@@ -0,0 +1,301 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+/// 令牌桶的容量，即允许的突发请求数
+const BUCKET_CAPACITY: f64 = 8.;
+/// 每秒补充的令牌数，即长期的平均请求频率
+const BUCKET_REFILL_PER_SEC: f64 = 4.;
+/// 触发网易云频率限制后暂停发送请求的时间
+const THROTTLE_COOLDOWN: Duration = Duration::from_secs(3);
+/// 连续失败多少次后断开
+const BREAKER_THRESHOLD: u32 = 5;
+/// 断开后多久允许发送一个试探请求
+const BREAKER_OPEN_DURATION: Duration = Duration::from_secs(30);
+/// 重试等待时间的基数和上限
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(8);
+
+/// 令牌桶限流器，令牌不足时预约之后的令牌并等待
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// 可能为负数，表示已经被预约的令牌
+    tokens: f64,
+    /// 上次补充令牌的时间，暂停时会被设置为暂停结束的时间
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// 取出一个令牌，返回拿到令牌前需要等待的时间
+    fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        if !elapsed.is_zero() {
+            state.tokens =
+                (state.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+            state.last_refill = now;
+        }
+        state.tokens -= 1.;
+        let paused = state.last_refill.saturating_duration_since(now);
+        if state.tokens >= 0. {
+            paused
+        } else {
+            paused + Duration::from_secs_f64(-state.tokens / self.refill_per_sec)
+        }
+    }
+
+    fn pause_remaining(&self) -> Duration {
+        self.state
+            .lock()
+            .unwrap()
+            .last_refill
+            .saturating_duration_since(Instant::now())
+    }
+
+    /// 等待直到拿到一个令牌，等待期间被暂停时会继续等到暂停结束
+    pub async fn acquire(&self) {
+        let mut wait = self.reserve();
+        while !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+            wait = self.pause_remaining();
+        }
+    }
+
+    /// 在 `duration` 内不再发放令牌，暂停结束后从空桶开始补充
+    pub fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+        if until > state.last_refill {
+            state.last_refill = until;
+        }
+        state.tokens = state.tokens.min(0.);
+    }
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// 已经放行了一个试探请求，等待它的结果
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// 熔断器，服务不可用时直接返回错误，不再发送请求
+///
+/// 连续失败达到阈值后断开，断开一段时间后放行一个试探请求，成功则恢复，失败则继续断开。
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            threshold,
+            open_duration,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// 检查是否可以发送请求，断开时返回大约还需要等待的时间
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now < until => Err(until - now),
+            // 试探请求被取消时不会有结果，超时后允许再试探一次
+            BreakerState::HalfOpen { since } if now < since + self.open_duration => {
+                Err(since + self.open_duration - now)
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen { since: now };
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            _ => self.threshold,
+        };
+        *state = if failures >= self.threshold {
+            println!(
+                "[WARN] 网络请求连续失败，{} 秒内不再发送请求",
+                self.open_duration.as_secs()
+            );
+            BreakerState::Open {
+                until: Instant::now() + self.open_duration,
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+}
+
+/// 所有接口请求共用的限流器和熔断器
+#[derive(Debug)]
+pub struct Throttle {
+    pub bucket: TokenBucket,
+    pub breaker: CircuitBreaker,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            bucket: TokenBucket::new(BUCKET_CAPACITY, BUCKET_REFILL_PER_SEC),
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD, BREAKER_OPEN_DURATION),
+        }
+    }
+}
+
+impl Throttle {
+    /// 触发了网易云的频率限制，暂停发送请求
+    pub fn throttled(&self) {
+        self.bucket.pause(THROTTLE_COOLDOWN);
+    }
+}
+
+/// 第 `attempt` 次重试前的等待时间，从 0 开始，指数增长并带有随机抖动
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let max = BACKOFF_BASE
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX);
+    // 在上限的一半到上限之间随机，避免同时失败的请求同时重试
+    max.mul_f64(rand::thread_rng().gen_range(0.5..=1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const SHORT: Duration = Duration::from_millis(60);
+
+    #[test]
+    fn bucket_reserves_and_refills() {
+        let bucket = TokenBucket::new(2., 10.);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        // 令牌用完后需要等待补充一个令牌的时间，即 100ms
+        let wait = bucket.reserve();
+        assert!(wait > Duration::from_millis(80), "{wait:?}");
+        assert!(wait <= Duration::from_millis(100), "{wait:?}");
+        // 已经预约的令牌会让之后的请求等待更久
+        let wait = bucket.reserve();
+        assert!(wait > Duration::from_millis(180), "{wait:?}");
+
+        // 补充的令牌不会超过容量
+        sleep(Duration::from_millis(600));
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert!(bucket.reserve() > Duration::ZERO);
+    }
+
+    #[test]
+    fn bucket_pause_delays_tokens() {
+        let bucket = TokenBucket::new(8., 10.);
+        bucket.pause(Duration::from_millis(200));
+        // 暂停结束后从空桶开始补充，第一个令牌还要再等 100ms
+        let wait = bucket.reserve();
+        assert!(wait > Duration::from_millis(250), "{wait:?}");
+        assert!(wait <= Duration::from_millis(300), "{wait:?}");
+        assert!(bucket.pause_remaining() > Duration::from_millis(150));
+        // 更短的暂停不会提前结束已有的暂停
+        bucket.pause(Duration::from_millis(10));
+        assert!(bucket.pause_remaining() > Duration::from_millis(150));
+    }
+
+    #[test]
+    fn breaker_opens_half_opens_and_closes() {
+        let breaker = CircuitBreaker::new(2, SHORT);
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        // 成功会清零失败次数
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        let wait = breaker.check().unwrap_err();
+        assert!(wait <= SHORT, "{wait:?}");
+
+        // 断开一段时间后只放行一个试探请求
+        sleep(SHORT);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        // 试探失败后重新断开
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+
+        sleep(SHORT);
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn breaker_allows_new_probe_after_lost_result() {
+        let breaker = CircuitBreaker::new(1, SHORT);
+        breaker.record_failure();
+        sleep(SHORT);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+        // 试探请求被取消，没有结果，超时后再放行一次
+        sleep(SHORT);
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn backoff_delay_stays_within_jitter_bounds() {
+        for attempt in 0..40 {
+            let max = (BACKOFF_BASE * 2u32.pow(attempt.min(16))).min(BACKOFF_MAX);
+            for _ in 0..200 {
+                let delay = backoff_delay(attempt);
+                assert!(delay >= max / 2, "{attempt} {delay:?}");
+                assert!(delay <= max, "{attempt} {delay:?}");
+            }
+        }
+        // 带有抖动，不会每次都相同
+        let first = backoff_delay(3);
+        assert!((0..200).any(|_| backoff_delay(3) != first));
+    }
+}
@@ -0,0 +1,86 @@
+use base64::prelude::*;
+use libaes::Cipher;
+use num_bigint::BigUint;
+use once_cell::unsync::Lazy;
+use rand::Rng;
+
+const WEAPI_PRESET_KEY: &[u8; 16] = b"0CoJUm6Qyw8W8jud";
+const WEAPI_IV: &[u8; 16] = b"0102030405060708";
+const WEAPI_PUBLIC_EXPONENT: u32 = 0x10001;
+const WEAPI_MODULUS: &[u8] = b"00e0b509f6259df8642dbc35662901477df22677ec152b5ff68ace615bb7b725152b3ab17a876aea8a5aa76d2e417629ec4ee341f56135fccf695280104e0312ecbda92557c93870114af6c9d05c4f7f0c3685b7a46bee255932575cce10b424d813cfe4875d3e82047b97ddef52741d546b8e289dc6935b3ece0462db0a22b8e7";
+const SECRET_KEY_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+std::thread_local! {
+    static WEAPI_CIPHER: Lazy<Cipher> = Lazy::new(|| Cipher::new_128(WEAPI_PRESET_KEY));
+}
+
+/// weapi 请求需要提交的表单字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeapiForm {
+    pub params: String,
+    pub enc_sec_key: String,
+}
+
+/// 以随机生成的密钥加密请求数据
+pub fn weapi_encrypt(data: &str) -> WeapiForm {
+    let mut rng = rand::thread_rng();
+    let secret_key: [u8; 16] =
+        std::array::from_fn(|_| SECRET_KEY_CHARS[rng.gen_range(0..SECRET_KEY_CHARS.len())]);
+    weapi_encrypt_with_key(data, &secret_key)
+}
+
+/// 先用固定密钥、再用随机密钥进行两次 AES-128-CBC 加密，随机密钥经 RSA 加密后一并提交
+pub fn weapi_encrypt_with_key(data: &str, secret_key: &[u8; 16]) -> WeapiForm {
+    let first =
+        WEAPI_CIPHER.with(|c| BASE64_STANDARD.encode(c.cbc_encrypt(WEAPI_IV, data.as_bytes())));
+    let second = Cipher::new_128(secret_key).cbc_encrypt(WEAPI_IV, first.as_bytes());
+    WeapiForm {
+        params: BASE64_STANDARD.encode(second),
+        enc_sec_key: rsa_encrypt_secret_key(secret_key),
+    }
+}
+
+/// 以无填充的 RSA 加密倒序后的随机密钥，结果为 256 位十六进制字符串
+fn rsa_encrypt_secret_key(secret_key: &[u8]) -> String {
+    let reversed = secret_key.iter().rev().copied().collect::<Vec<_>>();
+    let modulus = BigUint::parse_bytes(WEAPI_MODULUS, 16).unwrap();
+    let encrypted =
+        BigUint::from_bytes_be(&reversed).modpow(&BigUint::from(WEAPI_PUBLIC_EXPONENT), &modulus);
+    format!("{encrypted:0256x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 以下结果由 OpenSSL 的 AES-128-CBC 和 Python 的大数运算独立计算得到
+    const DATA: &str = r#"{"csrf_token":"","ids":"[1]"}"#;
+    const SECRET_KEY: &[u8; 16] = b"abcdefghijklmnop";
+    const PARAMS: &str = "Xq7VamFhKH1OmZhJfUWqviIa7v8O28ot5d+fmlApIQYcTbZ4mal0Vu3B+752z/We";
+    const ENC_SEC_KEY: &str = concat!(
+        "d15a1683c992095d0c234c19966605c5c5964911268bbeda8cb8d08d834913e5",
+        "9d53b32358903a121b5fca784c1f5ae44951fd02524df58ecc98e52cc7cf8689",
+        "b42c2e93ddf05b0592512d87f5960467e2f086c018849d76014d323500e30f13",
+        "ef4cafbb0cf5a66731a3f1776c75ca35d0062dac70a3e33245afabcf47938487",
+    );
+
+    #[test]
+    fn weapi_encrypt_with_key_known_answer() {
+        assert_eq!(
+            weapi_encrypt_with_key(DATA, SECRET_KEY),
+            WeapiForm {
+                params: PARAMS.into(),
+                enc_sec_key: ENC_SEC_KEY.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn weapi_encrypt_uses_random_printable_key() {
+        let a = weapi_encrypt(DATA);
+        let b = weapi_encrypt(DATA);
+        assert_ne!(a.enc_sec_key, b.enc_sec_key);
+        assert_eq!(a.enc_sec_key.len(), 256);
+        assert!(BASE64_STANDARD.decode(&a.params).is_ok());
+    }
+}
@@ -44,6 +44,8 @@ export const BottomPlayControls: React.FC = () => {
 			} else if (evt.payload.type === "playStatus") {
 				console.log(evt);
 				setPlaying(evt.payload.data.isPlaying);
+			} else if (evt.payload.type === "outputError") {
+				console.warn("输出设备出错，播放已暂停", evt.payload.data.error);
 			}
 		}).then((v) => {
 			invokeSyncStatus();
@@ -96,7 +98,9 @@ export const BottomPlayControls: React.FC = () => {
 				<div className="play-controls-buttons">
 					<button
 						onClick={() => {
-							sendMsgToAudioThread("prevSong");
+							sendMsgToAudioThread("prevSong").catch((err) => {
+								console.warn("切换到上一首歌曲出错", err);
+							});
 						}}
 					>
 						<img alt="上一首歌曲" src={IconRewind} />
@@ -104,7 +108,9 @@ export const BottomPlayControls: React.FC = () => {
 					{isPlaying ? (
 						<button
 							onClick={() => {
-								sendMsgToAudioThread("pauseAudio");
+								sendMsgToAudioThread("pauseAudio").catch((err) => {
+									console.warn("暂停播放出错", err);
+								});
 							}}
 						>
 							<img alt="播放/暂停" src={IconPause} />
@@ -112,7 +118,9 @@ export const BottomPlayControls: React.FC = () => {
 					) : (
 						<button
 							onClick={() => {
-								sendMsgToAudioThread("resumeAudio");
+								sendMsgToAudioThread("resumeAudio").catch((err) => {
+									console.warn("继续播放出错", err);
+								});
 							}}
 						>
 							<img alt="播放/暂停" src={IconPlay} />
@@ -120,7 +128,9 @@ export const BottomPlayControls: React.FC = () => {
 					)}
 					<button
 						onClick={() => {
-							sendMsgToAudioThread("nextSong");
+							sendMsgToAudioThread("nextSong").catch((err) => {
+								console.warn("切换到下一首歌曲出错", err);
+							});
 						}}
 					>
 						<img alt="下一首歌曲" src={IconForward} />
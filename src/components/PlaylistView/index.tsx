@@ -48,17 +48,21 @@ export const PlaylistView: React.FC<{
 					style={style}
 					onDoubleClick={async () => {
 						if (props.songs) {
-							await sendMsgToAudioThread("setPlaylist", {
-								songs: props.songs.map((v, i) => ({
-									ncmId: String(v.id),
-									localFile: "",
-									duration: 0,
-									origOrder: i,
-								})),
-							});
-							await sendMsgToAudioThread("jumpToSong", {
-								songIndex: index,
-							});
+							try {
+								await sendMsgToAudioThread("setPlaylist", {
+									songs: props.songs.map((v, i) => ({
+										ncmId: String(v.id),
+										localFile: "",
+										duration: 0,
+										origOrder: i,
+									})),
+								});
+								await sendMsgToAudioThread("jumpToSong", {
+									songIndex: index,
+								});
+							} catch (err) {
+								console.warn("播放歌曲出错", err);
+							}
 						}
 					}}
 				>
@@ -3,7 +3,8 @@ import { Cookie, ncmCookieAtom } from "./ncm-cookie";
 import {
 	eapiDecrypt,
 	eapiEncryptForRequest,
-	sendMsgToAudioThread,
+	ncmGetSongDetail,
+	setCookie,
 } from "./tauri-api";
 import { atom } from "jotai";
 import { invoke } from "@tauri-apps/api/tauri";
@@ -56,9 +57,7 @@ export class NCMAPI {
 
 export const ncmAPIAtom = atom(async (get) => {
 	const cookies = get(ncmCookieAtom);
-	await sendMsgToAudioThread("setCookie", {
-		cookie: cookies.map((v) => `${v.Name}=${v.Value}`).join("; "),
-	});
+	await setCookie(cookies.map((v) => `${v.Name}=${v.Value}`).join("; "));
 	const api = new NCMAPI(cookies);
 	return api;
 });
@@ -166,7 +165,8 @@ async function searchForSongsCache(
 }
 
 export const getSongDetailAtom = atom(async (get) => {
-	const ncm = await get(ncmAPIAtom);
+	// 等待 Cookie 同步到后端后再请求
+	await get(ncmAPIAtom);
 	return async (ids: number[]) => {
 		const results = new Map<number, NCMSongDetail>(
 			(await searchForSongsCache(ids)).map((v) => [v.id, v]),
@@ -174,29 +174,11 @@ export const getSongDetailAtom = atom(async (get) => {
 		const uncachedIds = ids.filter((id) => {
 			return !results.has(id);
 		});
-		const songsThreads = [];
-		for (let i = 0; i < uncachedIds.length; i += 1000) {
-			const postData = [];
-			for (let j = 0; j < Math.min(1000, uncachedIds.length - i); j++) {
-				postData.push({
-					id: uncachedIds[i + j],
-					v: 0,
-				});
+		if (uncachedIds.length > 0) {
+			for (const song of await ncmGetSongDetail(uncachedIds)) {
+				results.set(song.id, song);
 			}
-			songsThreads.push(
-				ncm
-					.request("https://music.163.com/eapi/v3/song/detail", {
-						c: JSON.stringify(postData),
-						e_r: true,
-					})
-					.then((v) => {
-						for (const song of v.songs) {
-							results.set(song.id, song);
-						}
-					}),
-			);
 		}
-		await Promise.all(songsThreads);
 		saveSongsCache([...results.values()]).catch((err) => {
 			console.warn("缓存歌曲信息出错", err);
 		});
@@ -81,15 +81,19 @@ export const PlaylistPage: React.FC = () => {
 							type="button"
 							onClick={async () => {
 								if (playlistSongs) {
-									await sendMsgToAudioThread("setPlaylist", {
-										songs: playlistSongs.map((v, i) => ({
-											ncmId: String(v.id),
-											localFile: "",
-											duration: 0,
-											origOrder: i,
-										})),
-									});
-									await sendMsgToAudioThread("nextSong");
+									try {
+										await sendMsgToAudioThread("setPlaylist", {
+											songs: playlistSongs.map((v, i) => ({
+												ncmId: String(v.id),
+												localFile: "",
+												duration: 0,
+												origOrder: i,
+											})),
+										});
+										await sendMsgToAudioThread("nextSong");
+									} catch (err) {
+										console.warn("播放歌单出错", err);
+									}
 								}
 							}}
 						>
@@ -108,15 +112,19 @@ export const PlaylistPage: React.FC = () => {
 									return newArr;
 								}
 								if (playlistSongs) {
-									await sendMsgToAudioThread("setPlaylist", {
-										songs: getShuffledArr(playlistSongs).map((v, i) => ({
-											ncmId: String(v.id),
-											localFile: "",
-											duration: 0,
-											origOrder: i,
-										})),
-									});
-									await sendMsgToAudioThread("nextSong");
+									try {
+										await sendMsgToAudioThread("setPlaylist", {
+											songs: getShuffledArr(playlistSongs).map((v, i) => ({
+												ncmId: String(v.id),
+												localFile: "",
+												duration: 0,
+												origOrder: i,
+											})),
+										});
+										await sendMsgToAudioThread("nextSong");
+									} catch (err) {
+										console.warn("播放歌单出错", err);
+									}
 								}
 							}}
 						>
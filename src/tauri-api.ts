@@ -1,20 +1,8 @@
 import { invoke } from "@tauri-apps/api/tauri";
 import { listen, EventCallback } from "@tauri-apps/api/event";
-import { uid } from "uid";
-
-const msgTasks = new Map<string, (value: any) => void>();
+import type { NCMSongDetail } from "./ncm-api";
 
 invoke("init_audio_thread");
-listen<{
-	callbackId: string;
-	data: any;
-}>("on_audio_thread_message", (evt) => {
-	const resolve = msgTasks.get(evt.payload.callbackId);
-	if (resolve) {
-		msgTasks.delete(evt.payload.callbackId);
-		resolve(evt.payload.data);
-	}
-});
 
 export interface AudioThreadMessage {
 	type: string;
@@ -26,21 +14,27 @@ export const listenAudioThreadEvent = (
 	handler: EventCallback<AudioThreadMessage>,
 ) => listen("on-audio-thread-event", handler);
 
+/**
+ * 音频线程处理消息后返回的结果，出错时 Promise 会以 `AudioError` 拒绝
+ */
+export interface AudioResponse {
+	type: string;
+	data?: any;
+}
+
+export interface AudioError {
+	type: string;
+	data?: any;
+}
+
 export function sendMsgToAudioThread(
 	msgType: string,
 	data: any = {},
-): Promise<any> {
-	const id = uid(32) + Date.now();
-	return new Promise((resolve) => {
-		msgTasks.set(id, resolve);
-		invoke("send_msg_to_audio_thread", {
-			msg: {
-				[msgType]: {
-					callbackId: id,
-					...data,
-				},
-			},
-		});
+): Promise<AudioResponse> {
+	return invoke("send_msg_to_audio_thread", {
+		msg: {
+			[msgType]: data,
+		},
 	});
 }
 
@@ -65,3 +59,234 @@ export function eapiDecrypt(data: string): Promise<string> {
 		data,
 	});
 }
+
+/**
+ * 导入 `a=1; b=2` 格式的 Cookie，会替换后端 Cookie 罐中的同名 Cookie
+ */
+export function setCookie(cookie: string): Promise<void> {
+	return invoke("set_cookie", {
+		cookie,
+	});
+}
+
+export function clearCookies(): Promise<void> {
+	return invoke("clear_cookies");
+}
+
+/**
+ * 网络设置，接口请求和音频下载都会使用
+ *
+ * `proxy` 支持 `http://`、`https://`、`socks5://` 和 `socks5h://`，
+ * `proxy` 和 `realIp` 为空时会使用环境变量 `MRBNCM_PROXY` 和 `MRBNCM_REAL_IP`。
+ * 使用代理时由代理解析域名，`dnsOverrides` 不会生效，所以不能和代理同时设置。
+ */
+export interface NetworkSettings {
+	proxy: string;
+	direct: boolean;
+	realIp: string;
+	dnsOverrides: Record<string, string>;
+}
+
+export function getNetworkSettings(): Promise<NetworkSettings> {
+	return invoke("get_network_settings");
+}
+
+/**
+ * 修改网络设置，代理地址或 IP 地址不合法，或者同时设置了代理和域名解析覆盖时，
+ * 会拒绝并保留原来的设置
+ */
+export function setNetworkSettings(settings: NetworkSettings): Promise<void> {
+	return invoke("set_network_settings", {
+		settings,
+	});
+}
+
+/**
+ * 取消指定了 `requestId` 的请求，请求不存在或已经完成时返回 `false`
+ */
+export function cancelRequest(requestId: string): Promise<boolean> {
+	return invoke("cancel_request", {
+		requestId,
+	});
+}
+
+export interface AudioPeaks {
+	intervalMs: number;
+	duration: number;
+	min: number[];
+	max: number[];
+}
+
+export function getAudioPeaks(
+	ncmId: string,
+	localFile = "",
+	intervalMs?: number,
+): Promise<AudioPeaks | null> {
+	return invoke("get_audio_peaks", {
+		ncmId,
+		localFile,
+		intervalMs,
+	});
+}
+
+/**
+ * 请求网易云接口时模拟的客户端身份，`deviceId` 首次启动时生成
+ */
+export interface ClientProfile {
+	os: string;
+	appver: string;
+	osver: string;
+	buildver: string;
+	resolution: string;
+	deviceId: string;
+}
+
+export function getClientProfile(): Promise<ClientProfile> {
+	return invoke("get_client_profile");
+}
+
+export function setClientProfile(
+	profile: ClientProfile,
+): Promise<ClientProfile> {
+	return invoke("set_client_profile", {
+		profile,
+	});
+}
+
+/**
+ * 网易云接口调用出错时 Promise 会以 `NCMApiError` 拒绝
+ */
+export interface NCMApiError {
+	type:
+		| "request"
+		| "api"
+		| "needLogin"
+		| "decode"
+		| "emptyData"
+		| "cancelled"
+		| "throttled"
+		| "unavailable";
+	data?: any;
+}
+
+export interface NCMSongUrl {
+	id: number;
+	url?: string;
+	br: number;
+	size: number;
+	md5?: string;
+	type?: string;
+	encodeType?: string;
+	time: number;
+}
+
+export interface NCMLyricContent {
+	version: number;
+	lyric: string;
+}
+
+export interface NCMLyric {
+	lrc?: NCMLyricContent;
+	tlyric?: NCMLyricContent;
+	romalrc?: NCMLyricContent;
+	yrc?: NCMLyricContent;
+}
+
+export type NCMSearchType = "song" | "album" | "artist" | "playlist";
+
+export const ncmGetAccount = () => invoke<any>("ncm_get_account");
+export const ncmGetSongDetail = (ids: number[], requestId?: string) =>
+	invoke<NCMSongDetail[]>("ncm_get_song_detail", { ids, requestId });
+export const ncmGetPlayerUrl = (ids: number[], level = "hires") =>
+	invoke<NCMSongUrl[]>("ncm_get_player_url", { ids, level });
+export const ncmGetLyric = (id: number) =>
+	invoke<NCMLyric>("ncm_get_lyric", { id });
+export const ncmGetPlaylistDetail = (id: number) =>
+	invoke<any>("ncm_get_playlist_detail", { id });
+export const ncmGetPlaylistTracks = (
+	id: number,
+	offset = 0,
+	limit = 1000,
+	requestId?: string,
+) =>
+	invoke<NCMSongDetail[]>("ncm_get_playlist_tracks", {
+		id,
+		offset,
+		limit,
+		requestId,
+	});
+export const ncmGetUserPlaylists = (uid: number, offset = 0, limit = 30) =>
+	invoke<any>("ncm_get_user_playlists", { uid, offset, limit });
+export const ncmGetLikedSongs = (uid: number) =>
+	invoke<number[]>("ncm_get_liked_songs", { uid });
+export const ncmLikeSong = (id: number, like: boolean) =>
+	invoke<void>("ncm_like_song", { id, like });
+export const ncmSearch = (
+	keywords: string,
+	searchType: NCMSearchType = "song",
+	offset = 0,
+	limit = 30,
+	requestId?: string,
+) =>
+	invoke<any>("ncm_search", {
+		keywords,
+		searchType,
+		offset,
+		limit,
+		requestId,
+	});
+export const ncmGetAlbum = (id: number) => invoke<any>("ncm_get_album", { id });
+export const ncmGetArtist = (id: number) =>
+	invoke<any>("ncm_get_artist", { id });
+export const ncmGetDailySongs = () =>
+	invoke<NCMSongDetail[]>("ncm_get_daily_songs");
+
+export interface NCMQrLogin {
+	unikey: string;
+	url: string;
+}
+
+/**
+ * 二维码登录的状态，`ncmLoginQrWait` 等待期间状态变化时会通过 `on-qr-login-event` 事件发送
+ */
+export type NCMQrLoginStatus =
+	| { type: "waiting" }
+	| { type: "scanned"; data: { nickname?: string; avatarUrl?: string } }
+	| { type: "confirmed" }
+	| { type: "expired" };
+
+export interface NCMQrLoginEvent {
+	unikey: string;
+	status: NCMQrLoginStatus;
+}
+
+export const listenQrLoginEvent = (handler: EventCallback<NCMQrLoginEvent>) =>
+	listen("on-qr-login-event", handler);
+
+export const ncmLoginQrCreate = () => invoke<NCMQrLogin>("ncm_login_qr_create");
+/**
+ * 等待扫码登录直到确认登录，二维码过期时以 `code` 为 800 的 `api` 错误拒绝
+ */
+export const ncmLoginQrWait = (unikey: string, requestId?: string) =>
+	invoke<any>("ncm_login_qr_wait", { unikey, requestId });
+/**
+ * 手机号登录，提供 `captcha` 时使用短信验证码登录，否则使用密码登录
+ */
+export const ncmLoginCellphone = (
+	phone: string,
+	options: { password?: string; captcha?: string; countryCode?: string },
+) => invoke<any>("ncm_login_cellphone", { phone, ...options });
+export const ncmLoginEmail = (email: string, password: string) =>
+	invoke<any>("ncm_login_email", { email, password });
+export const ncmCaptchaSend = (phone: string, countryCode?: string) =>
+	invoke<void>("ncm_captcha_send", { phone, countryCode });
+export const ncmCaptchaVerify = (
+	phone: string,
+	captcha: string,
+	countryCode?: string,
+) => invoke<void>("ncm_captcha_verify", { phone, captcha, countryCode });
+export const ncmLoginRefresh = () => invoke<void>("ncm_login_refresh");
+/**
+ * 退出登录，无论请求是否成功都会清空 Cookie
+ */
+export const ncmLogout = () => invoke<void>("ncm_logout");